@@ -1,5 +1,247 @@
 #![allow(warnings)]
 
 use criterion::{criterion_group, criterion_main, Criterion};
+use evm::{
+    backend::{Apply, ApplyBackend, Backend, Basic},
+    executor::stack::{StackExecutor, StackSubstateMetadata},
+};
+use ovr::{
+    ethvm::{
+        impls::{cache::BackendCache, stack::OvrStackState},
+        precompile::OvrPrecompileSet,
+        State,
+    },
+    ledger::{Ledger, MAIN_BRANCH_NAME},
+};
+use primitive_types::{H160, H256, U256};
+use serde::{Deserialize, Serialize};
+use vsdb::{MapxVs, VersionName, Vs};
 
-fn main() {}
+// Number of storage slots touched by each call.
+const SLOTS: u8 = 32;
+
+// Number of transactions in each block.
+const TXS: u64 = 100;
+
+// Number of `EXTCODEHASH` in each call.
+const HASHES: u8 = 32;
+
+// Number of accounts read by each round.
+const ACCOUNTS: u64 = 100;
+
+// The size limit of EIP-170.
+const CODE_SIZE: usize = 24576;
+
+fn contract() -> H160 {
+    H160::from_low_u64_be(0xc0de)
+}
+
+fn hasher() -> H160 {
+    H160::from_low_u64_be(0xc0de + 1)
+}
+
+// `slot[i] += 1` for each of the first `SLOTS` slots.
+fn counter_code() -> Vec<u8> {
+    let mut code = vec![];
+    for i in 0..SLOTS {
+        // PUSH1 i, SLOAD, PUSH1 1, ADD, PUSH1 i, SSTORE
+        code.extend([0x60, i, 0x54, 0x60, 0x01, 0x01, 0x60, i, 0x55]);
+    }
+    // STOP
+    code.push(0x00);
+    code
+}
+
+// `EXTCODEHASH` of the counter contract `HASHES` times.
+fn hasher_code() -> Vec<u8> {
+    let mut code = vec![];
+    for _ in 0..HASHES {
+        // PUSH20 contract, EXTCODEHASH, POP
+        code.push(0x73);
+        code.extend(contract().as_bytes());
+        code.extend([0x3f, 0x50]);
+    }
+    // STOP
+    code.push(0x00);
+    code
+}
+
+fn new_state() -> State {
+    let ledger = Ledger::new(
+        1234,
+        String::from("BENCH"),
+        String::from("1"),
+        Some(10000000000),
+        Some(u64::MAX as u128),
+        Some(1),
+    )
+    .unwrap();
+
+    let state = ledger.state.evm;
+    let deploy = [(contract(), counter_code()), (hasher(), hasher_code())]
+        .into_iter()
+        .map(|(address, code)| Apply::Modify {
+            address,
+            basic: Basic {
+                balance: U256::zero(),
+                nonce: U256::one(),
+            },
+            code: Some(code),
+            storage: Vec::<(H256, H256)>::new(),
+            reset_storage: false,
+        })
+        .collect::<Vec<_>>();
+    state
+        .get_backend_hdr(MAIN_BRANCH_NAME)
+        .apply(deploy, vec![], false);
+    state
+}
+
+// Execute a block of transactions, all of them call the same contract,
+// as `ethvm::tx::Tx::exec` does.
+fn run_block(state: &State, target: H160, cache: Option<&BackendCache>) {
+    let cfg = state.fork_schedule.evm_cfg_at(0);
+    let precompiles =
+        OvrPrecompileSet::new(&state.fork_schedule.precompiles_at(0), None, None, None);
+
+    for i in 0..TXS {
+        let mut backend = state.get_backend_hdr(MAIN_BRANCH_NAME);
+        if let Some(c) = cache {
+            backend = backend.with_cache(c);
+        }
+
+        let metadata = StackSubstateMetadata::new(u64::MAX, &cfg);
+        let stack_state = OvrStackState::new(metadata, &backend);
+        let mut executor =
+            StackExecutor::new_with_precompiles(stack_state, &cfg, &precompiles);
+
+        let (exit_reason, _) = executor.transact_call(
+            H160::from_low_u64_be(1 + i),
+            target,
+            U256::zero(),
+            vec![],
+            10_000_000,
+            vec![],
+        );
+        assert!(exit_reason.is_succeed());
+
+        let (changes, logs) = executor.into_state().deconstruct();
+        backend.apply(changes, logs, false);
+    }
+}
+
+fn contract_heavy_block(c: &mut Criterion) {
+    let state = new_state();
+
+    let mut group = c.benchmark_group("contract_heavy_block");
+    group.sample_size(10);
+
+    group.bench_function("uncached", |b| {
+        b.iter(|| run_block(&state, contract(), None))
+    });
+
+    // one cache per block, as `ledger::StateBranch` does
+    group.bench_function("cached", |b| {
+        b.iter(|| run_block(&state, contract(), Some(&BackendCache::default())))
+    });
+
+    group.finish();
+}
+
+// The stored code hash is served without loading and hashing the code.
+fn extcodehash_block(c: &mut Criterion) {
+    let state = new_state();
+
+    let mut group = c.benchmark_group("extcodehash_block");
+    group.sample_size(10);
+
+    group.bench_function("uncached", |b| b.iter(|| run_block(&state, hasher(), None)));
+
+    group.bench_function("cached", |b| {
+        b.iter(|| run_block(&state, hasher(), Some(&BackendCache::default())))
+    });
+
+    group.finish();
+}
+
+// The layout before the code is stored by hash.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+struct InlineAccount {
+    nonce: U256,
+    balance: U256,
+    code: Vec<u8>,
+}
+
+fn big_account(i: u64) -> H160 {
+    H160::from_low_u64_be(0xb16 << 32 | i)
+}
+
+// Different for each account, so nothing is shared.
+fn big_code(i: u64) -> Vec<u8> {
+    let mut code = vec![0x5b; CODE_SIZE];
+    code[..8].copy_from_slice(&i.to_be_bytes());
+    code
+}
+
+// Balances and nonces of accounts with large code,
+// the by-hash layout does not load the code for them.
+fn account_read(c: &mut Criterion) {
+    let state = new_state();
+    let deploy = (0..ACCOUNTS)
+        .map(|i| Apply::Modify {
+            address: big_account(i),
+            basic: Basic {
+                balance: U256::from(i),
+                nonce: U256::one(),
+            },
+            code: Some(big_code(i)),
+            storage: Vec::<(H256, H256)>::new(),
+            reset_storage: false,
+        })
+        .collect::<Vec<_>>();
+    state
+        .get_backend_hdr(MAIN_BRANCH_NAME)
+        .apply(deploy, vec![], false);
+
+    let inline = MapxVs::new();
+    inline.version_create(VersionName(b"bench")).unwrap();
+    for i in 0..ACCOUNTS {
+        let account = InlineAccount {
+            nonce: U256::one(),
+            balance: U256::from(i),
+            code: big_code(i),
+        };
+        inline.insert(big_account(i), account).unwrap();
+    }
+
+    let mut group = c.benchmark_group("account_read");
+    group.sample_size(10);
+
+    group.bench_function("inline", |b| {
+        b.iter(|| {
+            for i in 0..ACCOUNTS {
+                let a: InlineAccount = inline.get(&big_account(i)).unwrap();
+                assert_eq!(U256::from(i), a.balance);
+            }
+        })
+    });
+
+    group.bench_function("by_hash", |b| {
+        b.iter(|| {
+            let backend = state.get_backend_hdr(MAIN_BRANCH_NAME);
+            for i in 0..ACCOUNTS {
+                assert_eq!(U256::from(i), backend.basic(big_account(i)).balance);
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    contract_heavy_block,
+    extcodehash_block,
+    account_read
+);
+criterion_main!(benches);
@@ -162,6 +162,7 @@ impl Env {
                 from: *addr,
                 salt,
                 bytecode,
+                ..Default::default()
             };
 
             vec![inital_contract]
@@ -417,6 +418,7 @@ impl Env {
                     let inital_state = InitalState {
                         addr_to_amount: self.token_distribution.addr_to_amount.clone(),
                         inital_contracts: self.inital_contracts.clone(),
+                        ..Default::default()
                     };
 
                     let app_state = serde_json::to_value(inital_state).c(d!())?;
@@ -66,6 +66,23 @@ pub struct DaemonCfg {
     pub tendermint_home_dir: Option<String>,
     #[clap(long, help = "A field for EIP1559")]
     pub block_base_fee_per_gas: Option<u128>,
+    #[clap(
+        long,
+        default_value_t = 0,
+        help = "Export a state-sync snapshot every N blocks, zero means disable"
+    )]
+    pub state_sync_itv: u64,
+    #[clap(
+        long,
+        default_value_t = 2,
+        help = "How many state-sync snapshots to keep"
+    )]
+    pub state_sync_keep: usize,
+    #[clap(
+        long,
+        help = "Hard forks scheduled after the genesis, a json file loaded at startup"
+    )]
+    pub fork_schedule: Option<String>,
 
     #[clap(
         short = 'A',
@@ -1,7 +1,8 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, fmt, process};
 
 use crate::{
-    ledger::{Log, VsVersion, MAIN_BRANCH_NAME},
+    ethvm::{fork::EvmFork, precompile::PrecompileCfg},
+    ledger::Log,
     {ethvm::State as EvmState, ledger::State as LedgerState},
 };
 use ethereum_types::{Bloom, BloomInput};
@@ -9,7 +10,6 @@ use primitive_types::{H160, H256, U256};
 use ruc::*;
 use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
-use vsdb::{BranchName, ParentBranchName, ValueEn, VsMgmt};
 use web3_rpc_core::types::BlockNumber;
 
 pub(crate) type BlockHeight = u64;
@@ -20,6 +20,30 @@ pub(crate) type HashValueRef<'a> = &'a [u8];
 pub(crate) type TmAddress = Vec<u8>;
 pub(crate) type TmAddressRef<'a> = &'a [u8];
 
+/// Halt the node on failures that can not be handled deterministically,
+/// such as a corrupted storage, other nodes would not see the same results,
+/// so going on means forking from the network.
+pub(crate) fn fatal(e: impl fmt::Display) -> ! {
+    eprintln!("\n\n******** FATAL ERROR, the node is halted ********\n");
+    eprintln!("{}", e);
+    eprintln!("\n******** The local data may be corrupted ********\n\n");
+    process::exit(1)
+}
+
+/// `pnk!` for the consensus paths, halt the node instead of panicking.
+macro_rules! halt {
+    ($ops: expr) => {
+        $crate::common::halt!($ops, "")
+    };
+    ($ops: expr, $msg: expr) => {
+        match $ops.c(ruc::d!($msg)) {
+            Ok(v) => v,
+            Err(e) => $crate::common::fatal(e),
+        }
+    };
+}
+pub(crate) use halt;
+
 /// global hash function
 pub fn hash_sha3_256(contents: &[&[u8]]) -> Vec<u8> {
     let mut hasher = Sha3_256::new();
@@ -49,11 +73,24 @@ pub fn block_hash_to_evm_format(hash: &HashValue) -> H256 {
     H256::from_slice(&buf)
 }
 
+/// Deployed by `CREATE2` in the genesis block.
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct InitalContract {
+    // used to identify the contract in the report of the genesis
+    #[serde(default)]
+    pub name: String,
     pub from: H160,
     pub salt: String,
     pub bytecode: String,
+    // abi encoded in hex, appended to the bytecode
+    #[serde(default)]
+    pub constructor_args: String,
+    // transferred from `from` to the contract
+    #[serde(default)]
+    pub value: U256,
+    // the block gas limit will be used if it is absent
+    #[serde(default)]
+    pub gas_limit: Option<u64>,
 }
 
 impl InitalContract {
@@ -61,55 +98,73 @@ impl InitalContract {
         Self {
             from,
             salt,
-            bytecode: String::new(),
+            ..Default::default()
         }
     }
 }
 
+/// An account at a fixed address, set directly without any execution.
 #[derive(Default, Debug, Clone, Deserialize, Serialize)]
-pub struct InitalState {
-    pub addr_to_amount: BTreeMap<H160, U256>,
-    pub inital_contracts: Vec<InitalContract>,
+pub struct InitalAccount {
+    pub address: H160,
+    #[serde(default)]
+    pub balance: U256,
+    #[serde(default)]
+    pub nonce: U256,
+    // runtime code in hex
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub storage: BTreeMap<H256, H256>,
 }
 
-pub fn rollback_to_height(
-    height: BlockHeight,
-    ledger_state: Option<&LedgerState>,
-    evm_state: Option<&EvmState>,
-    prefix: &str,
-) -> Result<String> {
-    let new_branch_name;
-
-    if height > 0 {
-        let ver = VsVersion::new(height + 1, 0);
-        new_branch_name = format!("{}_{}", prefix, height + 1);
-
-        if let Some(evm_state) = evm_state {
-            evm_state.branch_create_by_base_branch(
-                BranchName::from(new_branch_name.as_str()),
-                ParentBranchName::from(MAIN_BRANCH_NAME.0),
-            )?;
-
-            evm_state.version_create_by_branch(
-                ver.encode_value().as_ref().into(),
-                BranchName::from(new_branch_name.as_str()),
-            )?;
-        } else if let Some(ledger_state) = ledger_state {
-            ledger_state.branch_create_by_base_branch(
-                BranchName::from(new_branch_name.as_str()),
-                ParentBranchName::from(MAIN_BRANCH_NAME.0),
-            )?;
-
-            ledger_state.version_create_by_branch(
-                ver.encode_value().as_ref().into(),
-                BranchName::from(new_branch_name.as_str()),
-            )?;
-        }
-    } else {
-        return Err(eg!("block height cannot be 0"));
-    };
+/// Credited in full at the genesis, then released by a vesting schedule,
+/// the timestamps are in seconds.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct InitalVesting {
+    pub address: H160,
+    pub total: U256,
+    pub start: u64,
+    pub cliff: u64,
+    pub end: u64,
+}
 
-    Ok(new_branch_name)
+/// The address of a contract created in the genesis block.
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct GenesisContract {
+    pub name: String,
+    pub from: H160,
+    pub salt: String,
+    pub address: H160,
+}
+
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct InitalState {
+    pub addr_to_amount: BTreeMap<H160, U256>,
+    pub inital_contracts: Vec<InitalContract>,
+    // applied before the contracts above
+    #[serde(default)]
+    pub inital_accounts: Vec<InitalAccount>,
+    // applied after all the others
+    #[serde(default)]
+    pub vesting_accounts: Vec<InitalVesting>,
+    // activation height => evm fork
+    #[serde(default)]
+    pub hard_forks: BTreeMap<BlockHeight, EvmFork>,
+    // activation height => precompile set,
+    // the default set will be used before the first activation
+    #[serde(default)]
+    pub precompiles: BTreeMap<BlockHeight, PrecompileCfg>,
+    // the admin of the consensus params
+    #[serde(default)]
+    pub params_admin: Option<H160>,
+    // the validators that can be delegated to by the staking precompile
+    #[serde(default)]
+    pub validators: Vec<H160>,
+    // the state is hashed into the block header every N blocks,
+    // zero means never, the state sync needs it
+    #[serde(default)]
+    pub state_hash_itv: u64,
 }
 
 pub fn block_number_to_height(
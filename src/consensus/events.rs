@@ -0,0 +1,71 @@
+//!
+//! # Events of the delivered transactions
+//!
+//! Indexed by tendermint, eg. `tx_search "ethereum_tx.from='0x..'"`.
+//!
+
+use crate::{common::block_hash_to_evm_format, ledger::Receipt, tx::Tx};
+use primitive_types::U256;
+use ruc::*;
+use tmtypes::abci::{Event, EventAttribute};
+
+const EVENT_TX: &str = "ethereum_tx";
+const EVENT_LOG: &str = "ethereum_log";
+
+pub(super) fn tx_events(
+    tx: &Tx,
+    receipt: Option<&Receipt>,
+    gas_used: U256,
+) -> Vec<Event> {
+    let hash = format!("{:?}", block_hash_to_evm_format(&tx.hash()));
+
+    let mut attrs = vec![attr("hash", hash.clone())];
+
+    let (from, to) = match (receipt, tx) {
+        (Some(r), _) => (r.from, r.to),
+        (None, Tx::Evm(tx)) => tx.get_from_to(),
+        (None, Tx::Native(_)) => (None, None),
+    };
+    if let Some(from) = from {
+        attrs.push(attr("from", format!("{:?}", from)));
+    }
+    if let Some(to) = to {
+        attrs.push(attr("to", format!("{:?}", to)));
+    }
+    if let Some(contract) = receipt.and_then(|r| r.contract_addr) {
+        attrs.push(attr("contract", format!("{:?}", contract)));
+    }
+    let status = receipt.map(|r| r.status_code).unwrap_or(false);
+    attrs.push(attr("status", alt!(status, "1", "0").to_owned()));
+    attrs.push(attr("gas_used", gas_used.to_string()));
+
+    let mut events = vec![Event {
+        r#type: EVENT_TX.to_owned(),
+        attributes: attrs,
+    }];
+
+    for log in receipt.iter().flat_map(|r| r.logs.iter()) {
+        let mut attrs = vec![
+            attr("hash", hash.clone()),
+            attr("address", format!("{:?}", log.address)),
+        ];
+        for topic in log.topics.iter() {
+            attrs.push(attr("topic", format!("{:?}", topic)));
+        }
+        events.push(Event {
+            r#type: EVENT_LOG.to_owned(),
+            attributes: attrs,
+        });
+    }
+
+    events
+}
+
+#[inline(always)]
+fn attr(key: &str, value: String) -> EventAttribute {
+    EventAttribute {
+        key: key.as_bytes().to_vec(),
+        value: value.into_bytes(),
+        index: true,
+    }
+}
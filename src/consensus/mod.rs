@@ -4,26 +4,43 @@
 
 #![allow(warnings)]
 
+mod events;
+mod params;
+mod query;
+mod snapshot;
+
 use crate::ledger::State;
 use crate::{
     cfg::DaemonCfg as Cfg,
-    common::{BlockHeight, HashValue},
-    ethvm::{tx::inital_create2, OvrAccount},
-    ledger::{Ledger, Receipt},
+    common::{halt, BlockHeight, HashValue},
+    ethvm::{
+        fork::ForkUpgrade,
+        tx::{inital_account, inital_create2, inital_vesting},
+        OvrAccount,
+    },
+    ledger::{ConsensusInfo, Ledger, Receipt},
     tx::Tx,
-    InitalState,
+    GenesisContract, InitalState,
 };
 use abci::Application;
 use primitive_types::{H160, U256};
 use ruc::*;
-use std::{collections::BTreeMap, fmt::format};
+use std::{collections::BTreeMap, fmt::format, fs};
 use tmtypes::abci::{
-    RequestBeginBlock, RequestCheckTx, RequestDeliverTx, RequestEndBlock, RequestInfo,
-    RequestInitChain, ResponseBeginBlock, ResponseCheckTx, ResponseCommit,
+    CheckTxType, RequestApplySnapshotChunk, RequestBeginBlock, RequestCheckTx,
+    RequestDeliverTx, RequestEndBlock, RequestInfo, RequestInitChain,
+    RequestListSnapshots, RequestLoadSnapshotChunk, RequestOfferSnapshot, RequestQuery,
+    ResponseApplySnapshotChunk, ResponseBeginBlock, ResponseCheckTx, ResponseCommit,
     ResponseDeliverTx, ResponseEndBlock, ResponseInfo, ResponseInitChain,
+    ResponseListSnapshots, ResponseLoadSnapshotChunk, ResponseOfferSnapshot,
+    ResponseQuery,
 };
 use vsdb::MapxOrd;
 
+/// Version of the state machine, reported to tendermint in the handshake,
+/// bump it on any change of the results of the transactions.
+pub const APP_VERSION: u64 = 1;
+
 #[derive(Clone)]
 pub struct App {
     pub cfg: Cfg,
@@ -47,10 +64,50 @@ impl App {
 
     pub fn load_or_create(cfg: Cfg) -> Result<Self> {
         cfg.set_vsdb_base_dir().c(d!())?;
-        if let Some(ledger) = Ledger::load_from_snapshot().c(d!())? {
-            Ok(Self { cfg, ledger })
+        let app = if let Some(ledger) = Ledger::load_from_snapshot().c(d!())? {
+            Self { cfg, ledger }
         } else {
-            Self::new(cfg).c(d!())
+            Self::new(cfg).c(d!())?
+        };
+        app.load_fork_schedule().c(d!())?;
+        Ok(app)
+    }
+
+    /// Apply the forks of `DaemonCfg::fork_schedule`,
+    /// the heights that have been committed can not be scheduled.
+    pub fn load_fork_schedule(&self) -> Result<()> {
+        if let Some(path) = self.cfg.fork_schedule.as_ref() {
+            let upgrade = fs::read(path)
+                .c(d!())
+                .and_then(|b| serde_json::from_slice::<ForkUpgrade>(&b).c(d!()))?;
+            let last = self.ledger.main.read().last_block();
+            self.ledger
+                .state
+                .evm
+                .fork_schedule
+                .upgrade(&upgrade, last.map(|b| b.header.height))
+                .c(d!())?;
+        }
+        Ok(())
+    }
+
+    // Blocks must come one by one after the last committed one,
+    // tendermint replays the missing ones after a crash,
+    // but it can never go back to a committed height.
+    fn check_block_height(&self, height: BlockHeight) -> Result<()> {
+        let last = self.ledger.main.read().last_block();
+        match last.map(|b| b.header.height) {
+            Some(last) if height <= last => Err(eg!(
+                "the app is ahead of tendermint, app: {}, tendermint: {}",
+                last,
+                height
+            )),
+            Some(last) if height > last + 1 => Err(eg!(
+                "blocks are missing, app: {}, tendermint: {}",
+                last,
+                height
+            )),
+            _ => Ok(()),
         }
     }
 
@@ -84,10 +141,21 @@ impl Application for App {
         if 0 < h {
             resp.last_block_app_hash = b.header_hash;
         }
+        resp.app_version = APP_VERSION;
+        resp.version = env!("CARGO_PKG_VERSION").to_owned();
+        resp.data = format!(
+            "{} {}",
+            ledger.state.chain_name.get_value(),
+            ledger.state.chain_version.get_value()
+        );
 
         println!("\n\n");
         println!("==========================================");
         println!("======== Last committed height: {} ========", h);
+        println!(
+            "======== App version: {}, tendermint: {} ========",
+            APP_VERSION, req.version
+        );
         println!("==========================================");
         println!("\n\n");
 
@@ -95,54 +163,119 @@ impl Application for App {
     }
 
     fn init_chain(&self, req: RequestInitChain) -> ResponseInitChain {
+        // tendermint has not committed any block
+        if let Some(b) = self.ledger.main.read().last_block() {
+            halt!(Err(eg!(
+                "the app is ahead of tendermint, app: {}, tendermint: 0",
+                b.header.height
+            )));
+        }
+
         if !req.app_state_bytes.is_empty() {
-            let inital_state =
-                pnk!(serde_json::from_slice::<InitalState>(&req.app_state_bytes));
+            let inital_state = halt!(
+                serde_json::from_slice::<InitalState>(&req.app_state_bytes),
+                "invalid genesis state"
+            );
+
+            // nothing has been committed, checked above
+            let fork_schedule = &self.ledger.state.evm.fork_schedule;
+            halt!(fork_schedule.set(&inital_state.hard_forks, None));
+            halt!(fork_schedule.set_precompiles(&inital_state.precompiles, None));
+
+            if let Some(admin) = inital_state.params_admin {
+                halt!(self.ledger.state.params_admin.set_value(admin));
+            }
+            halt!(self
+                .ledger
+                .state
+                .state_hash_itv
+                .set_value(inital_state.state_hash_itv));
 
             let token_distribution = inital_state.addr_to_amount;
 
             for (addr, am) in token_distribution.into_iter() {
-                pnk!(
-                    self.ledger
-                        .state
-                        .evm
-                        .OFUEL
-                        .accounts
-                        .insert(addr, OvrAccount::from_balance(am))
-                );
+                halt!(self
+                    .ledger
+                    .state
+                    .evm
+                    .OFUEL
+                    .accounts
+                    .insert(addr, OvrAccount::from_balance(am)));
             }
 
             let b = self.ledger.main.read().branch.clone();
             let b = b.as_slice().into();
 
+            for account in inital_state.inital_accounts {
+                halt!(inital_account(account, &self.ledger.state.evm, b));
+            }
+
+            let mut contracts = vec![];
             for contract in inital_state.inital_contracts {
-                pnk!(inital_create2(contract, &self.ledger.state.evm, b));
+                let (name, from, salt) =
+                    (contract.name.clone(), contract.from, contract.salt.clone());
+                let address = halt!(inital_create2(contract, &self.ledger.state.evm, b));
+                contracts.push(GenesisContract {
+                    name,
+                    from,
+                    salt,
+                    address,
+                });
+            }
+            halt!(self.ledger.state.genesis_contracts.set_value(contracts));
+
+            for vesting in inital_state.vesting_accounts {
+                halt!(inital_vesting(vesting, &self.ledger.state.evm, b));
+            }
+
+            for validator in inital_state.validators {
+                halt!(self.ledger.state.staking.register_validator(validator, b));
             }
         }
-        ResponseInitChain::default()
+
+        halt!(params::init(
+            &self.ledger.state,
+            req.consensus_params.as_ref()
+        ));
+
+        let mut resp = ResponseInitChain::default();
+        resp.consensus_params = Some(halt!(params::full(&self.ledger.state)));
+        resp
+    }
+
+    fn query(&self, req: RequestQuery) -> ResponseQuery {
+        query::query(&self.ledger.state, &req)
     }
 
     fn check_tx(&self, req: RequestCheckTx) -> ResponseCheckTx {
         let mut resp = ResponseCheckTx::default();
-        alt!(0 != req.r#type, return resp);
-
-        match Tx::deserialize(&req.tx) {
-            Ok(tx) => {
-                if tx.valid_in_abci() {
-                    let mut sb = self.ledger.check_tx.write();
-                    if let Err(e) = info!(sb.apply_tx(tx)) {
-                        resp.log = e.to_string();
-                        resp.code = 1;
-                    }
-                } else {
-                    resp.log = "Should not appear in ABCI".to_owned();
-                    resp.code = 1;
-                }
+
+        let ret = Tx::decode_in_abci(&req.tx).and_then(|tx| {
+            let mut sb = self.ledger.check_tx.write();
+            tx.valid_in_abci(&sb)?;
+            let tx_hash = tx.hash();
+            // counted by tendermint against the `max_gas` of blocks
+            let gas_wanted = match &tx {
+                Tx::Evm(tx) => to_i64(tx.gas_limit()),
+                Tx::Native(_) => 0,
+            };
+            if CheckTxType::Recheck as i32 == req.r#type {
+                sb.recheck_tx(tx)?;
+            } else {
+                sb.check_tx(tx)?;
+            }
+            Ok((sb.priority(&tx_hash), gas_wanted))
+        });
+
+        match ret {
+            Ok((priority, gas_wanted)) => {
+                resp.priority = priority;
+                resp.gas_wanted = gas_wanted;
             }
             Err(e) => {
-                println!("deserialize err:{:?}", e);
                 resp.log = e.to_string();
-                resp.code = 1;
+                resp.code = e.code();
+                resp.codespace = e.codespace().to_owned();
             }
         }
 
@@ -150,11 +283,36 @@ impl Application for App {
     }
 
     fn begin_block(&self, req: RequestBeginBlock) -> ResponseBeginBlock {
-        let header = req.header.unwrap();
+        // never absent in the requests from tendermint
+        let header = halt!(req.header, "no header in BeginBlock");
         let height = header.height as u64;
-        let ts = header.time.unwrap().seconds as u64;
-
-        pnk!(self.ledger.consensus_refresh(header.proposer_address, ts));
+        let time = halt!(header.time, "no time in BeginBlock header");
+        halt!(self.check_block_height(height));
+
+        let (last_commit_signed_power, last_commit_total_power) = req
+            .last_commit_info
+            .map(|c| {
+                c.votes.iter().fold((0, 0), |(signed, total), v| {
+                    let power =
+                        v.validator.as_ref().map(|v| v.power as u64).unwrap_or(0);
+                    let signed = alt!(v.signed_last_block, signed + power, signed);
+                    (signed, total + power)
+                })
+            })
+            .unwrap_or_default();
+
+        let consensus = ConsensusInfo {
+            proposer: header.proposer_address,
+            validators_hash: header.validators_hash,
+            block_time_ns: (time.seconds as u64)
+                .saturating_mul(1_000_000_000)
+                .saturating_add(time.nanos as u64),
+            block_hash: req.hash,
+            last_commit_signed_power,
+            last_commit_total_power,
+        };
+
+        halt!(self.ledger.consensus_refresh(consensus));
 
         info_omit!(self.btm_snapshot(height));
 
@@ -164,35 +322,93 @@ impl Application for App {
     fn deliver_tx(&self, req: RequestDeliverTx) -> ResponseDeliverTx {
         let mut resp = ResponseDeliverTx::default();
 
-        if let Ok(tx) = Tx::deserialize(&req.tx) {
-            if tx.valid_in_abci() {
-                let mut sb = self.ledger.deliver_tx.write();
-                if let Err(e) = sb.apply_tx(tx.clone()) {
-                    resp.log = e.to_string();
-                    resp.code = 1;
-                }
-            } else {
-                resp.log = "Should not appear in ABCI".to_owned();
-                resp.code = 1;
+        let tx = match Tx::decode_in_abci(&req.tx) {
+            Ok(tx) => tx,
+            Err(e) => {
+                resp.log = e.to_string();
+                resp.code = e.code();
+                resp.codespace = e.codespace().to_owned();
+                return resp;
             }
-        } else {
-            resp.log = "Invalid data format".to_owned();
-            resp.code = 1;
+        };
+
+        if let Tx::Evm(evm_tx) = &tx {
+            resp.gas_wanted = to_i64(evm_tx.gas_limit());
         }
 
+        let mut sb = self.ledger.deliver_tx.write();
+        let ret = tx
+            .valid_in_abci(&sb)
+            .map_err(|e| (e, U256::zero()))
+            .and_then(|_| sb.apply_tx_with_receipt(tx.clone()));
+
+        let (receipt, gas_used) = match ret {
+            Ok(receipt) => {
+                let gas_used = receipt.as_ref().map(|r| r.tx_gas_used);
+                (receipt, gas_used.unwrap_or_default())
+            }
+            Err((e, gas_used)) => {
+                resp.log = e.to_string();
+                resp.code = e.code();
+                resp.codespace = e.codespace().to_owned();
+                (None, gas_used)
+            }
+        };
+
+        resp.gas_used = to_i64(gas_used);
+        resp.events = events::tx_events(&tx, receipt.as_ref(), gas_used);
+
         resp
     }
 
     // TODO: staking related logic
     fn end_block(&self, _req: RequestEndBlock) -> ResponseEndBlock {
-        ResponseEndBlock::default()
+        let mut resp = ResponseEndBlock::default();
+        resp.consensus_param_updates = halt!(params::updates(&self.ledger));
+        resp
     }
 
     fn commit(&self) -> ResponseCommit {
-        pnk!(self.ledger.commit());
+        halt!(self.ledger.commit());
+
+        let main = self.ledger.main.read();
+        let height = main.last_block().map(|b| b.header.height).unwrap_or(0);
 
         let mut r = ResponseCommit::default();
-        r.data = self.ledger.main.read().last_block_hash();
+        r.data = main.last_block_hash();
+        drop(main);
+
+        if 0 < self.cfg.state_sync_itv && 0 == height % self.cfg.state_sync_itv {
+            snapshot::export(&self.ledger, self.cfg.state_sync_keep);
+        }
+
         r
     }
+
+    fn list_snapshots(&self, _req: RequestListSnapshots) -> ResponseListSnapshots {
+        snapshot::list_snapshots()
+    }
+
+    fn offer_snapshot(&self, req: RequestOfferSnapshot) -> ResponseOfferSnapshot {
+        snapshot::offer_snapshot(&self.ledger, req)
+    }
+
+    fn load_snapshot_chunk(
+        &self,
+        req: RequestLoadSnapshotChunk,
+    ) -> ResponseLoadSnapshotChunk {
+        snapshot::load_snapshot_chunk(req)
+    }
+
+    fn apply_snapshot_chunk(
+        &self,
+        req: RequestApplySnapshotChunk,
+    ) -> ResponseApplySnapshotChunk {
+        snapshot::apply_snapshot_chunk(&self.ledger, req)
+    }
+}
+
+#[inline(always)]
+fn to_i64(v: U256) -> i64 {
+    v.min(U256::from(i64::MAX)).as_u64() as i64
 }
@@ -0,0 +1,102 @@
+//!
+//! # Consensus parameters
+//!
+//! Reported to tendermint by `InitChain` in full,
+//! then by `EndBlock` when the block in process has changed any of them.
+//!
+
+use crate::ledger::{ConsensusParams, Ledger, State, MAIN_BRANCH_NAME};
+use primitive_types::U256;
+use ruc::*;
+use tmtypes::{
+    abci::{BlockParams, ConsensusParams as TmParams},
+    google::protobuf::Duration,
+    types::EvidenceParams,
+};
+use vsdb::BranchName;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+// Take the block size and evidence params from the genesis of tendermint.
+pub(super) fn init(state: &State, genesis: Option<&TmParams>) -> Result<()> {
+    let mut params = state.consensus_params.get_value();
+
+    if let Some(block) = genesis.and_then(|p| p.block.as_ref()) {
+        params.block_max_bytes = block.max_bytes;
+    }
+    if let Some(evidence) = genesis.and_then(|p| p.evidence.as_ref()) {
+        params.evidence_max_age_num_blocks = evidence.max_age_num_blocks;
+        params.evidence_max_bytes = evidence.max_bytes;
+        if let Some(d) = evidence.max_age_duration.as_ref() {
+            params.evidence_max_age_duration_ns = (d.seconds as u64)
+                .saturating_mul(NS_PER_SEC)
+                .saturating_add(d.nanos as u64);
+        }
+    }
+
+    state.consensus_params.set_value(params).c(d!())
+}
+
+pub(super) fn full(state: &State) -> Result<TmParams> {
+    let b = MAIN_BRANCH_NAME;
+    let (params, block_gas_limit) = get(state, b);
+    Ok(to_tm(&params, block_gas_limit))
+}
+
+// Compare the branch of the block in process with the last committed state.
+pub(super) fn updates(ledger: &Ledger) -> Result<Option<TmParams>> {
+    let state = &ledger.state;
+    let b: BranchName = ledger.deliver_tx.read().branch.as_slice().into();
+
+    let last = get(state, MAIN_BRANCH_NAME);
+    let next = get(state, b);
+    if last == next {
+        return Ok(None);
+    }
+
+    Ok(Some(to_tm(&next.0, next.1)))
+}
+
+// Ledgers created before the params were kept in the state have no value,
+// they have been running with the defaults of tendermint.
+fn get(state: &State, b: BranchName) -> (ConsensusParams, U256) {
+    (
+        state
+            .consensus_params
+            .get_value_by_branch(b)
+            .unwrap_or_default(),
+        state
+            .evm
+            .block_gas_limit
+            .get_value_by_branch(b)
+            .unwrap_or_else(|| U256::from(u128::MAX)),
+    )
+}
+
+fn to_tm(params: &ConsensusParams, block_gas_limit: U256) -> TmParams {
+    // -1 means no limit for tendermint
+    let max_gas = if block_gas_limit > U256::from(i64::MAX) {
+        -1
+    } else {
+        block_gas_limit.as_u64() as i64
+    };
+
+    let ns = params.evidence_max_age_duration_ns;
+
+    TmParams {
+        block: Some(BlockParams {
+            max_bytes: params.block_max_bytes,
+            max_gas,
+        }),
+        evidence: Some(EvidenceParams {
+            max_age_num_blocks: params.evidence_max_age_num_blocks,
+            max_age_duration: Some(Duration {
+                seconds: (ns / NS_PER_SEC) as i64,
+                nanos: (ns % NS_PER_SEC) as i32,
+            }),
+            max_bytes: params.evidence_max_bytes,
+        }),
+        validator: None,
+        version: None,
+    }
+}
@@ -0,0 +1,191 @@
+//!
+//! # ABCI queries
+//!
+//! Paths, all results are encoded in json:
+//! - `/account/<address>`, `/balance/<address>`, `/nonce/<address>`
+//! - `/spendable/<address>`, the balance without the locked vesting part
+//! - `/code/<address>`, `/storage/<address>/<slot>`
+//! - `/block`, `/block/<height>`, `/receipt/<tx hash>`
+//! - `/call`, with a json encoded `CallRequest` as the data
+//! - `/genesis/contracts`, the addresses of the contracts created in the genesis
+//!
+//! The state is read at the height of the request, 0 means the latest one.
+//! Proofs can not be given, the state has no commitment yet.
+//!
+
+use crate::{
+    common::{block_hash_to_evm_format, BlockHeight, HashValue},
+    ledger::{view::StateView, State},
+};
+use primitive_types::{H160, H256, U256};
+use ruc::*;
+use serde::Serialize;
+use serde_json::json;
+use sha3::{Digest, Keccak256};
+use tmtypes::abci::{RequestQuery, ResponseQuery};
+use web3_rpc_core::types::CallRequest;
+
+const CODE_QUERY_FAILED: u32 = 1;
+
+pub(super) fn query(state: &State, req: &RequestQuery) -> ResponseQuery {
+    let mut resp = ResponseQuery::default();
+
+    match route(state, req) {
+        Ok((height, value)) => {
+            resp.key = req.path.as_bytes().to_vec();
+            resp.value = value;
+            resp.height = height as i64;
+        }
+        Err(e) => {
+            resp.code = CODE_QUERY_FAILED;
+            resp.log = e.to_string();
+        }
+    }
+
+    resp
+}
+
+fn route(state: &State, req: &RequestQuery) -> Result<(BlockHeight, Vec<u8>)> {
+    if req.prove {
+        return Err(eg!("proofs are not available"));
+    }
+    if 0 > req.height {
+        return Err(eg!("invalid height: {}", req.height));
+    }
+    let height = req.height as BlockHeight;
+
+    let view = || state.view_at(height, None).c(d!());
+    let segments = req.path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+    match segments.as_slice() {
+        ["account", addr] => {
+            let (view, addr) = (view()?, parse_address(addr)?);
+            let code_hash = H256::from_slice(&Keccak256::digest(&view.code(addr)));
+            encode(
+                &view,
+                json!({
+                    "balance": view.balance(addr),
+                    "spendable": view.spendable_balance(addr),
+                    "nonce": view.nonce(addr),
+                    "code_hash": code_hash,
+                }),
+            )
+        }
+        ["balance", addr] => {
+            let (view, addr) = (view()?, parse_address(addr)?);
+            encode(&view, view.balance(addr))
+        }
+        ["spendable", addr] => {
+            let (view, addr) = (view()?, parse_address(addr)?);
+            encode(&view, view.spendable_balance(addr))
+        }
+        ["nonce", addr] => {
+            let (view, addr) = (view()?, parse_address(addr)?);
+            encode(&view, view.nonce(addr))
+        }
+        ["code", addr] => {
+            let (view, addr) = (view()?, parse_address(addr)?);
+            encode(&view, format!("0x{}", hex::encode(view.code(addr))))
+        }
+        ["storage", addr, slot] => {
+            let (view, addr) = (view()?, parse_address(addr)?);
+            let mut index = [0; 32];
+            U256::from_str_radix(slot.trim_start_matches("0x"), 16)
+                .c(d!("invalid slot"))?
+                .to_big_endian(&mut index);
+            encode(&view, view.storage(addr, H256::from(index)))
+        }
+        ["block"] => block(state, height),
+        ["block", h] => block(state, h.parse().c(d!("invalid height"))?),
+        ["receipt", hash] => receipt(state, height, hash),
+        ["genesis", "contracts"] => {
+            let view = view()?;
+            encode(&view, view.genesis_contracts())
+        }
+        ["call"] => {
+            let call = serde_json::from_slice::<CallRequest>(&req.data).c(d!())?;
+            let view = view()?;
+            let resp = view.call_contract(call).c(d!())?;
+            encode(&view, resp)
+        }
+        _ => Err(eg!("unknown path: {}", req.path)),
+    }
+}
+
+fn block(state: &State, height: BlockHeight) -> Result<(BlockHeight, Vec<u8>)> {
+    let block = if 0 == height {
+        state.blocks.last().map(|(_, b)| b)
+    } else {
+        state.blocks.get(&height)
+    }
+    .c(d!("block not found"))?;
+
+    let txs = block
+        .txs
+        .iter()
+        .map(|tx| block_hash_to_evm_format(&tx.hash()))
+        .collect::<Vec<_>>();
+
+    let value = json!({
+        "height": block.header.height,
+        "hash": to_hex(&block.header_hash),
+        "prev_hash": to_hex(&block.header.prev_hash),
+        "proposer": to_hex(&block.header.proposer),
+        "timestamp": block.header.timestamp,
+        "tx_root": to_hex(&block.header.tx_merkle.root_hash),
+        "txs": txs,
+    });
+    serde_json::to_vec(&value)
+        .c(d!())
+        .map(|v| (block.header.height, v))
+}
+
+// Not found at the heights before the block of the tx.
+fn receipt(
+    state: &State,
+    height: BlockHeight,
+    hash: &str,
+) -> Result<(BlockHeight, Vec<u8>)> {
+    let hash = hex::decode(hash.trim_start_matches("0x")).c(d!("invalid hash"))?;
+    let hash = HashValue::from(hash);
+
+    let h = state
+        .tx_height(&hash)
+        .filter(|h| 0 == height || *h <= height)
+        .c(d!("receipt not found"))?;
+    let block = state.blocks.get(&h).c(d!("block not found"))?;
+    let receipt = block
+        .header
+        .receipts
+        .get(&hash)
+        .cloned()
+        .c(d!("receipt not found"))?;
+
+    let value = json!({
+        "height": h,
+        "block_hash": to_hex(&block.header_hash),
+        "receipt": receipt,
+    });
+    serde_json::to_vec(&value).c(d!()).map(|v| (h, v))
+}
+
+#[inline(always)]
+fn encode<T: Serialize>(view: &StateView, value: T) -> Result<(BlockHeight, Vec<u8>)> {
+    serde_json::to_vec(&value)
+        .c(d!())
+        .map(|v| (view.height(), v))
+}
+
+#[inline(always)]
+fn parse_address(addr: &str) -> Result<H160> {
+    let bytes = hex::decode(addr.trim_start_matches("0x")).c(d!("invalid address"))?;
+    if H160::len_bytes() != bytes.len() {
+        return Err(eg!("invalid address length"));
+    }
+    Ok(H160::from_slice(&bytes))
+}
+
+#[inline(always)]
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
@@ -0,0 +1,165 @@
+//!
+//! # ABCI state sync
+//!
+//! Snapshots are exported in the background, after the `Commit` of some heights.
+//!
+//! An offered snapshot is accepted if it matches the trusted app hash,
+//! its chunks are buffered in order, then imported and verified
+//! against the state hash in the header after the last one.
+//!
+
+use crate::{
+    common::hash_sha3_256,
+    ledger::{
+        sync::{self, SnapshotMeta, SNAPSHOT_FORMAT},
+        Ledger,
+    },
+};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use ruc::*;
+use std::{
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+use tmtypes::abci::{
+    response_apply_snapshot_chunk::Result as ApplyResult,
+    response_offer_snapshot::Result as OfferResult, RequestApplySnapshotChunk,
+    RequestLoadSnapshotChunk, RequestOfferSnapshot, ResponseApplySnapshotChunk,
+    ResponseListSnapshots, ResponseLoadSnapshotChunk, ResponseOfferSnapshot, Snapshot,
+};
+use vsdb::ValueEnDe;
+
+// At most one export at a time.
+static EXPORTING: AtomicBool = AtomicBool::new(false);
+
+// The snapshot accepted by `offer_snapshot`, being restored.
+static RESTORING: Lazy<Mutex<Option<Restoring>>> = Lazy::new(|| Mutex::new(None));
+
+struct Restoring {
+    meta: SnapshotMeta,
+    // the chunks applied so far, in order
+    payload: Vec<u8>,
+    applied: u32,
+}
+
+// Do not block the consensus, skipped if the last export is still running.
+pub(super) fn export(ledger: &Ledger, keep: usize) {
+    if EXPORTING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let ledger = ledger.clone();
+    thread::spawn(move || {
+        info_omit!(ledger.export_snapshot(keep));
+        EXPORTING.store(false, Ordering::Release);
+    });
+}
+
+pub(super) fn list_snapshots() -> ResponseListSnapshots {
+    let snapshots = info!(sync::list_snapshots()).unwrap_or_default();
+
+    ResponseListSnapshots {
+        snapshots: snapshots
+            .into_iter()
+            .map(|meta| Snapshot {
+                height: meta.height,
+                format: meta.format,
+                chunks: meta.chunk_hashes.len() as u32,
+                hash: meta.hash.clone(),
+                metadata: meta.encode(),
+            })
+            .collect(),
+    }
+}
+
+pub(super) fn offer_snapshot(
+    ledger: &Ledger,
+    req: RequestOfferSnapshot,
+) -> ResponseOfferSnapshot {
+    let result = match req.snapshot {
+        Some(s) if SNAPSHOT_FORMAT != s.format => OfferResult::RejectFormat,
+        // only restored into an empty ledger
+        _ if ledger.main.read().last_block().is_some() => OfferResult::Abort,
+        Some(s) => match SnapshotMeta::decode(&s.metadata) {
+            Ok(meta)
+                if meta.height == s.height
+                    && meta.hash == s.hash
+                    && meta.chunk_hashes.len() == s.chunks as usize
+                    && meta.app_hash == req.app_hash =>
+            {
+                *RESTORING.lock() = Some(Restoring {
+                    meta,
+                    payload: vec![],
+                    applied: 0,
+                });
+                OfferResult::Accept
+            }
+            _ => OfferResult::Reject,
+        },
+        None => OfferResult::Reject,
+    };
+
+    ResponseOfferSnapshot {
+        result: result as i32,
+    }
+}
+
+pub(super) fn load_snapshot_chunk(
+    req: RequestLoadSnapshotChunk,
+) -> ResponseLoadSnapshotChunk {
+    let chunk = if SNAPSHOT_FORMAT == req.format {
+        info!(sync::load_snapshot_chunk(req.height, req.chunk)).unwrap_or_default()
+    } else {
+        vec![]
+    };
+
+    ResponseLoadSnapshotChunk { chunk }
+}
+
+// Tendermint applies the chunks one by one, in order.
+pub(super) fn apply_snapshot_chunk(
+    ledger: &Ledger,
+    req: RequestApplySnapshotChunk,
+) -> ResponseApplySnapshotChunk {
+    let mut resp = ResponseApplySnapshotChunk::default();
+
+    let mut restoring = RESTORING.lock();
+    let r = match restoring.as_mut() {
+        Some(r) => r,
+        None => {
+            resp.result = ApplyResult::Abort as i32;
+            return resp;
+        }
+    };
+
+    if req.index != r.applied {
+        *restoring = None;
+        resp.result = ApplyResult::RejectSnapshot as i32;
+        return resp;
+    }
+
+    // fetched again from another peer
+    if Some(&hash_sha3_256(&[&req.chunk])) != r.meta.chunk_hashes.get(req.index as usize)
+    {
+        resp.result = ApplyResult::Retry as i32;
+        resp.refetch_chunks = vec![req.index];
+        resp.reject_senders = vec![req.sender];
+        return resp;
+    }
+
+    r.payload.extend_from_slice(&req.chunk);
+    r.applied += 1;
+
+    if r.meta.chunk_hashes.len() == r.applied as usize {
+        let (meta, payload) = (mem::take(&mut r.meta), mem::take(&mut r.payload));
+        *restoring = None;
+        if info!(ledger.import_snapshot(&meta, &payload)).is_err() {
+            resp.result = ApplyResult::RejectSnapshot as i32;
+            return resp;
+        }
+    }
+
+    resp.result = ApplyResult::Accept as i32;
+    resp
+}
@@ -0,0 +1,205 @@
+//!
+//! # Hard-fork schedule of the evm
+//!
+//! Each fork is activated at a given block height,
+//! the evm config of a block is decided by the latest activated fork,
+//! and so is the precompile set.
+//!
+
+use crate::{
+    common::BlockHeight,
+    ethvm::precompile::{check_cfg, PrecompileCfg, DEFAULT_PRECOMPILE_CFG},
+};
+use evm::Config as EvmCfg;
+use ruc::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use vsdb::{MapxOrd, Vs};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum EvmFork {
+    Istanbul,
+    // EIP-2565, EIP-2929, EIP-2930
+    Berlin,
+    // EIP-1559, EIP-3198, EIP-3529, EIP-3541
+    London,
+}
+
+impl EvmFork {
+    #[inline(always)]
+    pub fn evm_cfg(self) -> EvmCfg {
+        match self {
+            Self::Istanbul => EvmCfg::istanbul(),
+            Self::Berlin => EvmCfg::berlin(),
+            Self::London => EvmCfg::london(),
+        }
+    }
+}
+
+impl Default for EvmFork {
+    fn default() -> Self {
+        Self::Istanbul
+    }
+}
+
+/// Forks scheduled after the genesis, reloaded from the daemon config at startup.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ForkUpgrade {
+    #[serde(default)]
+    pub hard_forks: BTreeMap<BlockHeight, EvmFork>,
+    #[serde(default)]
+    pub precompiles: BTreeMap<BlockHeight, PrecompileCfg>,
+}
+
+#[derive(Vs, Clone, Debug, Deserialize, Serialize)]
+pub struct ForkSchedule {
+    // activation height => fork
+    forks: MapxOrd<BlockHeight, EvmFork>,
+    // activation height => precompile set
+    precompiles: MapxOrd<BlockHeight, PrecompileCfg>,
+}
+
+impl ForkSchedule {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {
+            forks: MapxOrd::new(),
+            precompiles: MapxOrd::new(),
+        }
+    }
+
+    // NOTE:
+    // The schedule can only be extended forward,
+    // forks that have been activated must keep unchanged.
+    //
+    // `committed` is the last committed height,
+    // `None` means no block has been committed, in the genesis or state sync.
+    pub fn set(
+        &self,
+        schedule: &BTreeMap<BlockHeight, EvmFork>,
+        committed: Option<BlockHeight>,
+    ) -> Result<()> {
+        check_heights(schedule.keys(), committed).c(d!())?;
+
+        let mut last = self.forks.last().map(|(_, f)| f).unwrap_or_default();
+        for (h, fork) in schedule.iter() {
+            if *fork < last {
+                return Err(eg!(
+                    "Invalid fork schedule: {:?} at height {} is older than {:?}",
+                    fork,
+                    h,
+                    last
+                ));
+            }
+            last = *fork;
+        }
+
+        schedule.iter().for_each(|(h, fork)| {
+            self.forks.insert(*h, *fork);
+        });
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn fork_at(&self, height: BlockHeight) -> EvmFork {
+        self.forks
+            .get_le(&height)
+            .map(|(_, fork)| fork)
+            .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn evm_cfg_at(&self, height: BlockHeight) -> EvmCfg {
+        self.fork_at(height).evm_cfg()
+    }
+
+    #[inline(always)]
+    pub fn iter(&self) -> impl Iterator<Item = (BlockHeight, EvmFork)> + '_ {
+        self.forks.iter()
+    }
+
+    // NOTE:
+    // The whole precompile set is replaced at each activation height,
+    // addresses missing in the new set will be disabled.
+    pub fn set_precompiles(
+        &self,
+        schedule: &BTreeMap<BlockHeight, PrecompileCfg>,
+        committed: Option<BlockHeight>,
+    ) -> Result<()> {
+        check_heights(schedule.keys(), committed).c(d!())?;
+        for (h, cfg) in schedule.iter() {
+            check_cfg(cfg).c(d!(format!("height: {}", h)))?;
+        }
+
+        schedule.iter().for_each(|(h, cfg)| {
+            self.precompiles.insert(*h, cfg.clone());
+        });
+
+        Ok(())
+    }
+
+    // The same file is loaded at each startup,
+    // so the entries that have been scheduled are skipped.
+    pub fn upgrade(
+        &self,
+        upgrade: &ForkUpgrade,
+        committed: Option<BlockHeight>,
+    ) -> Result<()> {
+        let forks = upgrade
+            .hard_forks
+            .iter()
+            .filter(|(h, fork)| self.forks.get(h) != Some(**fork))
+            .map(|(h, fork)| (*h, *fork))
+            .collect();
+        let precompiles = upgrade
+            .precompiles
+            .iter()
+            .filter(|(h, cfg)| self.precompiles.get(h).as_ref() != Some(*cfg))
+            .map(|(h, cfg)| (*h, cfg.clone()))
+            .collect();
+
+        self.set(&forks, committed).c(d!())?;
+        self.set_precompiles(&precompiles, committed).c(d!())
+    }
+
+    #[inline(always)]
+    pub fn iter_precompiles(
+        &self,
+    ) -> impl Iterator<Item = (BlockHeight, PrecompileCfg)> + '_ {
+        self.precompiles.iter()
+    }
+
+    #[inline(always)]
+    pub fn precompiles_at(&self, height: BlockHeight) -> PrecompileCfg {
+        self.precompiles
+            .get_le(&height)
+            .map(|(_, cfg)| cfg)
+            .unwrap_or_else(|| DEFAULT_PRECOMPILE_CFG.clone())
+    }
+}
+
+// Blocks that have been committed can not be re-executed by other rules.
+fn check_heights<'a>(
+    heights: impl Iterator<Item = &'a BlockHeight>,
+    committed: Option<BlockHeight>,
+) -> Result<()> {
+    if let Some(committed) = committed {
+        for h in heights {
+            if *h <= committed {
+                return Err(eg!(
+                    "Invalid schedule: height {} has been committed, the last one is {}",
+                    h,
+                    committed
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Default for ForkSchedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
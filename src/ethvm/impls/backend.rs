@@ -4,14 +4,24 @@
 //! Ported from [evm](evm::executor::stack::memory).
 //!
 
+use super::cache::BackendCache;
 use crate::{
-    common::BlockHeight,
-    ethvm::{OvrAccount, OvrVicinity},
+    common::{halt, BlockHeight},
+    ethvm::{vesting::VestingSchedule, OvrAccount, OvrVicinity},
 };
 use evm::backend::{Apply, ApplyBackend, Backend, Basic, Log};
 use primitive_types::{H160, H256, U256};
 use ruc::*;
-use vsdb::{BranchName, MapxDkVs, MapxOrd, MapxVs};
+use sha3::{Digest, Keccak256};
+use vsdb::{BranchName, MapxDkVs, MapxOrd, MapxVs, VersionName};
+
+/// A backend that can serve the code hash without loading the code,
+/// used by `EXTCODEHASH`.
+pub trait CodeHashBackend: Backend {
+    fn code_hash(&self, address: H160) -> H256 {
+        H256::from_slice(Keccak256::digest(&self.code(address)).as_slice())
+    }
+}
 
 // Ovr backend, storing all state values in vsdb.
 #[derive(Clone, Debug)]
@@ -19,14 +29,144 @@ pub struct OvrBackend<'a> {
     pub(crate) branch: BranchName<'a>,
     pub(crate) state: MapxVs<H160, OvrAccount>,
     pub(crate) storages: MapxDkVs<H160, H256, H256>,
+    pub(crate) codes: MapxVs<H256, Vec<u8>>,
+    pub(crate) vesting: MapxVs<H160, VestingSchedule>,
     pub(crate) block_hashes: MapxOrd<BlockHeight, H256>,
     pub(crate) vicinity: OvrVicinity,
+    pub(crate) cache: Option<&'a BackendCache>,
+    // Read the state at this version of the branch, instead of its head.
+    pub(crate) version: Option<VersionName<'a>>,
+    // The fee limit of the transaction in process, charged after the execution.
+    pub(crate) fee_reserved: Option<(H160, U256)>,
 }
 
 impl<'a> OvrBackend<'a> {
+    // All reads and writes will go through the cache,
+    // the cache must belong to the same branch as this backend.
+    #[inline(always)]
+    pub fn with_cache(mut self, cache: &'a BackendCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    // A read-only backend of a historical version,
+    // no branch or version will be created for this.
+    #[inline(always)]
+    pub fn at_version(mut self, version: VersionName<'a>) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    // The evm can not spend the fee limit of the caller,
+    // so the fee can always be charged in full after the execution.
+    #[inline(always)]
+    pub(crate) fn with_fee_reserved(mut self, caller: H160, fee_limit: U256) -> Self {
+        self.fee_reserved = Some((caller, fee_limit));
+        self
+    }
+
+    #[inline(always)]
+    fn account(&self, address: H160) -> Option<OvrAccount> {
+        let load = || match self.version {
+            Some(v) => self.state.get_by_branch_version(&address, self.branch, v),
+            None => self.state.get_by_branch(&address, self.branch),
+        };
+        match self.cache {
+            Some(c) => c.account(address, load),
+            None => load(),
+        }
+    }
+
+    /// The balance with the locked vesting part,
+    /// `basic` gives the spendable one.
+    #[inline(always)]
+    pub fn total_balance(&self, address: H160) -> U256 {
+        self.account(address).map(|a| a.balance).unwrap_or_default()
+    }
+
+    // The vesting part that can not be spent in the current block,
+    // plus the reserved fee.
+    #[inline(always)]
+    fn locked(&self, address: H160) -> U256 {
+        let reserved = self
+            .fee_reserved
+            .filter(|(caller, _)| *caller == address)
+            .map(|(_, fee_limit)| fee_limit)
+            .unwrap_or_default();
+
+        let now = self
+            .vicinity
+            .block_timestamp
+            .min(U256::from(u64::MAX))
+            .as_u64();
+        match self.version {
+            Some(v) => self.vesting.get_by_branch_version(&address, self.branch, v),
+            None => self.vesting.get_by_branch(&address, self.branch),
+        }
+        .map(|v| v.locked(now))
+        .unwrap_or_default()
+        .saturating_add(reserved)
+    }
+
+    #[inline(always)]
+    fn set_account(&self, address: H160, account: OvrAccount) {
+        if let Some(c) = self.cache {
+            c.set_account(address, Some(account.clone()));
+        }
+        halt!(self.state.insert_by_branch(address, account, self.branch));
+    }
+
     #[inline(always)]
-    fn reset_storage(&self, target: H160, b: BranchName) {
-        pnk!(self.storages.remove_by_branch(&(&target, None), b));
+    fn remove_account(&self, address: H160) {
+        if let Some(c) = self.cache {
+            c.set_account(address, None);
+        }
+        halt!(self.state.remove_by_branch(&address, self.branch));
+    }
+
+    // Store the code by its hash, return the hash,
+    // the hash of an empty code is all zero.
+    #[inline(always)]
+    fn set_code(&self, code: Vec<u8>) -> H256 {
+        if code.is_empty() {
+            return H256::zero();
+        }
+        let hash = H256::from_slice(Keccak256::digest(&code).as_slice());
+        if !self.codes.contains_key_by_branch(&hash, self.branch) {
+            if let Some(c) = self.cache {
+                c.set_code(hash, code.clone());
+            }
+            halt!(self.codes.insert_by_branch(hash, code, self.branch));
+        }
+        hash
+    }
+
+    // Remove all storage slots of the target account.
+    #[inline(always)]
+    fn reset_storage(&self, target: H160) {
+        if let Some(c) = self.cache {
+            c.reset_storage(target);
+        }
+        halt!(self
+            .storages
+            .remove_by_branch(&(&target, None), self.branch));
+    }
+
+    // Writing a zero value means deleting the slot.
+    #[inline(always)]
+    fn set_storage(&self, target: H160, index: H256, value: H256) {
+        if let Some(c) = self.cache {
+            c.set_storage(target, index, value);
+        }
+        if value == H256::zero() {
+            halt!(self
+                .storages
+                .remove_by_branch(&(&target, Some(&index)), self.branch));
+        } else {
+            halt!(self
+                .storages
+                .insert_by_branch((target, index), value, self.branch));
+        }
     }
 }
 
@@ -43,6 +183,10 @@ impl<'a> Backend for OvrBackend<'a> {
 
     #[inline(always)]
     fn block_hash(&self, number: U256) -> H256 {
+        // `BLOCKHASH` takes any word from the contract
+        if number > U256::from(u64::MAX) {
+            return H256::zero();
+        }
         self.block_hashes.get(&number.as_u64()).unwrap_or_default()
     }
 
@@ -83,15 +227,14 @@ impl<'a> Backend for OvrBackend<'a> {
 
     #[inline(always)]
     fn exists(&self, address: H160) -> bool {
-        self.state.contains_key_by_branch(&address, self.branch)
+        self.account(address).is_some()
     }
 
     #[inline(always)]
     fn basic(&self, address: H160) -> Basic {
-        self.state
-            .get_by_branch(&address, self.branch)
+        self.account(address)
             .map(|a| Basic {
-                balance: a.balance,
+                balance: a.balance.saturating_sub(self.locked(address)),
                 nonce: a.nonce,
             })
             .unwrap_or_default()
@@ -99,17 +242,40 @@ impl<'a> Backend for OvrBackend<'a> {
 
     #[inline(always)]
     fn code(&self, address: H160) -> Vec<u8> {
-        self.state
-            .get_by_branch(&address, self.branch)
-            .map(|v| v.code)
-            .unwrap_or_default()
+        let hash = match self.account(address) {
+            Some(a) if H256::zero() != a.code_hash => a.code_hash,
+            _ => return vec![],
+        };
+        let load = || match self.version {
+            Some(v) => self.codes.get_by_branch_version(&hash, self.branch, v),
+            None => self.codes.get_by_branch(&hash, self.branch),
+        };
+        match self.cache {
+            Some(c) => c.code(hash, load),
+            None => load(),
+        }
+        .unwrap_or_default()
     }
 
     #[inline(always)]
     fn storage(&self, address: H160, index: H256) -> H256 {
-        self.storages
-            .get_by_branch(&(&address, &index), self.branch)
+        let load = || {
+            match self.version {
+                Some(v) => self.storages.get_by_branch_version(
+                    &(&address, &index),
+                    self.branch,
+                    v,
+                ),
+                None => self
+                    .storages
+                    .get_by_branch(&(&address, &index), self.branch),
+            }
             .unwrap_or_default()
+        };
+        match self.cache {
+            Some(c) => c.storage(address, index, load),
+            None => load(),
+        }
     }
 
     #[inline(always)]
@@ -118,6 +284,17 @@ impl<'a> Backend for OvrBackend<'a> {
     }
 }
 
+impl<'a> CodeHashBackend for OvrBackend<'a> {
+    // The hash of an empty code is stored as all zero.
+    #[inline(always)]
+    fn code_hash(&self, address: H160) -> H256 {
+        match self.account(address) {
+            Some(a) if H256::zero() != a.code_hash => a.code_hash,
+            _ => H256::from_slice(Keccak256::digest(&[]).as_slice()),
+        }
+    }
+}
+
 impl<'a> ApplyBackend for OvrBackend<'a> {
     fn apply<A, I, L>(&mut self, values: A, logs: L, delete_empty: bool)
     where
@@ -125,6 +302,11 @@ impl<'a> ApplyBackend for OvrBackend<'a> {
         I: IntoIterator<Item = (H256, H256)>,
         L: IntoIterator<Item = Log>,
     {
+        // historical versions are read-only
+        if self.version.is_some() {
+            return;
+        }
+
         for apply in values {
             match apply {
                 Apply::Modify {
@@ -135,43 +317,40 @@ impl<'a> ApplyBackend for OvrBackend<'a> {
                     reset_storage,
                 } => {
                     let is_empty = {
-                        let mut account = self
-                            .state
-                            .get_by_branch(&address, self.branch)
-                            .unwrap_or_default();
-                        account.balance = basic.balance;
+                        let mut account = self.account(address).unwrap_or_default();
+                        // the locked part is hidden by `basic`
+                        account.balance =
+                            basic.balance.saturating_add(self.locked(address));
                         account.nonce = basic.nonce;
                         if let Some(code) = code {
-                            account.code = code;
+                            account.code_hash = self.set_code(code);
                         }
 
                         if reset_storage {
-                            self.reset_storage(address, self.branch);
+                            self.reset_storage(address);
                         }
 
                         for (index, value) in storage {
-                            if value != H256::default() {
-                                self.storages.insert((address, index), value).unwrap();
-                            }
+                            self.set_storage(address, index, value);
                         }
 
                         let ret = account.balance == U256::zero()
                             && account.nonce == U256::zero()
-                            && account.code.is_empty();
+                            && H256::zero() == account.code_hash;
 
-                        self.state
-                            .insert_by_branch(address, account, self.branch)
-                            .unwrap();
+                        self.set_account(address, account);
 
                         ret
                     };
 
                     if is_empty && delete_empty {
-                        self.state.remove_by_branch(&address, self.branch).unwrap();
+                        self.remove_account(address);
                     }
                 }
+                // SELFDESTRUCT
                 Apply::Delete { address } => {
-                    self.state.remove_by_branch(&address, self.branch).unwrap();
+                    self.reset_storage(address);
+                    self.remove_account(address);
                 }
             }
         }
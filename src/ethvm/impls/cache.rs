@@ -0,0 +1,231 @@
+//!
+//! # Read cache for the evm backend
+//!
+//! Scoped to one branch and one block,
+//! the owner must clear it whenever the underlying branch
+//! is changed without going through the cache.
+//!
+//! A recording cache also keeps the read keys and the ordered writes,
+//! which are used by the parallel executor of the ledger.
+//!
+
+use crate::ethvm::{tx::token::Erc20Like, OvrAccount};
+use parking_lot::Mutex;
+use primitive_types::{H160, H256};
+use ruc::*;
+use std::collections::{HashMap, HashSet};
+use vsdb::BranchName;
+
+#[derive(Debug, Default)]
+pub struct BackendCache {
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    // `None` means the account is known to be absent.
+    accounts: HashMap<H160, Option<OvrAccount>>,
+    storages: HashMap<H160, HashMap<H256, H256>>,
+    // Contents are addressed by their hashes, never stale.
+    codes: HashMap<H256, Vec<u8>>,
+    // Survives `clear`, only taken by `take_record`.
+    record: Option<AccessRecord>,
+}
+
+impl CacheInner {
+    #[inline(always)]
+    fn read(&mut self, key: StateKey) {
+        if let Some(r) = self.record.as_mut() {
+            r.reads.insert(key);
+        }
+    }
+
+    #[inline(always)]
+    fn write(&mut self, w: StateWrite) {
+        if let Some(r) = self.record.as_mut() {
+            r.writes.push(w);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum StateKey {
+    Account(H160),
+    Storage(H160, H256),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum StateWrite {
+    Account(H160, Option<OvrAccount>),
+    Storage(H160, H256, H256),
+    ResetStorage(H160),
+    Code(H256, Vec<u8>),
+}
+
+impl StateWrite {
+    // Replay on the given branch,
+    // exactly as the backend did when it was recorded.
+    pub(crate) fn apply(self, ofuel: &Erc20Like, b: BranchName) -> Result<()> {
+        match self {
+            StateWrite::Account(address, Some(account)) => {
+                ofuel
+                    .accounts
+                    .insert_by_branch(address, account, b)
+                    .c(d!())?;
+            }
+            StateWrite::Account(address, None) => {
+                ofuel.accounts.remove_by_branch(&address, b).c(d!())?;
+            }
+            StateWrite::Storage(address, index, value) => {
+                if value == H256::zero() {
+                    ofuel
+                        .storages
+                        .remove_by_branch(&(&address, Some(&index)), b)
+                        .c(d!())?;
+                } else {
+                    ofuel
+                        .storages
+                        .insert_by_branch((address, index), value, b)
+                        .c(d!())?;
+                }
+            }
+            StateWrite::ResetStorage(address) => {
+                ofuel
+                    .storages
+                    .remove_by_branch(&(&address, None), b)
+                    .c(d!())?;
+            }
+            StateWrite::Code(hash, code) => {
+                if !ofuel.codes.contains_key_by_branch(&hash, b) {
+                    ofuel.codes.insert_by_branch(hash, code, b).c(d!())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AccessRecord {
+    pub(crate) reads: HashSet<StateKey>,
+    pub(crate) writes: Vec<StateWrite>,
+}
+
+// A cloned branch never shares the cached entries.
+impl Clone for BackendCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl BackendCache {
+    pub(crate) fn recording() -> Self {
+        let cache = Self::default();
+        cache.inner.lock().record = Some(AccessRecord::default());
+        cache
+    }
+
+    #[inline(always)]
+    pub(crate) fn take_record(&self) -> AccessRecord {
+        self.inner.lock().record.take().unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        let record = inner.record.take();
+        *inner = CacheInner {
+            record,
+            ..Default::default()
+        };
+    }
+
+    pub(crate) fn account(
+        &self,
+        address: H160,
+        load: impl FnOnce() -> Option<OvrAccount>,
+    ) -> Option<OvrAccount> {
+        {
+            let mut inner = self.inner.lock();
+            inner.read(StateKey::Account(address));
+            if let Some(a) = inner.accounts.get(&address) {
+                return a.clone();
+            }
+        }
+        let a = load();
+        self.inner.lock().accounts.insert(address, a.clone());
+        a
+    }
+
+    #[inline(always)]
+    pub(crate) fn set_account(&self, address: H160, account: Option<OvrAccount>) {
+        let mut inner = self.inner.lock();
+        inner.write(StateWrite::Account(address, account.clone()));
+        inner.accounts.insert(address, account);
+    }
+
+    pub(crate) fn storage(
+        &self,
+        address: H160,
+        index: H256,
+        load: impl FnOnce() -> H256,
+    ) -> H256 {
+        {
+            let mut inner = self.inner.lock();
+            inner.read(StateKey::Storage(address, index));
+            if let Some(v) = inner.storages.get(&address).and_then(|s| s.get(&index)) {
+                return *v;
+            }
+        }
+        let v = load();
+        self.inner
+            .lock()
+            .storages
+            .entry(address)
+            .or_default()
+            .insert(index, v);
+        v
+    }
+
+    #[inline(always)]
+    pub(crate) fn set_storage(&self, address: H160, index: H256, value: H256) {
+        let mut inner = self.inner.lock();
+        inner.write(StateWrite::Storage(address, index, value));
+        inner
+            .storages
+            .entry(address)
+            .or_default()
+            .insert(index, value);
+    }
+
+    // Forget all cached slots of the target account,
+    // they will be re-loaded from the wiped storage.
+    #[inline(always)]
+    pub(crate) fn reset_storage(&self, address: H160) {
+        let mut inner = self.inner.lock();
+        inner.write(StateWrite::ResetStorage(address));
+        inner.storages.remove(&address);
+    }
+
+    #[inline(always)]
+    pub(crate) fn set_code(&self, hash: H256, code: Vec<u8>) {
+        let mut inner = self.inner.lock();
+        inner.write(StateWrite::Code(hash, code.clone()));
+        inner.codes.insert(hash, code);
+    }
+
+    pub(crate) fn code(
+        &self,
+        hash: H256,
+        load: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        if let Some(c) = self.inner.lock().codes.get(&hash) {
+            return Some(c.clone());
+        }
+        let c = load();
+        if let Some(c) = c.as_ref() {
+            self.inner.lock().codes.insert(hash, c.clone());
+        }
+        c
+    }
+}
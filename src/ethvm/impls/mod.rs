@@ -1,2 +1,3 @@
 pub mod backend;
-pub(crate) mod stack;
+pub mod cache;
+pub mod stack;
@@ -4,13 +4,17 @@
 //! Ported from [evm](evm::executor::stack::memory).
 //!
 
+use super::backend::CodeHashBackend;
+use crate::ethvm::precompile::staking::Journal;
 use evm::{
     backend::{Apply, Backend, Basic, Log},
     executor::stack::{Accessed, StackState, StackSubstateMetadata},
     ExitError, Transfer,
 };
 use primitive_types::{H160, H256, U256};
+use sha3::{Digest, Keccak256};
 use std::{
+    cell::RefCell,
     collections::{BTreeMap, BTreeSet},
     mem,
 };
@@ -376,31 +380,33 @@ impl<'config> OvrStackSubstate<'config> {
         Ok(())
     }
 
-    // // Only needed for jsontests.
-    // pub(crate) fn withdraw<B: Backend>(
-    //     &mut self,
-    //     address: H160,
-    //     value: U256,
-    //     backend: &B,
-    // ) -> Result<(), ExitError> {
-    //     let source = self.account_mut(address, backend);
-    //     if source.basic.balance < value {
-    //         return Err(ExitError::OutOfFund);
-    //     }
-    //     source.basic.balance -= value;
-    //     Ok(())
-    // }
+    // Only needed for jsontests.
+    #[cfg(feature = "jsontests")]
+    pub(crate) fn withdraw<B: Backend>(
+        &mut self,
+        address: H160,
+        value: U256,
+        backend: &B,
+    ) -> Result<(), ExitError> {
+        let source = self.account_mut(address, backend);
+        if source.basic.balance < value {
+            return Err(ExitError::OutOfFund);
+        }
+        source.basic.balance -= value;
+        Ok(())
+    }
 
-    // // Only needed for jsontests.
-    // pub(crate) fn deposit<B: Backend>(
-    //     &mut self,
-    //     address: H160,
-    //     value: U256,
-    //     backend: &B,
-    // ) {
-    //     let target = self.account_mut(address, backend);
-    //     target.basic.balance = target.basic.balance.saturating_add(value);
-    // }
+    // Only needed for jsontests.
+    #[cfg(feature = "jsontests")]
+    pub(crate) fn deposit<B: Backend>(
+        &mut self,
+        address: H160,
+        value: U256,
+        backend: &B,
+    ) {
+        let target = self.account_mut(address, backend);
+        target.basic.balance = target.basic.balance.saturating_add(value);
+    }
 
     #[inline(always)]
     pub(crate) fn reset_balance<B: Backend>(&mut self, address: H160, backend: &B) {
@@ -414,12 +420,19 @@ impl<'config> OvrStackSubstate<'config> {
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct OvrStackState<'backend, 'config, B> {
+pub struct OvrStackState<'backend, 'config, B> {
     backend: &'backend B,
     substate: OvrStackSubstate<'config>,
+    // Values of the storage slots at the start of the transaction,
+    // recorded at the first access, used by the EIP-2200/EIP-3529 gas metering.
+    originals: RefCell<BTreeMap<(H160, H256), H256>>,
+    // Operations of the staking precompile, entered and exited with the substates.
+    staking: Option<Journal>,
 }
 
-impl<'backend, 'config, B: Backend> Backend for OvrStackState<'backend, 'config, B> {
+impl<'backend, 'config, B: CodeHashBackend> Backend
+    for OvrStackState<'backend, 'config, B>
+{
     #[inline(always)]
     fn gas_price(&self) -> U256 {
         self.backend.gas_price()
@@ -493,7 +506,7 @@ impl<'backend, 'config, B: Backend> Backend for OvrStackState<'backend, 'config,
     fn storage(&self, address: H160, key: H256) -> H256 {
         self.substate
             .known_storage(address, key)
-            .unwrap_or_else(|| self.backend.storage(address, key))
+            .unwrap_or_else(|| self.original(address, key))
     }
 
     #[inline(always)]
@@ -501,11 +514,11 @@ impl<'backend, 'config, B: Backend> Backend for OvrStackState<'backend, 'config,
         if let Some(value) = self.substate.known_original_storage(address, key) {
             return Some(value);
         }
-        self.backend.original_storage(address, key)
+        Some(self.original(address, key))
     }
 }
 
-impl<'backend, 'config, B: Backend> StackState<'config>
+impl<'backend, 'config, B: CodeHashBackend> StackState<'config>
     for OvrStackState<'backend, 'config, B>
 {
     #[inline(always)]
@@ -520,21 +533,36 @@ impl<'backend, 'config, B: Backend> StackState<'config>
 
     #[inline(always)]
     fn enter(&mut self, gas_limit: u64, is_static: bool) {
-        self.substate.enter(gas_limit, is_static)
+        self.substate.enter(gas_limit, is_static);
+        self.staking.iter().for_each(|j| j.enter());
     }
 
-    #[inline(always)]
+    // The payouts of the staking precompile are made in the frame of its call,
+    // so they are committed or reverted together with the frame.
     fn exit_commit(&mut self) -> Result<(), ExitError> {
+        if let Some(j) = self.staking.clone() {
+            for transfer in j.take_payouts() {
+                if let Err(e) = self.substate.transfer(transfer, self.backend) {
+                    // the staking pool does not back the delegations and rewards
+                    j.fail();
+                    self.exit_revert()?;
+                    return Err(e);
+                }
+            }
+            j.exit_commit();
+        }
         self.substate.exit_commit()
     }
 
     #[inline(always)]
     fn exit_revert(&mut self) -> Result<(), ExitError> {
+        self.staking.iter().for_each(|j| j.exit_revert());
         self.substate.exit_revert()
     }
 
     #[inline(always)]
     fn exit_discard(&mut self) -> Result<(), ExitError> {
+        self.staking.iter().for_each(|j| j.exit_revert());
         self.substate.exit_discard()
     }
 
@@ -607,35 +635,62 @@ impl<'backend, 'config, B: Backend> StackState<'config>
     fn touch(&mut self, address: H160) {
         self.substate.touch(address, self.backend)
     }
+
+    // `EXTCODEHASH`, the stored hash is used unless the code
+    // has been changed in this transaction.
+    #[inline(always)]
+    fn code_hash(&self, address: H160) -> H256 {
+        self.substate
+            .known_code(address)
+            .map(|code| H256::from_slice(Keccak256::digest(&code).as_slice()))
+            .unwrap_or_else(|| self.backend.code_hash(address))
+    }
 }
 
-impl<'backend, 'config, B: Backend> OvrStackState<'backend, 'config, B> {
+impl<'backend, 'config, B: CodeHashBackend> OvrStackState<'backend, 'config, B> {
     #[inline(always)]
-    pub(crate) fn new(
-        metadata: StackSubstateMetadata<'config>,
-        backend: &'backend B,
-    ) -> Self {
+    pub fn new(metadata: StackSubstateMetadata<'config>, backend: &'backend B) -> Self {
         Self {
             backend,
             substate: OvrStackSubstate::new(metadata),
+            originals: RefCell::new(BTreeMap::new()),
+            staking: None,
         }
     }
 
+    #[inline(always)]
+    pub(crate) fn with_staking(mut self, journal: Option<Journal>) -> Self {
+        self.staking = journal;
+        self
+    }
+
+    // NOTE:
+    // The backend is not changed until the end of the transaction,
+    // the recording makes this independent of the backend implementation.
+    #[inline(always)]
+    fn original(&self, address: H160, key: H256) -> H256 {
+        *self
+            .originals
+            .borrow_mut()
+            .entry((address, key))
+            .or_insert_with(|| self.backend.storage(address, key))
+    }
+
     #[must_use]
     #[inline(always)]
-    pub(crate) fn deconstruct(self) -> (Vec<Apply<BTreeMap<H256, H256>>>, Vec<Log>) {
+    pub fn deconstruct(self) -> (Vec<Apply<BTreeMap<H256, H256>>>, Vec<Log>) {
         self.substate.deconstruct(self.backend)
     }
 
-    // pub(crate) fn withdraw(
-    //     &mut self,
-    //     address: H160,
-    //     value: U256,
-    // ) -> Result<(), ExitError> {
-    //     self.substate.withdraw(address, value, self.backend)
-    // }
+    // Only needed for jsontests.
+    #[cfg(feature = "jsontests")]
+    pub fn withdraw(&mut self, address: H160, value: U256) -> Result<(), ExitError> {
+        self.substate.withdraw(address, value, self.backend)
+    }
 
-    // pub(crate) fn deposit(&mut self, address: H160, value: U256) {
-    //     self.substate.deposit(address, value, self.backend)
-    // }
+    // Only needed for jsontests.
+    #[cfg(feature = "jsontests")]
+    pub fn deposit(&mut self, address: H160, value: U256) {
+        self.substate.deposit(address, value, self.backend)
+    }
 }
@@ -1,22 +1,26 @@
+pub mod fork;
 pub mod impls;
-mod precompile;
+pub mod precompile;
 pub mod tx;
+pub mod vesting;
 
 use crate::{
-    common::{block_number_to_height, rollback_to_height, BlockHeight},
-    ethvm::{impls::stack::OvrStackState, precompile::PRECOMPILE_SET},
+    common::BlockHeight,
+    ethvm::{impls::stack::OvrStackState, precompile::OvrPrecompileSet},
+    ledger::{staking::State as StakingState, ConsensusInfo},
 };
 use evm::{
     executor::stack::{StackExecutor, StackSubstateMetadata},
     ExitReason,
 };
+use fork::ForkSchedule;
 use impls::backend::OvrBackend;
 use primitive_types::{H160, H256, U256};
 use ruc::*;
 use serde::{Deserialize, Serialize};
 use tx::token::Erc20Like;
-use vsdb::{BranchName, MapxOrd, OrphanVs, Vs, VsMgmt};
-use web3_rpc_core::types::{BlockNumber, CallRequest};
+use vsdb::{BranchName, MapxOrd, OrphanVs, VersionName, Vs};
+use web3_rpc_core::types::CallRequest;
 
 #[allow(non_snake_case)]
 #[derive(Vs, Clone, Debug, Deserialize, Serialize)]
@@ -27,6 +31,9 @@ pub struct State {
 
     pub OFUEL: Erc20Like,
 
+    // Hard forks activated by block height.
+    pub fork_schedule: ForkSchedule,
+
     // Environmental block hashes.
     pub block_hashes: MapxOrd<BlockHeight, H256>,
 
@@ -37,41 +44,41 @@ pub struct State {
 impl State {
     pub fn call_contract(
         &self,
-        branch_name: BranchName,
+        staking: &StakingState,
+        consensus: &ConsensusInfo,
+        params_admin: Option<H160>,
+        backend: &OvrBackend,
+        height: BlockHeight,
         req: CallRequest,
-        bn: Option<BlockNumber>,
     ) -> Result<CallContractResp> {
         let caller = req.from.unwrap_or_default();
         let address = req.to.unwrap_or_default();
         let value = req.value.unwrap_or_default();
         let data = req.data.unwrap_or_default();
         // This parameter is used as the divisor and cannot be 0
-        let gas_price = req.gas_price.unwrap_or_else(U256::one).as_u64();
-        let gas = if let Some(gas) = req.gas {
-            gas.as_u64()
-        } else {
-            u64::MAX
-        };
-        let gas_limit = gas.checked_div(gas_price).unwrap();
-
-        let height = block_number_to_height(bn, None, Some(self));
-
-        let new_branch_name =
-            rollback_to_height(height, None, Some(self), "call_contract")?;
-
-        let backend = OvrBackend {
-            branch: branch_name,
-            state: self.OFUEL.accounts.clone(),
-            storages: self.OFUEL.storages.clone(),
-            block_hashes: self.block_hashes,
-            vicinity: self.vicinity.clone(),
-        };
-
-        let cfg = evm::Config::istanbul();
-        let metadata = StackSubstateMetadata::new(u64::MAX, &cfg);
-
-        let ovr_stack_state = OvrStackState::new(metadata, &backend);
-        let precompiles = PRECOMPILE_SET.clone();
+        let gas_price = req.gas_price.unwrap_or_else(U256::one).max(U256::one());
+        let gas = req.gas.unwrap_or_else(|| U256::from(u64::MAX));
+        // never more than a transaction can take in a block
+        let gas_limit = (gas / gas_price)
+            .min(backend.vicinity.block_gas_limit)
+            .min(U256::from(u64::MAX))
+            .as_u64();
+
+        let cfg = self.fork_schedule.evm_cfg_at(height);
+        let metadata = StackSubstateMetadata::new(gas_limit, &cfg);
+
+        let mut precompiles = OvrPrecompileSet::new(
+            &self.fork_schedule.precompiles_at(height),
+            Some((staking, backend.branch)),
+            Some(consensus),
+            params_admin,
+        );
+        // the same version as the backend
+        if let Some(v) = backend.version.as_ref() {
+            precompiles = precompiles.at_version(VersionName(v.0));
+        }
+        let ovr_stack_state = OvrStackState::new(metadata, backend)
+            .with_staking(precompiles.staking_journal());
         let mut executor =
             StackExecutor::new_with_precompiles(ovr_stack_state, &cfg, &precompiles);
 
@@ -86,18 +93,22 @@ impl State {
             gas_used: executor.used_gas(),
         };
 
-        self.branch_remove(BranchName::from(new_branch_name.as_str()))?;
         Ok(cc_resp)
     }
 
     #[inline(always)]
-    fn get_backend_hdr<'a>(&self, branch: BranchName<'a>) -> OvrBackend<'a> {
+    pub fn get_backend_hdr<'a>(&self, branch: BranchName<'a>) -> OvrBackend<'a> {
         OvrBackend {
             branch,
             state: self.OFUEL.accounts.clone(),
             storages: self.OFUEL.storages.clone(),
+            codes: self.OFUEL.codes.clone(),
+            vesting: self.OFUEL.vesting.clone(),
             block_hashes: self.block_hashes,
             vicinity: self.vicinity.clone(),
+            cache: None,
+            version: None,
+            fee_reserved: None,
         }
     }
 
@@ -135,6 +146,7 @@ impl Default for State {
             block_gas_limit: OrphanVs::default(),
             block_base_fee_per_gas: OrphanVs::default(),
             OFUEL: Erc20Like::ofuel_token(),
+            fork_schedule: ForkSchedule::new(),
             block_hashes: MapxOrd::new(),
             vicinity: OvrVicinity::default(),
         }
@@ -146,7 +158,9 @@ impl Default for State {
 pub struct OvrAccount {
     pub nonce: U256,
     pub balance: U256,
-    pub code: Vec<u8>,
+    // keccak256 hash of the code, all zero if there is no code,
+    // the code itself is stored in `Erc20Like.codes`
+    pub code_hash: H256,
 }
 
 impl OvrAccount {
@@ -0,0 +1,113 @@
+//!
+//! # Block-context precompile
+//!
+//! Exposes the consensus data of the current block,
+//! which can NOT be expressed by the standard evm environment.
+//!
+//! Solidity interface:
+//!
+//! ```solidity
+//! interface BlockContext {
+//!     function proposer() external view returns (bytes memory);
+//!     function validatorsHash() external view returns (bytes32);
+//!     function blockTimeNanos() external view returns (uint256);
+//!     function blockHash() external view returns (bytes32);
+//!     function lastCommitPower() external view returns (uint256 signed, uint256 total);
+//! }
+//! ```
+//!
+
+use super::staking::{encode_u256, output, revert, selector};
+use crate::ledger::ConsensusInfo;
+use evm::{
+    executor::stack::{PrecompileFailure, PrecompileOutput},
+    Context, ExitError,
+};
+use once_cell::sync::Lazy;
+use primitive_types::{H160, U256};
+use std::result::Result as StdResult;
+
+const GAS_QUERY: u64 = 200;
+
+static SEL_PROPOSER: Lazy<[u8; 4]> = Lazy::new(|| selector("proposer()"));
+static SEL_VALIDATORS_HASH: Lazy<[u8; 4]> = Lazy::new(|| selector("validatorsHash()"));
+static SEL_BLOCK_TIME_NANOS: Lazy<[u8; 4]> = Lazy::new(|| selector("blockTimeNanos()"));
+static SEL_BLOCK_HASH: Lazy<[u8; 4]> = Lazy::new(|| selector("blockHash()"));
+static SEL_LAST_COMMIT_POWER: Lazy<[u8; 4]> =
+    Lazy::new(|| selector("lastCommitPower()"));
+
+type PrecompileResult = StdResult<PrecompileOutput, PrecompileFailure>;
+
+#[derive(Clone, Debug)]
+pub(crate) struct BlockCtxPrecompile {
+    address: H160,
+    info: ConsensusInfo,
+}
+
+impl BlockCtxPrecompile {
+    #[inline(always)]
+    pub(crate) fn new(address: H160, info: ConsensusInfo) -> Self {
+        Self { address, info }
+    }
+
+    #[inline(always)]
+    pub(crate) fn address(&self) -> H160 {
+        self.address
+    }
+
+    pub(crate) fn execute(
+        &self,
+        input: &[u8],
+        gas_limit: Option<u64>,
+        context: &Context,
+    ) -> PrecompileResult {
+        if gas_limit.map(|l| l < GAS_QUERY).unwrap_or(false) {
+            return Err(PrecompileFailure::Error {
+                exit_status: ExitError::OutOfGas,
+            });
+        }
+        if !context.apparent_value.is_zero() {
+            return Err(revert("function is not payable", GAS_QUERY));
+        }
+        if input.len() < 4 {
+            return Err(revert("invalid selector", 0));
+        }
+
+        let sel = &input[..4];
+        let ret = if sel == *SEL_PROPOSER {
+            encode_bytes(&self.info.proposer)
+        } else if sel == *SEL_VALIDATORS_HASH {
+            encode_bytes32(&self.info.validators_hash)
+        } else if sel == *SEL_BLOCK_TIME_NANOS {
+            encode_u256(U256::from(self.info.block_time_ns))
+        } else if sel == *SEL_BLOCK_HASH {
+            encode_bytes32(&self.info.block_hash)
+        } else if sel == *SEL_LAST_COMMIT_POWER {
+            let mut ret = encode_u256(U256::from(self.info.last_commit_signed_power));
+            ret.extend(encode_u256(U256::from(self.info.last_commit_total_power)));
+            ret
+        } else {
+            return Err(revert("unknown selector", 0));
+        };
+
+        Ok(output(GAS_QUERY, ret, vec![]))
+    }
+}
+
+// Tendermint hashes are 32 bytes(sha256),
+// pad or truncate to be safe with other hash functions.
+#[inline(always)]
+fn encode_bytes32(v: &[u8]) -> Vec<u8> {
+    let mut ret = v.to_vec();
+    ret.resize(32, 0);
+    ret
+}
+
+// The standard abi encoding of a single dynamic `bytes`.
+fn encode_bytes(v: &[u8]) -> Vec<u8> {
+    let mut ret = encode_u256(U256::from(32u8));
+    ret.extend(encode_u256(U256::from(v.len())));
+    ret.extend_from_slice(v);
+    ret.resize(ret.len() + (32 - v.len() % 32) % 32, 0);
+    ret
+}
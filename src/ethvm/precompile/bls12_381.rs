@@ -0,0 +1,476 @@
+//!
+//! # BLS12-381 precompiles
+//!
+//! Implemented as specified in [EIP-2537](https://eips.ethereum.org/EIPS/eip-2537),
+//! the standard addresses are:
+//!
+//! - `0x0b`: G1ADD
+//! - `0x0c`: G1MSM
+//! - `0x0d`: G2ADD
+//! - `0x0e`: G2MSM
+//! - `0x0f`: PAIRING_CHECK
+//! - `0x10`: MAP_FP_TO_G1
+//! - `0x11`: MAP_FP2_TO_G2
+//!
+//! They are registered but NOT activated by default,
+//! add them to the precompile config at a fork height to enable them.
+//!
+
+use blst::{
+    blst_bendian_from_fp, blst_final_exp, blst_fp, blst_fp12, blst_fp12_is_one,
+    blst_fp12_mul, blst_fp2, blst_fp_from_bendian, blst_map_to_g1, blst_map_to_g2,
+    blst_miller_loop, blst_p1, blst_p1_add_or_double_affine, blst_p1_affine,
+    blst_p1_affine_in_g1, blst_p1_affine_is_inf, blst_p1_affine_on_curve,
+    blst_p1_from_affine, blst_p1_mult, blst_p1_to_affine, blst_p2,
+    blst_p2_add_or_double_affine, blst_p2_affine, blst_p2_affine_in_g2,
+    blst_p2_affine_is_inf, blst_p2_affine_on_curve, blst_p2_from_affine, blst_p2_mult,
+    blst_p2_to_affine, blst_scalar, blst_scalar_from_bendian,
+};
+use evm::{
+    executor::stack::{PrecompileFailure, PrecompileOutput},
+    Context, ExitError, ExitSucceed,
+};
+use fevm::Precompile;
+
+type PrecompileResult = Result<PrecompileOutput, PrecompileFailure>;
+
+const FP_LEN: usize = 48;
+const PADDED_FP_LEN: usize = 64;
+const PADDED_FP2_LEN: usize = 2 * PADDED_FP_LEN;
+const G1_LEN: usize = 2 * PADDED_FP_LEN;
+const G2_LEN: usize = 2 * PADDED_FP2_LEN;
+const SCALAR_LEN: usize = 32;
+
+const G1_ADD_GAS: u64 = 375;
+const G1_MUL_GAS: u64 = 12000;
+const G2_ADD_GAS: u64 = 600;
+const G2_MUL_GAS: u64 = 22500;
+const PAIRING_BASE_GAS: u64 = 37700;
+const PAIRING_PER_PAIR_GAS: u64 = 32600;
+const MAP_FP_TO_G1_GAS: u64 = 5500;
+const MAP_FP2_TO_G2_GAS: u64 = 23800;
+
+const MSM_MULTIPLIER: u64 = 1000;
+
+// Discounts for k = 1..=128, k > 128 uses the last one.
+const G1_MSM_DISCOUNT: [u16; 128] = [
+    1000, 949, 848, 797, 764, 750, 738, 728, 719, 712, 705, 698, 692, 687, 682, 677,
+    673, 669, 665, 661, 658, 654, 651, 648, 645, 642, 640, 637, 635, 632, 630, 627, 625,
+    623, 621, 619, 617, 615, 613, 611, 609, 608, 606, 604, 603, 601, 599, 598, 596, 595,
+    593, 592, 591, 589, 588, 586, 585, 584, 582, 581, 580, 579, 577, 576, 575, 574, 573,
+    572, 570, 569, 568, 567, 566, 565, 564, 563, 562, 561, 560, 559, 558, 557, 556, 555,
+    554, 553, 552, 551, 550, 549, 548, 547, 547, 546, 545, 544, 543, 542, 541, 540, 540,
+    539, 538, 537, 536, 536, 535, 534, 533, 532, 532, 531, 530, 529, 528, 528, 527, 526,
+    525, 525, 524, 523, 522, 522, 521, 520, 520, 519,
+];
+
+const G2_MSM_DISCOUNT: [u16; 128] = [
+    1000, 1000, 923, 884, 855, 832, 812, 796, 782, 770, 759, 749, 740, 732, 724, 717,
+    711, 704, 699, 693, 688, 683, 679, 674, 670, 666, 663, 659, 655, 652, 649, 646, 643,
+    640, 637, 634, 632, 629, 627, 624, 622, 620, 618, 615, 613, 611, 609, 607, 606, 604,
+    602, 600, 598, 597, 595, 593, 592, 590, 589, 587, 586, 584, 583, 582, 580, 579, 578,
+    576, 575, 574, 573, 571, 570, 569, 568, 567, 566, 565, 563, 562, 561, 560, 559, 558,
+    557, 556, 555, 554, 553, 552, 552, 551, 550, 549, 548, 547, 546, 545, 545, 544, 543,
+    542, 541, 541, 540, 539, 538, 537, 537, 536, 535, 535, 534, 533, 532, 532, 531, 530,
+    530, 529, 528, 528, 527, 526, 526, 525, 524, 524,
+];
+
+pub struct Bls12G1Add;
+
+impl Precompile for Bls12G1Add {
+    fn execute(
+        input: &[u8],
+        target_gas: Option<u64>,
+        _: &Context,
+        _: bool,
+    ) -> PrecompileResult {
+        charge(G1_ADD_GAS, target_gas)?;
+        if input.len() != 2 * G1_LEN {
+            return Err(failure("invalid input length"));
+        }
+
+        // NOTE: no subgroup checks for additions
+        let a = decode_g1(&input[..G1_LEN], false)?;
+        let b = decode_g1(&input[G1_LEN..], false)?;
+
+        let mut p = blst_p1::default();
+        unsafe {
+            blst_p1_from_affine(&mut p, &a);
+            blst_p1_add_or_double_affine(&mut p, &p, &b);
+        }
+
+        Ok(output(G1_ADD_GAS, encode_g1(&p)))
+    }
+}
+
+pub struct Bls12G1Msm;
+
+impl Precompile for Bls12G1Msm {
+    fn execute(
+        input: &[u8],
+        target_gas: Option<u64>,
+        _: &Context,
+        _: bool,
+    ) -> PrecompileResult {
+        const PAIR_LEN: usize = G1_LEN + SCALAR_LEN;
+
+        let k = input.len() / PAIR_LEN;
+        if 0 == k || 0 != input.len() % PAIR_LEN {
+            return Err(failure("invalid input length"));
+        }
+        let cost = msm_gas(k, G1_MUL_GAS, &G1_MSM_DISCOUNT);
+        charge(cost, target_gas)?;
+
+        let mut acc = blst_p1::default();
+        for pair in input.chunks_exact(PAIR_LEN) {
+            let p = decode_g1(&pair[..G1_LEN], true)?;
+            let scalar = decode_scalar(&pair[G1_LEN..]);
+
+            let mut p_mul = blst_p1::default();
+            let mut p_mul_affine = blst_p1_affine::default();
+            unsafe {
+                if blst_p1_affine_is_inf(&p) {
+                    continue;
+                }
+                blst_p1_from_affine(&mut p_mul, &p);
+                blst_p1_mult(&mut p_mul, &p_mul, scalar.b.as_ptr(), 8 * SCALAR_LEN);
+                blst_p1_to_affine(&mut p_mul_affine, &p_mul);
+                blst_p1_add_or_double_affine(&mut acc, &acc, &p_mul_affine);
+            }
+        }
+
+        Ok(output(cost, encode_g1(&acc)))
+    }
+}
+
+pub struct Bls12G2Add;
+
+impl Precompile for Bls12G2Add {
+    fn execute(
+        input: &[u8],
+        target_gas: Option<u64>,
+        _: &Context,
+        _: bool,
+    ) -> PrecompileResult {
+        charge(G2_ADD_GAS, target_gas)?;
+        if input.len() != 2 * G2_LEN {
+            return Err(failure("invalid input length"));
+        }
+
+        // NOTE: no subgroup checks for additions
+        let a = decode_g2(&input[..G2_LEN], false)?;
+        let b = decode_g2(&input[G2_LEN..], false)?;
+
+        let mut p = blst_p2::default();
+        unsafe {
+            blst_p2_from_affine(&mut p, &a);
+            blst_p2_add_or_double_affine(&mut p, &p, &b);
+        }
+
+        Ok(output(G2_ADD_GAS, encode_g2(&p)))
+    }
+}
+
+pub struct Bls12G2Msm;
+
+impl Precompile for Bls12G2Msm {
+    fn execute(
+        input: &[u8],
+        target_gas: Option<u64>,
+        _: &Context,
+        _: bool,
+    ) -> PrecompileResult {
+        const PAIR_LEN: usize = G2_LEN + SCALAR_LEN;
+
+        let k = input.len() / PAIR_LEN;
+        if 0 == k || 0 != input.len() % PAIR_LEN {
+            return Err(failure("invalid input length"));
+        }
+        let cost = msm_gas(k, G2_MUL_GAS, &G2_MSM_DISCOUNT);
+        charge(cost, target_gas)?;
+
+        let mut acc = blst_p2::default();
+        for pair in input.chunks_exact(PAIR_LEN) {
+            let p = decode_g2(&pair[..G2_LEN], true)?;
+            let scalar = decode_scalar(&pair[G2_LEN..]);
+
+            let mut p_mul = blst_p2::default();
+            let mut p_mul_affine = blst_p2_affine::default();
+            unsafe {
+                if blst_p2_affine_is_inf(&p) {
+                    continue;
+                }
+                blst_p2_from_affine(&mut p_mul, &p);
+                blst_p2_mult(&mut p_mul, &p_mul, scalar.b.as_ptr(), 8 * SCALAR_LEN);
+                blst_p2_to_affine(&mut p_mul_affine, &p_mul);
+                blst_p2_add_or_double_affine(&mut acc, &acc, &p_mul_affine);
+            }
+        }
+
+        Ok(output(cost, encode_g2(&acc)))
+    }
+}
+
+pub struct Bls12Pairing;
+
+impl Precompile for Bls12Pairing {
+    fn execute(
+        input: &[u8],
+        target_gas: Option<u64>,
+        _: &Context,
+        _: bool,
+    ) -> PrecompileResult {
+        const PAIR_LEN: usize = G1_LEN + G2_LEN;
+
+        let k = input.len() / PAIR_LEN;
+        if 0 == k || 0 != input.len() % PAIR_LEN {
+            return Err(failure("invalid input length"));
+        }
+        let cost = PAIRING_PER_PAIR_GAS
+            .saturating_mul(k as u64)
+            .saturating_add(PAIRING_BASE_GAS);
+        charge(cost, target_gas)?;
+
+        let mut acc: Option<blst_fp12> = None;
+        for pair in input.chunks_exact(PAIR_LEN) {
+            let p1 = decode_g1(&pair[..G1_LEN], true)?;
+            let p2 = decode_g2(&pair[G1_LEN..], true)?;
+
+            // `e(P, Q) == 1` if any of them is the point at infinity
+            if unsafe { blst_p1_affine_is_inf(&p1) || blst_p2_affine_is_inf(&p2) } {
+                continue;
+            }
+
+            let mut ml = blst_fp12::default();
+            unsafe {
+                blst_miller_loop(&mut ml, &p2, &p1);
+                if let Some(a) = acc.as_mut() {
+                    blst_fp12_mul(a, a, &ml);
+                } else {
+                    acc = Some(ml);
+                }
+            }
+        }
+
+        let is_one = acc
+            .map(|ml| {
+                let mut ret = blst_fp12::default();
+                unsafe {
+                    blst_final_exp(&mut ret, &ml);
+                    blst_fp12_is_one(&ret)
+                }
+            })
+            .unwrap_or(true);
+
+        let mut out = vec![0u8; 32];
+        out[31] = is_one as u8;
+
+        Ok(output(cost, out))
+    }
+}
+
+pub struct Bls12MapFpToG1;
+
+impl Precompile for Bls12MapFpToG1 {
+    fn execute(
+        input: &[u8],
+        target_gas: Option<u64>,
+        _: &Context,
+        _: bool,
+    ) -> PrecompileResult {
+        charge(MAP_FP_TO_G1_GAS, target_gas)?;
+        if input.len() != PADDED_FP_LEN {
+            return Err(failure("invalid input length"));
+        }
+
+        let u = decode_fp(input)?;
+
+        let mut p = blst_p1::default();
+        unsafe {
+            blst_map_to_g1(&mut p, &u, std::ptr::null());
+        }
+
+        Ok(output(MAP_FP_TO_G1_GAS, encode_g1(&p)))
+    }
+}
+
+pub struct Bls12MapFp2ToG2;
+
+impl Precompile for Bls12MapFp2ToG2 {
+    fn execute(
+        input: &[u8],
+        target_gas: Option<u64>,
+        _: &Context,
+        _: bool,
+    ) -> PrecompileResult {
+        charge(MAP_FP2_TO_G2_GAS, target_gas)?;
+        if input.len() != PADDED_FP2_LEN {
+            return Err(failure("invalid input length"));
+        }
+
+        let u = decode_fp2(input)?;
+
+        let mut p = blst_p2::default();
+        unsafe {
+            blst_map_to_g2(&mut p, &u, std::ptr::null());
+        }
+
+        Ok(output(MAP_FP2_TO_G2_GAS, encode_g2(&p)))
+    }
+}
+
+#[inline(always)]
+fn msm_gas(k: usize, mul_gas: u64, discount: &[u16; 128]) -> u64 {
+    let d = *discount.get(k - 1).unwrap_or(&discount[discount.len() - 1]) as u64;
+    (k as u64).saturating_mul(mul_gas).saturating_mul(d) / MSM_MULTIPLIER
+}
+
+#[inline(always)]
+fn charge(cost: u64, target_gas: Option<u64>) -> Result<(), PrecompileFailure> {
+    if target_gas.map(|g| g < cost).unwrap_or(false) {
+        Err(PrecompileFailure::Error {
+            exit_status: ExitError::OutOfGas,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[inline(always)]
+fn failure(msg: &'static str) -> PrecompileFailure {
+    PrecompileFailure::Error {
+        exit_status: ExitError::Other(msg.into()),
+    }
+}
+
+#[inline(always)]
+fn output(cost: u64, output: Vec<u8>) -> PrecompileOutput {
+    PrecompileOutput {
+        exit_status: ExitSucceed::Returned,
+        cost,
+        output,
+        logs: vec![],
+    }
+}
+
+// A field element is encoded as 64 bytes,
+// the top 16 bytes must be zero, and the value must be less than the modulus.
+fn decode_fp(input: &[u8]) -> Result<blst_fp, PrecompileFailure> {
+    let (pad, be) = input.split_at(PADDED_FP_LEN - FP_LEN);
+    if pad.iter().any(|b| 0 != *b) {
+        return Err(failure("invalid field element padding"));
+    }
+
+    let mut fp = blst_fp::default();
+    let mut canonical = [0u8; FP_LEN];
+    unsafe {
+        blst_fp_from_bendian(&mut fp, be.as_ptr());
+        blst_bendian_from_fp(canonical.as_mut_ptr(), &fp);
+    }
+
+    if canonical[..] != *be {
+        return Err(failure("field element is not canonical"));
+    }
+
+    Ok(fp)
+}
+
+#[inline(always)]
+fn decode_fp2(input: &[u8]) -> Result<blst_fp2, PrecompileFailure> {
+    Ok(blst_fp2 {
+        fp: [
+            decode_fp(&input[..PADDED_FP_LEN])?,
+            decode_fp(&input[PADDED_FP_LEN..])?,
+        ],
+    })
+}
+
+fn decode_g1(
+    input: &[u8],
+    subgroup_check: bool,
+) -> Result<blst_p1_affine, PrecompileFailure> {
+    let p = blst_p1_affine {
+        x: decode_fp(&input[..PADDED_FP_LEN])?,
+        y: decode_fp(&input[PADDED_FP_LEN..])?,
+    };
+
+    // NOTE: the point at infinity is encoded as all zeros,
+    // which passes both of the checks below.
+    if unsafe { !blst_p1_affine_on_curve(&p) } {
+        return Err(failure("g1 point is not on curve"));
+    }
+    if subgroup_check && unsafe { !blst_p1_affine_in_g1(&p) } {
+        return Err(failure("g1 point is not in the correct subgroup"));
+    }
+
+    Ok(p)
+}
+
+fn decode_g2(
+    input: &[u8],
+    subgroup_check: bool,
+) -> Result<blst_p2_affine, PrecompileFailure> {
+    let p = blst_p2_affine {
+        x: decode_fp2(&input[..PADDED_FP2_LEN])?,
+        y: decode_fp2(&input[PADDED_FP2_LEN..])?,
+    };
+
+    if unsafe { !blst_p2_affine_on_curve(&p) } {
+        return Err(failure("g2 point is not on curve"));
+    }
+    if subgroup_check && unsafe { !blst_p2_affine_in_g2(&p) } {
+        return Err(failure("g2 point is not in the correct subgroup"));
+    }
+
+    Ok(p)
+}
+
+// Scalars are NOT required to be less than the group order.
+#[inline(always)]
+fn decode_scalar(input: &[u8]) -> blst_scalar {
+    let mut scalar = blst_scalar::default();
+    unsafe {
+        blst_scalar_from_bendian(&mut scalar, input.as_ptr());
+    }
+    scalar
+}
+
+#[inline(always)]
+fn encode_fp(fp: &blst_fp, out: &mut [u8]) {
+    unsafe {
+        blst_bendian_from_fp(out[PADDED_FP_LEN - FP_LEN..].as_mut_ptr(), fp);
+    }
+}
+
+fn encode_g1(p: &blst_p1) -> Vec<u8> {
+    let mut affine = blst_p1_affine::default();
+    unsafe {
+        blst_p1_to_affine(&mut affine, p);
+    }
+
+    let mut out = vec![0u8; G1_LEN];
+    encode_fp(&affine.x, &mut out[..PADDED_FP_LEN]);
+    encode_fp(&affine.y, &mut out[PADDED_FP_LEN..]);
+    out
+}
+
+fn encode_g2(p: &blst_p2) -> Vec<u8> {
+    let mut affine = blst_p2_affine::default();
+    unsafe {
+        blst_p2_to_affine(&mut affine, p);
+    }
+
+    let mut out = vec![0u8; G2_LEN];
+    for (i, fp) in [
+        affine.x.fp[0],
+        affine.x.fp[1],
+        affine.y.fp[0],
+        affine.y.fp[1],
+    ]
+    .iter()
+    .enumerate()
+    {
+        encode_fp(fp, &mut out[i * PADDED_FP_LEN..(i + 1) * PADDED_FP_LEN]);
+    }
+    out
+}
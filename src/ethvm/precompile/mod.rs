@@ -1,8 +1,28 @@
 //!
 //! # Precompiled components(contracts)
 //!
+//! All precompiles are registered by name,
+//! the active set of a block is a mapping of `address => name`,
+//! which is defined at genesis and can be changed at fork heights.
+//!
+
+pub(crate) mod block_ctx;
+pub mod bls12_381;
+pub(crate) mod params;
+pub(crate) mod staking;
 
-use evm::executor::stack::PrecompileFn;
+use crate::ledger::{staking::State as StakingState, ConsensusInfo};
+use block_ctx::BlockCtxPrecompile;
+use bls12_381::{
+    Bls12G1Add, Bls12G1Msm, Bls12G2Add, Bls12G2Msm, Bls12MapFp2ToG2, Bls12MapFpToG1,
+    Bls12Pairing,
+};
+use evm::{
+    executor::stack::{
+        PrecompileFailure, PrecompileFn, PrecompileOutput, PrecompileSet,
+    },
+    Context,
+};
 use fevm::Precompile;
 use fevm_precompile_blake2::Blake2F;
 use fevm_precompile_bn128::{Bn128Add, Bn128Mul, Bn128Pairing};
@@ -14,29 +34,257 @@ use fevm_precompile_simple::{
     ECRecover, ECRecoverPublicKey, Identity, Ripemd160, Sha256,
 };
 use once_cell::sync::Lazy;
+use params::ParamsPrecompile;
+use parking_lot::RwLock;
 use primitive_types::H160;
 use ruc::*;
-use std::collections::BTreeMap;
+use staking::{Journal, StakingPrecompile};
+use std::{collections::BTreeMap, result::Result as StdResult};
+use vsdb::{BranchName, VersionName};
+
+// address => registered name
+pub type PrecompileCfg = BTreeMap<H160, String>;
+
+pub const ECRECOVER: &str = "ecrecover";
+pub const SHA256: &str = "sha256";
+pub const RIPEMD160: &str = "ripemd160";
+pub const IDENTITY: &str = "identity";
+pub const MODEXP: &str = "modexp";
+pub const ECRECOVER_PUBLIC_KEY: &str = "ecrecover_public_key";
+pub const SHA3_FIPS256: &str = "sha3_fips256";
+pub const BLAKE2F: &str = "blake2f";
+pub const BN128_PAIRING: &str = "bn128_pairing";
+pub const BN128_ADD: &str = "bn128_add";
+pub const BN128_MUL: &str = "bn128_mul";
+pub const CURVE25519_ADD: &str = "curve25519_add";
+pub const CURVE25519_SCALAR_MUL: &str = "curve25519_scalar_mul";
+pub const ED25519_VERIFY: &str = "ed25519_verify";
+
+// EIP-2537, not in the default set
+pub const BLS12_G1ADD: &str = "bls12_g1add";
+pub const BLS12_G1MSM: &str = "bls12_g1msm";
+pub const BLS12_G2ADD: &str = "bls12_g2add";
+pub const BLS12_G2MSM: &str = "bls12_g2msm";
+pub const BLS12_PAIRING_CHECK: &str = "bls12_pairing_check";
+pub const BLS12_MAP_FP_TO_G1: &str = "bls12_map_fp_to_g1";
+pub const BLS12_MAP_FP2_TO_G2: &str = "bls12_map_fp2_to_g2";
+
+// Stateful precompiles, they are built for each transaction.
+pub const STAKING: &str = "staking";
+pub const BLOCK_CTX: &str = "block_ctx";
+pub const PARAMS: &str = "params";
+
+const STATEFUL: [&str; 3] = [STAKING, BLOCK_CTX, PARAMS];
+
+static REGISTRY: Lazy<RwLock<BTreeMap<String, PrecompileFn>>> = Lazy::new(|| {
+    RwLock::new(map! {B
+        ECRECOVER.to_owned() => ECRecover::execute as PrecompileFn,
+        SHA256.to_owned() => Sha256::execute,
+        RIPEMD160.to_owned() => Ripemd160::execute,
+        IDENTITY.to_owned() => Identity::execute,
+        MODEXP.to_owned() => Modexp::execute,
+        ECRECOVER_PUBLIC_KEY.to_owned() => ECRecoverPublicKey::execute,
+        SHA3_FIPS256.to_owned() => Sha3FIPS256::execute,
+        BLAKE2F.to_owned() => Blake2F::execute,
+        BN128_PAIRING.to_owned() => Bn128Pairing::execute,
+        BN128_ADD.to_owned() => Bn128Add::execute,
+        BN128_MUL.to_owned() => Bn128Mul::execute,
+        CURVE25519_ADD.to_owned() => Curve25519Add::execute,
+        CURVE25519_SCALAR_MUL.to_owned() => Curve25519ScalarMul::execute,
+        ED25519_VERIFY.to_owned() => Ed25519Verify::execute,
+        BLS12_G1ADD.to_owned() => Bls12G1Add::execute,
+        BLS12_G1MSM.to_owned() => Bls12G1Msm::execute,
+        BLS12_G2ADD.to_owned() => Bls12G2Add::execute,
+        BLS12_G2MSM.to_owned() => Bls12G2Msm::execute,
+        BLS12_PAIRING_CHECK.to_owned() => Bls12Pairing::execute,
+        BLS12_MAP_FP_TO_G1.to_owned() => Bls12MapFpToG1::execute,
+        BLS12_MAP_FP2_TO_G2.to_owned() => Bls12MapFp2ToG2::execute,
+    })
+});
 
-pub(crate) static PRECOMPILE_SET: Lazy<BTreeMap<H160, PrecompileFn>> = Lazy::new(|| {
+// Used when no precompile set is defined in the genesis.
+pub static DEFAULT_PRECOMPILE_CFG: Lazy<PrecompileCfg> = Lazy::new(|| {
     map! {B
-        idx_to_h160(1) => ECRecover::execute as PrecompileFn,
-        idx_to_h160(2) => Sha256::execute,
-        idx_to_h160(3) => Ripemd160::execute,
-        idx_to_h160(4) => Identity::execute,
-        idx_to_h160(5) => Modexp::execute,
-        idx_to_h160(6) => ECRecoverPublicKey::execute, // Compitable with F
-        idx_to_h160(7) => Sha3FIPS256::execute, // Compitable with F
-        idx_to_h160(1024) => Blake2F::execute,
-        idx_to_h160(1025) => Bn128Pairing::execute,
-        idx_to_h160(1026) => Bn128Add::execute,
-        idx_to_h160(1027) => Bn128Mul::execute,
-        idx_to_h160(1028) => Curve25519Add::execute,
-        idx_to_h160(1029) => Curve25519ScalarMul::execute,
-        idx_to_h160(1030) => Ed25519Verify::execute,
+        idx_to_h160(1) => ECRECOVER.to_owned(),
+        idx_to_h160(2) => SHA256.to_owned(),
+        idx_to_h160(3) => RIPEMD160.to_owned(),
+        idx_to_h160(4) => IDENTITY.to_owned(),
+        idx_to_h160(5) => MODEXP.to_owned(),
+        idx_to_h160(6) => ECRECOVER_PUBLIC_KEY.to_owned(), // Compitable with F
+        idx_to_h160(7) => SHA3_FIPS256.to_owned(), // Compitable with F
+        idx_to_h160(1024) => BLAKE2F.to_owned(),
+        idx_to_h160(1025) => BN128_PAIRING.to_owned(),
+        idx_to_h160(1026) => BN128_ADD.to_owned(),
+        idx_to_h160(1027) => BN128_MUL.to_owned(),
+        idx_to_h160(1028) => CURVE25519_ADD.to_owned(),
+        idx_to_h160(1029) => CURVE25519_SCALAR_MUL.to_owned(),
+        idx_to_h160(1030) => ED25519_VERIFY.to_owned(),
+        idx_to_h160(0x1001) => STAKING.to_owned(),
+        idx_to_h160(0x1002) => BLOCK_CTX.to_owned(),
+        idx_to_h160(0x1003) => PARAMS.to_owned(),
     }
 });
 
+/// Plug a new stateless precompile into the registry,
+/// it can be activated by the precompile config after this.
+///
+/// NOTE:
+/// All nodes must register the same set of precompiles before starting,
+/// or the chain will fork once the new one is activated.
+pub fn register(name: &str, f: PrecompileFn) -> Result<()> {
+    if STATEFUL.contains(&name) {
+        return Err(eg!("'{}' is a reserved name", name));
+    }
+    let mut registry = REGISTRY.write();
+    if registry.contains_key(name) {
+        return Err(eg!("'{}' has been registered", name));
+    }
+    registry.insert(name.to_owned(), f);
+    Ok(())
+}
+
+/// Names of all the available precompiles.
+pub fn registered() -> Vec<String> {
+    let mut names = REGISTRY.read().keys().cloned().collect::<Vec<_>>();
+    names.extend(STATEFUL.iter().map(|n| n.to_string()));
+    names
+}
+
+/// Ensure that all names in the config have been registered,
+/// and that no name is activated at more than one address.
+pub fn check_cfg(cfg: &PrecompileCfg) -> Result<()> {
+    let registry = REGISTRY.read();
+    let mut used = BTreeMap::new();
+    for (addr, name) in cfg.iter() {
+        if !STATEFUL.contains(&name.as_str()) && !registry.contains_key(name) {
+            return Err(eg!("Unknown precompile: {}", name));
+        }
+        if let Some(prev) = used.insert(name.as_str(), addr) {
+            return Err(eg!(
+                "Precompile '{}' is set at both {:?} and {:?}",
+                name,
+                prev,
+                addr
+            ));
+        }
+    }
+    Ok(())
+}
+
+// The precompile set used by the executor,
+// stateful precompiles are bound to the branch of the current transaction.
+pub struct OvrPrecompileSet<'a> {
+    stateless: BTreeMap<H160, PrecompileFn>,
+    staking: Option<StakingPrecompile<'a>>,
+    block_ctx: Option<BlockCtxPrecompile>,
+    params: Option<ParamsPrecompile>,
+}
+
+impl<'a> OvrPrecompileSet<'a> {
+    // NOTE:
+    // Stateful precompiles are disabled if their states are `None`,
+    // except the params one, which reverts without an admin.
+    pub fn new(
+        cfg: &PrecompileCfg,
+        staking_state: Option<(&StakingState, BranchName<'a>)>,
+        consensus: Option<&ConsensusInfo>,
+        params_admin: Option<H160>,
+    ) -> Self {
+        let registry = REGISTRY.read();
+
+        let mut stateless = BTreeMap::new();
+        let mut staking = None;
+        let mut block_ctx = None;
+        let mut params = None;
+        for (addr, name) in cfg.iter() {
+            if STAKING == name {
+                staking = staking_state
+                    .map(|(s, b)| StakingPrecompile::new(*addr, s.clone(), b));
+            } else if BLOCK_CTX == name {
+                block_ctx = consensus.map(|c| BlockCtxPrecompile::new(*addr, c.clone()));
+            } else if PARAMS == name {
+                params = Some(ParamsPrecompile::new(*addr, params_admin));
+            } else if let Some(f) = registry.get(name) {
+                stateless.insert(*addr, *f);
+            }
+        }
+
+        Self {
+            stateless,
+            staking,
+            block_ctx,
+            params,
+        }
+    }
+
+    // Stateful precompiles read a historical state,
+    // used by the calls of read-only views.
+    #[inline(always)]
+    pub fn at_version(mut self, version: VersionName<'a>) -> Self {
+        self.staking = self.staking.map(|s| s.at_version(version));
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn staking(&self) -> Option<&StakingPrecompile<'a>> {
+        self.staking.as_ref()
+    }
+
+    #[inline(always)]
+    pub(crate) fn params(&self) -> Option<&ParamsPrecompile> {
+        self.params.as_ref()
+    }
+
+    // Must be attached to the stack state of the executor.
+    #[inline(always)]
+    pub(crate) fn staking_journal(&self) -> Option<Journal> {
+        self.staking.as_ref().map(|s| s.journal())
+    }
+}
+
+impl<'a> PrecompileSet for OvrPrecompileSet<'a> {
+    fn execute(
+        &self,
+        address: H160,
+        input: &[u8],
+        gas_limit: Option<u64>,
+        context: &Context,
+        is_static: bool,
+    ) -> Option<StdResult<PrecompileOutput, PrecompileFailure>> {
+        if let Some(s) = self.staking.as_ref().filter(|s| s.address() == address) {
+            return Some(s.execute(input, gas_limit, context, is_static));
+        }
+        if let Some(c) = self.block_ctx.as_ref().filter(|c| c.address() == address) {
+            return Some(c.execute(input, gas_limit, context));
+        }
+        if let Some(p) = self.params.as_ref().filter(|p| p.address() == address) {
+            return Some(p.execute(input, gas_limit, context, is_static));
+        }
+        self.stateless
+            .get(&address)
+            .map(|f| f(input, gas_limit, context, is_static))
+    }
+
+    fn is_precompile(&self, address: H160) -> bool {
+        self.stateless.contains_key(&address)
+            || self
+                .staking
+                .as_ref()
+                .map(|s| s.address() == address)
+                .unwrap_or(false)
+            || self
+                .block_ctx
+                .as_ref()
+                .map(|c| c.address() == address)
+                .unwrap_or(false)
+            || self
+                .params
+                .as_ref()
+                .map(|p| p.address() == address)
+                .unwrap_or(false)
+    }
+}
+
 #[inline(always)]
 pub(crate) fn idx_to_h160(i: u64) -> H160 {
     H160::from_low_u64_be(i)
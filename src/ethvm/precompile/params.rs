@@ -0,0 +1,219 @@
+//!
+//! # Consensus-params precompile
+//!
+//! The governance path of the consensus params and the block gas limit,
+//! only the admin defined in the genesis(a multisig or a DAO contract in
+//! practice) can call the mutable functions.
+//!
+//! Solidity interface:
+//!
+//! ```solidity
+//! interface ConsensusParams {
+//!     event ConsensusParamsUpdated(
+//!         uint256 blockMaxBytes,
+//!         uint256 blockGasLimit,
+//!         uint256 evidenceMaxAgeNumBlocks,
+//!         uint256 evidenceMaxAgeDurationNs,
+//!         uint256 evidenceMaxBytes
+//!     );
+//!     event AdminChanged(address indexed admin);
+//!
+//!     function setConsensusParams(
+//!         uint256 blockMaxBytes,
+//!         uint256 blockGasLimit,
+//!         uint256 evidenceMaxAgeNumBlocks,
+//!         uint256 evidenceMaxAgeDurationNs,
+//!         uint256 evidenceMaxBytes
+//!     ) external;
+//!     function setAdmin(address admin) external;
+//!     function admin() external view returns (address);
+//! }
+//! ```
+//!
+//! Like the staking precompile, changes are journaled as event logs and
+//! settled by `settle` after the transaction succeeds. The new params are
+//! reported to tendermint by the `EndBlock` of the same block, a new admin
+//! takes effect from the next transaction.
+//!
+
+use super::staking::{
+    encode_u256, keccak, output, read_address, read_u256, revert, selector,
+};
+use crate::ledger::{ConsensusParams, State as LedgerState};
+use evm::{
+    backend::Log,
+    executor::stack::{PrecompileFailure, PrecompileOutput},
+    Context, ExitError,
+};
+use once_cell::sync::Lazy;
+use primitive_types::{H160, H256, U256};
+use ruc::*;
+use std::result::Result as StdResult;
+
+const GAS_SET: u64 = 20_000;
+const GAS_QUERY: u64 = 200;
+
+// `MaxBlockSizeBytes` of tendermint
+const BLOCK_MAX_BYTES_LIMIT: u64 = 104_857_600;
+// a plain transfer must fit in a block
+const BLOCK_GAS_LIMIT_MIN: u64 = 21_000;
+
+static SEL_SET_PARAMS: Lazy<[u8; 4]> = Lazy::new(|| {
+    selector("setConsensusParams(uint256,uint256,uint256,uint256,uint256)")
+});
+static SEL_SET_ADMIN: Lazy<[u8; 4]> = Lazy::new(|| selector("setAdmin(address)"));
+static SEL_ADMIN: Lazy<[u8; 4]> = Lazy::new(|| selector("admin()"));
+
+static EV_PARAMS_UPDATED: Lazy<H256> = Lazy::new(|| {
+    keccak("ConsensusParamsUpdated(uint256,uint256,uint256,uint256,uint256)")
+});
+static EV_ADMIN_CHANGED: Lazy<H256> = Lazy::new(|| keccak("AdminChanged(address)"));
+
+type PrecompileResult = StdResult<PrecompileOutput, PrecompileFailure>;
+
+#[derive(Clone, Debug)]
+pub(crate) struct ParamsPrecompile {
+    address: H160,
+    // `None` means that the governance is disabled
+    admin: Option<H160>,
+}
+
+impl ParamsPrecompile {
+    // The zero address is never an admin,
+    // it is the default caller of `eth_call`.
+    #[inline(always)]
+    pub(crate) fn new(address: H160, admin: Option<H160>) -> Self {
+        let admin = admin.filter(|a| !a.is_zero());
+        Self { address, admin }
+    }
+
+    #[inline(always)]
+    pub(crate) fn address(&self) -> H160 {
+        self.address
+    }
+
+    pub(crate) fn execute(
+        &self,
+        input: &[u8],
+        gas_limit: Option<u64>,
+        context: &Context,
+        is_static: bool,
+    ) -> PrecompileResult {
+        if input.len() < 4 {
+            return Err(revert("invalid selector", 0));
+        }
+        let (sel, args) = input.split_at(4);
+
+        let cost = alt!(sel == *SEL_ADMIN, GAS_QUERY, GAS_SET);
+        if gas_limit.map(|l| l < cost).unwrap_or(false) {
+            return Err(PrecompileFailure::Error {
+                exit_status: ExitError::OutOfGas,
+            });
+        }
+        if !context.apparent_value.is_zero() {
+            return Err(revert("function is not payable", cost));
+        }
+
+        if sel == *SEL_ADMIN {
+            let ret = H256::from(self.admin.unwrap_or_default())
+                .as_bytes()
+                .to_vec();
+            return Ok(output(GAS_QUERY, ret, vec![]));
+        }
+
+        if is_static {
+            return Err(revert("state change in static call", GAS_SET));
+        }
+        // the caller of a delegatecall is not the admin itself
+        if context.address != self.address {
+            return Err(revert("delegatecall is not allowed", GAS_SET));
+        }
+        match self.admin {
+            None => return Err(revert("admin not set", GAS_SET)),
+            Some(admin) if admin != context.caller => {
+                return Err(revert("caller is not the admin", GAS_SET));
+            }
+            _ => {}
+        }
+
+        if sel == *SEL_SET_PARAMS {
+            let words = (0..5)
+                .map(|i| read_u256(args, i))
+                .collect::<StdResult<Vec<_>, _>>()
+                .map_err(|e| revert(e, GAS_SET))?;
+            check_params(&words).map_err(|e| revert(e, GAS_SET))?;
+            let log = Log {
+                address: self.address,
+                topics: vec![*EV_PARAMS_UPDATED],
+                data: words.into_iter().flat_map(encode_u256).collect(),
+            };
+            Ok(output(GAS_SET, vec![], vec![log]))
+        } else if sel == *SEL_SET_ADMIN {
+            let admin = read_address(args, 0).map_err(|e| revert(e, GAS_SET))?;
+            let log = Log {
+                address: self.address,
+                topics: vec![*EV_ADMIN_CHANGED, H256::from(admin)],
+                data: vec![],
+            };
+            Ok(output(GAS_SET, vec![], vec![log]))
+        } else {
+            Err(revert("unknown selector", 0))
+        }
+    }
+
+    // Apply the changes journaled in the logs of a succeeded transaction,
+    // to the default branch of the state.
+    pub(crate) fn settle(&self, logs: &[Log], state: &LedgerState) -> Result<()> {
+        for l in logs.iter().filter(|l| l.address == self.address) {
+            let topic = l.topics.first().copied().unwrap_or_default();
+            if topic == *EV_PARAMS_UPDATED {
+                let words = (0..5)
+                    .map(|i| read_u256(&l.data, i))
+                    .collect::<StdResult<Vec<_>, _>>()
+                    .map_err(|e| eg!(e))?;
+                let params = ConsensusParams {
+                    block_max_bytes: words[0].as_u64() as i64,
+                    evidence_max_age_num_blocks: words[2].as_u64() as i64,
+                    evidence_max_age_duration_ns: words[3].as_u64(),
+                    evidence_max_bytes: words[4].as_u64() as i64,
+                };
+                state.consensus_params.set_value(params).c(d!())?;
+                state.evm.block_gas_limit.set_value(words[1]).c(d!())?;
+            } else if topic == *EV_ADMIN_CHANGED {
+                let admin = l
+                    .topics
+                    .get(1)
+                    .map(|t| H160::from_slice(&t[12..]))
+                    .c(d!("invalid params log"))?;
+                state.params_admin.set_value(admin).c(d!())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The same rules as the `ValidateConsensusParams` of tendermint,
+// an invalid update would halt the chain in `EndBlock`.
+fn check_params(words: &[U256]) -> StdResult<(), &'static str> {
+    let (max_bytes, gas_limit, max_age_blocks, max_age_ns, evidence_bytes) =
+        (words[0], words[1], words[2], words[3], words[4]);
+
+    if max_bytes.is_zero() || max_bytes > U256::from(BLOCK_MAX_BYTES_LIMIT) {
+        return Err("invalid block max bytes");
+    }
+    if gas_limit < U256::from(BLOCK_GAS_LIMIT_MIN) {
+        return Err("block gas limit is too low");
+    }
+    if max_age_blocks.is_zero() || max_age_blocks > U256::from(i64::MAX) {
+        return Err("invalid evidence max age");
+    }
+    if max_age_ns.is_zero() || max_age_ns > U256::from(i64::MAX) {
+        return Err("invalid evidence max age");
+    }
+    if evidence_bytes > max_bytes {
+        return Err("evidence max bytes exceeds block max bytes");
+    }
+
+    Ok(())
+}
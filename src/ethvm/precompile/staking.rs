@@ -0,0 +1,516 @@
+//!
+//! # Staking precompile
+//!
+//! Solidity interface:
+//!
+//! ```solidity
+//! interface Staking {
+//!     event Delegated(address indexed delegator, address indexed validator, uint256 amount);
+//!     event Undelegated(address indexed delegator, address indexed validator, uint256 amount);
+//!     event RewardsClaimed(address indexed delegator, uint256 amount);
+//!     event RewardsFunded(address indexed delegator, uint256 amount);
+//!
+//!     function delegate(address validator) external payable;
+//!     function undelegate(address validator, uint256 amount) external;
+//!     function claimRewards() external returns (uint256);
+//!     function fundRewards(address delegator) external payable;
+//!     function delegationOf(address delegator, address validator) external view returns (uint256);
+//!     function validatorPower(address validator) external view returns (uint256);
+//!     function rewardsOf(address delegator) external view returns (uint256);
+//! }
+//! ```
+//!
+//! Staking operations are NOT written to the ledger during the evm execution,
+//! instead, each operation is journaled as an event log of this precompile.
+//! Logs are a part of the evm substate, so the operations made in a reverted
+//! call frame are discarded together with the frame, and the remaining ones
+//! are settled by `settle` after the transaction succeeds.
+//!
+//! The operations are also recorded in a journal whose frames follow the substates
+//! of `OvrStackState`, so queries see the operations made before them in the same
+//! transaction, except the ones of reverted frames.
+//!
+//! Rewards are funded by `fundRewards`, the funds are kept in the account of
+//! this precompile together with the delegated ones, and paid from there.
+//! The payouts are transfers of the evm substate, made by `OvrStackState` when
+//! the frame of the precompile is committed, so they follow the reverts too.
+//!
+//! Only the validators registered in the genesis can be delegated to.
+//!
+
+use crate::ledger::staking::State as StakingState;
+use evm::{
+    backend::Log,
+    executor::stack::{PrecompileFailure, PrecompileOutput},
+    Context, ExitError, ExitRevert, ExitSucceed, Transfer,
+};
+use once_cell::sync::Lazy;
+use primitive_types::{H160, H256, U256};
+use ruc::*;
+use sha3::{Digest, Keccak256};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    result::Result as StdResult,
+};
+use vsdb::{BranchName, KeyEnDe, MapxVs, ValueEnDe, VersionName};
+
+const GAS_DELEGATE: u64 = 25_000;
+const GAS_UNDELEGATE: u64 = 25_000;
+const GAS_CLAIM: u64 = 20_000;
+const GAS_FUND: u64 = 20_000;
+const GAS_QUERY: u64 = 2_100;
+
+static SEL_DELEGATE: Lazy<[u8; 4]> = Lazy::new(|| selector("delegate(address)"));
+static SEL_UNDELEGATE: Lazy<[u8; 4]> =
+    Lazy::new(|| selector("undelegate(address,uint256)"));
+static SEL_CLAIM: Lazy<[u8; 4]> = Lazy::new(|| selector("claimRewards()"));
+static SEL_FUND: Lazy<[u8; 4]> = Lazy::new(|| selector("fundRewards(address)"));
+static SEL_DELEGATION_OF: Lazy<[u8; 4]> =
+    Lazy::new(|| selector("delegationOf(address,address)"));
+static SEL_VALIDATOR_POWER: Lazy<[u8; 4]> =
+    Lazy::new(|| selector("validatorPower(address)"));
+static SEL_REWARDS_OF: Lazy<[u8; 4]> = Lazy::new(|| selector("rewardsOf(address)"));
+
+static EV_DELEGATED: Lazy<H256> =
+    Lazy::new(|| keccak("Delegated(address,address,uint256)"));
+static EV_UNDELEGATED: Lazy<H256> =
+    Lazy::new(|| keccak("Undelegated(address,address,uint256)"));
+static EV_REWARDS_CLAIMED: Lazy<H256> =
+    Lazy::new(|| keccak("RewardsClaimed(address,uint256)"));
+static EV_REWARDS_FUNDED: Lazy<H256> =
+    Lazy::new(|| keccak("RewardsFunded(address,uint256)"));
+
+type PrecompileResult = StdResult<PrecompileOutput, PrecompileFailure>;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Delegate(H160, H160, U256),
+    Undelegate(H160, H160, U256),
+    Claim(H160),
+    Fund(H160, U256),
+}
+
+/// Staking operations of the current transaction, one frame for each substate.
+#[derive(Clone, Debug)]
+pub(crate) struct Journal {
+    frames: Rc<RefCell<Vec<Vec<Op>>>>,
+    // payouts of the precompile call being executed,
+    // made by `OvrStackState` when its frame is committed
+    payouts: Rc<RefCell<Vec<Transfer>>>,
+    // a payout has failed, the transaction can not be applied
+    failed: Rc<Cell<bool>>,
+}
+
+impl Journal {
+    #[inline(always)]
+    fn new() -> Self {
+        Self {
+            frames: Rc::new(RefCell::new(vec![vec![]])),
+            payouts: Rc::new(RefCell::new(vec![])),
+            failed: Rc::new(Cell::new(false)),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn enter(&self) {
+        self.frames.borrow_mut().push(vec![]);
+    }
+
+    pub(crate) fn exit_commit(&self) {
+        let mut frames = self.frames.borrow_mut();
+        if 1 < frames.len() {
+            if let Some(mut exited) = frames.pop() {
+                if let Some(f) = frames.last_mut() {
+                    f.append(&mut exited);
+                }
+            }
+        }
+    }
+
+    // Also used for the discarded frames.
+    pub(crate) fn exit_revert(&self) {
+        self.payouts.borrow_mut().clear();
+        let mut frames = self.frames.borrow_mut();
+        if 1 < frames.len() {
+            frames.pop();
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn take_payouts(&self) -> Vec<Transfer> {
+        self.payouts.take()
+    }
+
+    #[inline(always)]
+    pub(crate) fn fail(&self) {
+        self.failed.set(true);
+    }
+
+    #[inline(always)]
+    pub(crate) fn failed(&self) -> bool {
+        self.failed.get()
+    }
+
+    #[inline(always)]
+    fn pay(&self, transfer: Transfer) {
+        self.payouts.borrow_mut().push(transfer);
+    }
+
+    #[inline(always)]
+    fn push(&self, op: Op) {
+        if let Some(f) = self.frames.borrow_mut().last_mut() {
+            f.push(op);
+        }
+    }
+
+    // Fold all the operations in the order they were made.
+    #[inline(always)]
+    fn fold<T>(&self, init: T, f: impl Fn(T, &Op) -> T) -> T {
+        self.frames.borrow().iter().flatten().fold(init, f)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct StakingPrecompile<'a> {
+    // Delegated funds and reward funds are kept in the account of this address.
+    address: H160,
+    state: StakingState,
+    branch: BranchName<'a>,
+    // set in the calls of a historical state
+    version: Option<VersionName<'a>>,
+    journal: Journal,
+}
+
+impl<'a> StakingPrecompile<'a> {
+    #[inline(always)]
+    pub(crate) fn new(
+        address: H160,
+        state: StakingState,
+        branch: BranchName<'a>,
+    ) -> Self {
+        Self {
+            address,
+            state,
+            branch,
+            version: None,
+            journal: Journal::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn at_version(mut self, version: VersionName<'a>) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn address(&self) -> H160 {
+        self.address
+    }
+
+    #[inline(always)]
+    pub(crate) fn journal(&self) -> Journal {
+        self.journal.clone()
+    }
+
+    #[inline(always)]
+    fn read<K: KeyEnDe, V: ValueEnDe + Default>(
+        &self,
+        map: &MapxVs<K, V>,
+        key: &K,
+    ) -> V {
+        match self.version.as_ref() {
+            Some(v) => map.get_by_branch_version(key, self.branch, VersionName(v.0)),
+            None => map.get_by_branch(key, self.branch),
+        }
+        .unwrap_or_default()
+    }
+
+    fn delegation_of(&self, delegator: H160, validator: H160) -> U256 {
+        let base = self.read(&self.state.delegations, &(delegator, validator));
+        self.journal.fold(base, |n, op| match op {
+            Op::Delegate(d, v, x) if (*d, *v) == (delegator, validator) => {
+                n.saturating_add(*x)
+            }
+            Op::Undelegate(d, v, x) if (*d, *v) == (delegator, validator) => {
+                n.saturating_sub(*x)
+            }
+            _ => n,
+        })
+    }
+
+    fn validator_power_of(&self, validator: H160) -> U256 {
+        let base = self.read(&self.state.validator_power, &validator);
+        self.journal.fold(base, |n, op| match op {
+            Op::Delegate(_, v, x) if *v == validator => n.saturating_add(*x),
+            Op::Undelegate(_, v, x) if *v == validator => n.saturating_sub(*x),
+            _ => n,
+        })
+    }
+
+    fn rewards_of(&self, delegator: H160) -> U256 {
+        let base = self.read(&self.state.rewards, &delegator);
+        self.journal.fold(base, |n, op| match op {
+            Op::Claim(d) if *d == delegator => U256::zero(),
+            Op::Fund(d, x) if *d == delegator => n.saturating_add(*x),
+            _ => n,
+        })
+    }
+
+    pub(crate) fn execute(
+        &self,
+        input: &[u8],
+        gas_limit: Option<u64>,
+        context: &Context,
+        is_static: bool,
+    ) -> PrecompileResult {
+        if input.len() < 4 {
+            return Err(revert("invalid selector", 0));
+        }
+        let (sel, args) = input.split_at(4);
+
+        macro_rules! charge {
+            ($cost: expr) => {
+                if gas_limit.map(|l| l < $cost).unwrap_or(false) {
+                    return Err(PrecompileFailure::Error {
+                        exit_status: ExitError::OutOfGas,
+                    });
+                }
+            };
+        }
+
+        macro_rules! ensure_mutable {
+            ($cost: expr) => {
+                if is_static {
+                    return Err(revert("state change in static call", $cost));
+                }
+            };
+        }
+
+        macro_rules! ensure_no_value {
+            ($cost: expr) => {
+                if !context.apparent_value.is_zero() {
+                    return Err(revert("function is not payable", $cost));
+                }
+            };
+        }
+
+        // the caller of a delegatecall is not the sender of the funds
+        if context.address != self.address {
+            return Err(revert("delegatecall is not allowed", 0));
+        }
+
+        let delegator = context.caller;
+
+        if sel == *SEL_DELEGATE {
+            charge!(GAS_DELEGATE);
+            ensure_mutable!(GAS_DELEGATE);
+            let validator =
+                read_address(args, 0).map_err(|e| revert(e, GAS_DELEGATE))?;
+            let amount = context.apparent_value;
+            if amount.is_zero() {
+                return Err(revert("zero amount", GAS_DELEGATE));
+            }
+            if !self.read(&self.state.validators, &validator) {
+                return Err(revert("unknown validator", GAS_DELEGATE));
+            }
+            self.journal
+                .push(Op::Delegate(delegator, validator, amount));
+            Ok(output(
+                GAS_DELEGATE,
+                vec![],
+                vec![self.event(*EV_DELEGATED, &[delegator, validator], amount)],
+            ))
+        } else if sel == *SEL_UNDELEGATE {
+            charge!(GAS_UNDELEGATE);
+            ensure_mutable!(GAS_UNDELEGATE);
+            ensure_no_value!(GAS_UNDELEGATE);
+            let validator =
+                read_address(args, 0).map_err(|e| revert(e, GAS_UNDELEGATE))?;
+            let amount = read_u256(args, 1).map_err(|e| revert(e, GAS_UNDELEGATE))?;
+            if amount.is_zero() {
+                return Err(revert("zero amount", GAS_UNDELEGATE));
+            }
+
+            if self.delegation_of(delegator, validator) < amount {
+                return Err(revert("insufficient delegation", GAS_UNDELEGATE));
+            }
+            self.journal
+                .push(Op::Undelegate(delegator, validator, amount));
+            self.journal.pay(self.payout(delegator, amount));
+
+            Ok(output(
+                GAS_UNDELEGATE,
+                vec![],
+                vec![self.event(*EV_UNDELEGATED, &[delegator, validator], amount)],
+            ))
+        } else if sel == *SEL_CLAIM {
+            charge!(GAS_CLAIM);
+            ensure_mutable!(GAS_CLAIM);
+            ensure_no_value!(GAS_CLAIM);
+            let amount = self.rewards_of(delegator);
+            self.journal.push(Op::Claim(delegator));
+            if !amount.is_zero() {
+                self.journal.pay(self.payout(delegator, amount));
+            }
+            Ok(output(
+                GAS_CLAIM,
+                encode_u256(amount),
+                vec![self.event(*EV_REWARDS_CLAIMED, &[delegator], amount)],
+            ))
+        } else if sel == *SEL_FUND {
+            charge!(GAS_FUND);
+            ensure_mutable!(GAS_FUND);
+            let delegator = read_address(args, 0).map_err(|e| revert(e, GAS_FUND))?;
+            let amount = context.apparent_value;
+            if amount.is_zero() {
+                return Err(revert("zero amount", GAS_FUND));
+            }
+            self.journal.push(Op::Fund(delegator, amount));
+            Ok(output(
+                GAS_FUND,
+                vec![],
+                vec![self.event(*EV_REWARDS_FUNDED, &[delegator], amount)],
+            ))
+        } else if sel == *SEL_DELEGATION_OF {
+            charge!(GAS_QUERY);
+            ensure_no_value!(GAS_QUERY);
+            let delegator = read_address(args, 0).map_err(|e| revert(e, GAS_QUERY))?;
+            let validator = read_address(args, 1).map_err(|e| revert(e, GAS_QUERY))?;
+            let amount = self.delegation_of(delegator, validator);
+            Ok(output(GAS_QUERY, encode_u256(amount), vec![]))
+        } else if sel == *SEL_VALIDATOR_POWER {
+            charge!(GAS_QUERY);
+            ensure_no_value!(GAS_QUERY);
+            let validator = read_address(args, 0).map_err(|e| revert(e, GAS_QUERY))?;
+            let amount = self.validator_power_of(validator);
+            Ok(output(GAS_QUERY, encode_u256(amount), vec![]))
+        } else if sel == *SEL_REWARDS_OF {
+            charge!(GAS_QUERY);
+            ensure_no_value!(GAS_QUERY);
+            let delegator = read_address(args, 0).map_err(|e| revert(e, GAS_QUERY))?;
+            let amount = self.rewards_of(delegator);
+            Ok(output(GAS_QUERY, encode_u256(amount), vec![]))
+        } else {
+            Err(revert("unknown selector", 0))
+        }
+    }
+
+    #[inline(always)]
+    fn event(&self, topic: H256, addrs: &[H160], amount: U256) -> Log {
+        let mut topics = vec![topic];
+        addrs.iter().for_each(|a| topics.push(H256::from(*a)));
+        Log {
+            address: self.address,
+            topics,
+            data: encode_u256(amount),
+        }
+    }
+
+    #[inline(always)]
+    fn payout(&self, to: H160, amount: U256) -> Transfer {
+        Transfer {
+            source: self.address,
+            target: to,
+            value: amount,
+        }
+    }
+
+    // Apply the staking operations journaled in the logs of a succeeded transaction,
+    // the balances have been moved by the evm, only the staking state is written.
+    pub(crate) fn settle(&self, logs: &[Log]) -> Result<()> {
+        let b = self.branch;
+
+        for l in logs.iter().filter(|l| l.address == self.address) {
+            let topic = l.topics.first().copied().unwrap_or_default();
+            let amount = U256::from_big_endian(&l.data);
+            let addr_at = |i: usize| {
+                l.topics
+                    .get(i)
+                    .map(|t| H160::from_slice(&t[12..]))
+                    .c(d!("invalid staking log"))
+            };
+
+            if topic == *EV_DELEGATED {
+                self.state
+                    .delegate(addr_at(1)?, addr_at(2)?, amount, b)
+                    .c(d!())?;
+            } else if topic == *EV_UNDELEGATED {
+                self.state
+                    .undelegate(addr_at(1)?, addr_at(2)?, amount, b)
+                    .c(d!())?;
+            } else if topic == *EV_REWARDS_CLAIMED {
+                self.state.take_rewards(addr_at(1)?, b).c(d!())?;
+            } else if topic == *EV_REWARDS_FUNDED {
+                self.state.add_rewards(addr_at(1)?, amount, b).c(d!())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[inline(always)]
+pub(super) fn output(cost: u64, output: Vec<u8>, logs: Vec<Log>) -> PrecompileOutput {
+    PrecompileOutput {
+        exit_status: ExitSucceed::Returned,
+        cost,
+        output,
+        logs,
+    }
+}
+
+// Revert with the standard `Error(string)` encoding.
+pub(super) fn revert(msg: &str, cost: u64) -> PrecompileFailure {
+    let mut output = selector("Error(string)").to_vec();
+    output.extend_from_slice(&encode_u256(U256::from(32u8)));
+    output.extend_from_slice(&encode_u256(U256::from(msg.len())));
+    output.extend_from_slice(msg.as_bytes());
+    output.resize(output.len() + (32 - msg.len() % 32) % 32, 0);
+
+    PrecompileFailure::Revert {
+        exit_status: ExitRevert::Reverted,
+        output,
+        cost,
+    }
+}
+
+#[inline(always)]
+fn read_word(args: &[u8], idx: usize) -> StdResult<&[u8], &'static str> {
+    args.get(idx * 32..(idx + 1) * 32)
+        .ok_or("invalid input length")
+}
+
+#[inline(always)]
+pub(super) fn read_address(args: &[u8], idx: usize) -> StdResult<H160, &'static str> {
+    let word = read_word(args, idx)?;
+    if word[..12].iter().any(|b| *b != 0) {
+        return Err("invalid address");
+    }
+    Ok(H160::from_slice(&word[12..]))
+}
+
+#[inline(always)]
+pub(super) fn read_u256(args: &[u8], idx: usize) -> StdResult<U256, &'static str> {
+    read_word(args, idx).map(U256::from_big_endian)
+}
+
+#[inline(always)]
+pub(super) fn encode_u256(v: U256) -> Vec<u8> {
+    let mut buf = [0u8; 32];
+    v.to_big_endian(&mut buf);
+    buf.to_vec()
+}
+
+#[inline(always)]
+pub(super) fn keccak(signature: &str) -> H256 {
+    H256::from_slice(Keccak256::digest(signature.as_bytes()).as_slice())
+}
+
+#[inline(always)]
+pub(super) fn selector(signature: &str) -> [u8; 4] {
+    let mut sel = [0u8; 4];
+    sel.copy_from_slice(&keccak(signature)[..4]);
+    sel
+}
@@ -1,16 +1,20 @@
 pub mod token;
 
 use crate::{
-    common::HashValueRef,
-    ethvm::{impls::stack::OvrStackState, precompile::PRECOMPILE_SET, OvrAccount},
+    common::{halt, HashValueRef},
+    ethvm::{
+        impls::stack::OvrStackState, precompile::OvrPrecompileSet,
+        vesting::VestingSchedule, OvrAccount,
+    },
     ledger::{Log as LedgerLog, Receipt, StateBranch},
-    InitalContract,
+    tx::error::TxError,
+    InitalAccount, InitalContract, InitalVesting,
 };
 use ethereum::{Log, TransactionAction, TransactionAny};
 use evm::{
-    backend::{Apply, ApplyBackend},
+    backend::{Apply, ApplyBackend, Basic},
     executor::stack::{StackExecutor, StackSubstateMetadata},
-    Config as EvmCfg, CreateScheme, ExitReason,
+    CreateScheme, ExitError, ExitFatal, ExitReason,
 };
 use once_cell::sync::Lazy;
 use primitive_types::{H160, H256, U256};
@@ -36,17 +40,75 @@ impl Tx {
         sb: &mut StateBranch,
         b: BranchName,
         estimate: bool,
-    ) -> StdResult<(ExecRet, Receipt), Option<ExecRet>> {
-        if let Ok((addr, _, gas_price)) = info!(self.pre_exec(sb, b)) {
-            let (from, to) = self.get_from_to();
-            let ret = self.exec(addr, sb, b, gas_price, estimate);
-            let r = ret.gen_receipt(from, to);
-            alt!(ret.success, Ok((ret, r)), Err(Some(ret)))
+    ) -> StdResult<(ExecRet, Receipt), (TxError, Option<ExecRet>)> {
+        let (addr, _, gas_price) = self.pre_exec(sb, b).map_err(|e| (e, None))?;
+        let (from, to) = self.get_from_to();
+        let ret = self.exec(addr, sb, b, gas_price, estimate);
+        let r = ret.gen_receipt(from, to);
+        if ret.success {
+            Ok((ret, r))
         } else {
-            Err(None)
+            Err((ret.to_error(), Some(ret)))
         }
     }
 
+    // Stateless checks, the EVM never runs for the rejected ones.
+    pub(crate) fn valid_in_abci(&self, sb: &StateBranch) -> StdResult<(), TxError> {
+        let (chain_id, gas_limit, gas_price, value) = match &self.tx {
+            // pre-EIP155 transactions have no chain id
+            TransactionAny::Legacy(tx) => (
+                tx.signature.chain_id(),
+                tx.gas_limit,
+                tx.gas_price,
+                tx.value,
+            ),
+            TransactionAny::EIP2930(tx) => {
+                (Some(tx.chain_id), tx.gas_limit, tx.gas_price, tx.value)
+            }
+            TransactionAny::EIP1559(tx) => (
+                Some(tx.chain_id),
+                tx.gas_limit,
+                tx.max_fee_per_gas,
+                tx.value,
+            ),
+        };
+
+        if self.recover_signer().is_none() {
+            return Err(TxError::InvalidSignature);
+        }
+
+        let expected = sb.state.chain_id.get_value();
+        if let Some(got) = chain_id.filter(|id| *id != expected) {
+            return Err(TxError::InvalidChainId { expected, got });
+        }
+
+        if gas_price
+            .checked_mul(gas_limit)
+            .and_then(|fee_limit| fee_limit.checked_add(value))
+            .is_none()
+        {
+            return Err(TxError::ValueOverflow);
+        }
+
+        let intrinsic = self.intrinsic_gas(sb);
+        if gas_limit < U256::from(intrinsic) {
+            return Err(TxError::IntrinsicGasTooLow {
+                intrinsic,
+                gas_limit,
+            });
+        }
+
+        let block_gas_limit = sb.state.evm.block_gas_limit.get_value();
+        if gas_limit > block_gas_limit {
+            return Err(TxError::GasLimitExceedsBlock {
+                gas_limit,
+                block_gas_limit,
+            });
+        }
+
+        Ok(())
+    }
+
     // 0. ensure the given gas price is big enough
     // 1. verify the transaction signature
     // 2. ensure the transaction nonce is bigger than the last nonce
@@ -56,32 +118,62 @@ impl Tx {
         &self,
         sb: &mut StateBranch,
         b: BranchName,
-    ) -> Result<(H160, OvrAccount, U256)> {
+    ) -> StdResult<(H160, OvrAccount, U256), TxError> {
         // {0.}
-        let gas_price = self.check_gas_price(sb, b).c(d!())?;
+        let gas_price = self.check_gas_price(sb, b)?;
 
         // {1.} if success, then the transaction signature is valid.
-        let addr = self.recover_signer().c(d!())?;
+        let addr = self.recover_signer().ok_or(TxError::InvalidSignature)?;
 
         // {2.}
-        if let Err((tx_nonce, system_nonce)) = self.check_nonce(&addr, sb, b) {
-            return Err(eg!(
-                "Invalid nonce: {}, should be: {}",
-                tx_nonce,
-                system_nonce
-            ));
+        if let Err((got, expected)) = self.check_nonce(&addr, sb, b) {
+            return Err(if got < expected {
+                TxError::NonceTooLow { expected, got }
+            } else {
+                TxError::NonceTooHigh { expected, got }
+            });
         }
 
         // {3.}{4.}
-        match self.check_balance(&addr, gas_price, sb, b) {
-            Ok((account, _)) => Ok((addr, account, gas_price)),
-            Err(Some((account, needed_amount))) => Err(eg!(
-                "Insufficient balance, needed: {}, total: {}",
-                needed_amount,
-                account.balance
-            )),
-            Err(_) => Err(eg!()),
-        }
+        let (account, _) = self.check_balance(&addr, gas_price, sb, b)?;
+        Ok((addr, account, gas_price))
+    }
+
+    // The gas charged before the first opcode, decided by the fork in use.
+    fn intrinsic_gas(&self, sb: &StateBranch) -> u64 {
+        let (action, input, access_list) = match &self.tx {
+            TransactionAny::Legacy(tx) => (tx.action, &tx.input, &[][..]),
+            TransactionAny::EIP2930(tx) => {
+                (tx.action, &tx.input, tx.access_list.as_slice())
+            }
+            TransactionAny::EIP1559(tx) => {
+                (tx.action, &tx.input, tx.access_list.as_slice())
+            }
+        };
+
+        let cfg = sb
+            .state
+            .evm
+            .fork_schedule
+            .evm_cfg_at(sb.block_in_process_height());
+
+        let base = match action {
+            TransactionAction::Call(_) => cfg.gas_transaction_call,
+            TransactionAction::Create => cfg.gas_transaction_create,
+        };
+        let zeros = input.iter().filter(|b| 0 == **b).count() as u64;
+        let non_zeros = input.len() as u64 - zeros;
+        let slots = access_list
+            .iter()
+            .map(|a| a.slots.len() as u64)
+            .sum::<u64>();
+
+        base.saturating_add(zeros.saturating_mul(cfg.gas_transaction_zero_data))
+            .saturating_add(non_zeros.saturating_mul(cfg.gas_transaction_non_zero_data))
+            .saturating_add(
+                (access_list.len() as u64).saturating_mul(cfg.gas_access_list_address),
+            )
+            .saturating_add(slots.saturating_mul(cfg.gas_access_list_storage_key))
     }
 
     // Support:
@@ -96,19 +188,32 @@ impl Tx {
         gas_price: U256,
         estimate: bool,
     ) -> ExecRet {
-        let mut evm_cfg = EvmCfg::istanbul();
+        let height = sb.block_in_process_height();
+        let mut evm_cfg = sb.state.evm.fork_schedule.evm_cfg_at(height);
         alt!(estimate, evm_cfg.estimate = true);
 
         let metadata = StackSubstateMetadata::new(u64::MAX, &evm_cfg);
-        let mut backend = sb.state.evm.get_backend_hdr(b);
-        let state = OvrStackState::new(metadata, &backend);
-
-        let precompiles = PRECOMPILE_SET.clone();
+        let fee_limit = gas_price.saturating_mul(self.gas_limit());
+        let mut backend = sb
+            .state
+            .evm
+            .get_backend_hdr(b)
+            .with_cache(&sb.cache)
+            .with_fee_reserved(addr, fee_limit);
+        let params_admin = sb.state.params_admin.get_value_by_branch(b);
+        let precompiles = OvrPrecompileSet::new(
+            &sb.state.evm.fork_schedule.precompiles_at(height),
+            Some((&sb.state.staking, b)),
+            Some(sb.block_in_process_consensus()),
+            params_admin,
+        );
+        let state = OvrStackState::new(metadata, &backend)
+            .with_staking(precompiles.staking_journal());
         let mut executor =
             StackExecutor::new_with_precompiles(state, &evm_cfg, &precompiles);
 
         let contract_addr;
-        let (exit_reason, extra_data) = match self.tx {
+        let (mut exit_reason, extra_data) = match self.tx {
             TransactionAny::Legacy(tx) => {
                 let gas_limit = tx.gas_limit.try_into().unwrap_or(u64::MAX);
                 match tx.action {
@@ -194,10 +299,26 @@ impl Tx {
         };
 
         let gas_used = U256::from(executor.used_gas());
-        let success = matches!(exit_reason, ExitReason::Succeed(_));
         let (changes, logs) = executor.into_state().deconstruct();
+
+        // checked before anything is applied
+        let payout_failed = precompiles.staking_journal().map(|j| j.failed());
+        if Some(true) == payout_failed {
+            let e = ExitFatal::Other("insufficient staking pool".into());
+            exit_reason = ExitReason::Fatal(e);
+        }
+
+        let success = matches!(exit_reason, ExitReason::Succeed(_));
         if success {
             backend.apply(changes, logs.clone(), false);
+            // the operations have been checked in the execution,
+            // so only a broken storage can fail them
+            if let Some(s) = precompiles.staking() {
+                halt!(s.settle(&logs));
+            }
+            if let Some(p) = precompiles.params() {
+                halt!(p.settle(&logs, &sb.state));
+            }
         } else {
             backend.apply(
                 Vec::<Apply<BTreeMap<H256, H256>>>::new(),
@@ -219,7 +340,11 @@ impl Tx {
     }
 
     #[inline(always)]
-    fn check_gas_price(&self, sb: &StateBranch, b: BranchName) -> Result<U256> {
+    fn check_gas_price(
+        &self,
+        sb: &StateBranch,
+        b: BranchName,
+    ) -> StdResult<U256, TxError> {
         let gas_price_min = sb
             .state
             .evm
@@ -227,16 +352,14 @@ impl Tx {
             .get_value_by_branch(b)
             .unwrap_or(*GAS_PRICE_MIN);
 
-        let gas_price = match &self.tx {
-            TransactionAny::Legacy(tx) => tx.gas_price,
-            TransactionAny::EIP2930(tx) => tx.gas_price,
-            TransactionAny::EIP1559(tx) => tx.max_fee_per_gas,
-        };
-
+        let gas_price = self.gas_price();
         if gas_price_min <= gas_price {
             Ok(gas_price)
         } else {
-            Err(eg!("Gas price is too low"))
+            Err(TxError::GasPriceTooLow {
+                min: gas_price_min,
+                got: gas_price,
+            })
         }
     }
 
@@ -246,7 +369,7 @@ impl Tx {
         gas_price: U256,
         sb: &StateBranch,
         b: BranchName,
-    ) -> StdResult<(OvrAccount, NeededAmount), Option<(OvrAccount, NeededAmount)>> {
+    ) -> StdResult<(OvrAccount, NeededAmount), TxError> {
         let (transfer_value, gas_limit) = match &self.tx {
             TransactionAny::Legacy(tx) => (tx.value, tx.gas_limit),
             TransactionAny::EIP2930(tx) => (tx.value, tx.gas_limit),
@@ -254,26 +377,31 @@ impl Tx {
         };
 
         if gas_limit.is_zero() {
-            return Err(None);
+            return Err(TxError::IntrinsicGasTooLow {
+                intrinsic: self.intrinsic_gas(sb),
+                gas_limit,
+            });
         }
 
         let needed_amount = gas_price
             .checked_mul(gas_limit)
             .and_then(|fee_limit| transfer_value.checked_add(fee_limit))
-            .ok_or(None)?;
+            .ok_or(TxError::ValueOverflow)?;
 
-        let account = sb
-            .state
-            .evm
-            .OFUEL
-            .accounts
-            .get_by_branch(addr, b)
-            .unwrap_or_default();
+        let token = &sb.state.evm.OFUEL;
+        let account = token.accounts.get_by_branch(addr, b).unwrap_or_default();
+
+        // only the vested part can be spent
+        let locked = token.locked_by_branch(addr, b, sb.block_in_process_timestamp());
+        let balance = account.balance.saturating_sub(locked);
 
-        if needed_amount <= account.balance {
+        if needed_amount <= balance {
             Ok((account, needed_amount))
         } else {
-            Err(Some((account, needed_amount)))
+            Err(TxError::InsufficientFunds {
+                needed: needed_amount,
+                balance,
+            })
         }
     }
 
@@ -284,11 +412,7 @@ impl Tx {
         sb: &StateBranch,
         b: BranchName,
     ) -> StdResult<(), (U256, U256)> {
-        let tx_nonce = match &self.tx {
-            TransactionAny::Legacy(tx) => tx.nonce,
-            TransactionAny::EIP2930(tx) => tx.nonce,
-            TransactionAny::EIP1559(tx) => tx.nonce,
-        };
+        let tx_nonce = self.nonce();
 
         let system_nonce = sb
             .state
@@ -347,6 +471,35 @@ impl Tx {
         sp_io::crypto::secp256k1_ecdsa_recover(&sig, &msg).ok()
     }
 
+    #[inline(always)]
+    pub fn nonce(&self) -> U256 {
+        match &self.tx {
+            TransactionAny::Legacy(tx) => tx.nonce,
+            TransactionAny::EIP2930(tx) => tx.nonce,
+            TransactionAny::EIP1559(tx) => tx.nonce,
+        }
+    }
+
+    #[inline(always)]
+    pub fn gas_limit(&self) -> U256 {
+        match &self.tx {
+            TransactionAny::Legacy(tx) => tx.gas_limit,
+            TransactionAny::EIP2930(tx) => tx.gas_limit,
+            TransactionAny::EIP1559(tx) => tx.gas_limit,
+        }
+    }
+
+    // The price actually charged for each unit of gas,
+    // there is no base fee to be burned.
+    #[inline(always)]
+    pub fn gas_price(&self) -> U256 {
+        match &self.tx {
+            TransactionAny::Legacy(tx) => tx.gas_price,
+            TransactionAny::EIP2930(tx) => tx.gas_price,
+            TransactionAny::EIP1559(tx) => tx.max_fee_per_gas,
+        }
+    }
+
     pub fn get_from_to(&self) -> (Option<H160>, Option<H160>) {
         let from = self.recover_signer();
         let to = match &self.tx {
@@ -469,6 +622,17 @@ impl ExecRet {
         }
         v
     }
+
+    // Why an executed transaction failed.
+    pub fn to_error(&self) -> TxError {
+        match &self.exit_reason {
+            ExitReason::Revert(_) => TxError::Reverted(revert_reason(&self.extra_data)),
+            ExitReason::Error(ExitError::OutOfGas) => TxError::OutOfGas,
+            ExitReason::Error(e) => TxError::EvmError(format!("{:?}", e)),
+            ExitReason::Fatal(e) => TxError::EvmError(format!("{:?}", e)),
+            ExitReason::Succeed(_) => TxError::Internal("not failed".to_owned()),
+        }
+    }
 }
 
 impl fmt::Display for ExecRet {
@@ -490,27 +654,48 @@ pub struct TxCommonProperties {
     pub v: u8,
 }
 
+// Decode the `Error(string)` payload of a revert, empty if it is not one.
+fn revert_reason(data: &[u8]) -> String {
+    const SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+    if data.len() < 4 + 64 || data[..4] != SELECTOR {
+        return String::new();
+    }
+    let body = &data[4..];
+    let len = U256::from_big_endian(&body[32..64]);
+    if len > U256::from(body.len() - 64) {
+        return String::new();
+    }
+    String::from_utf8_lossy(&body[64..64 + len.as_usize()]).into_owned()
+}
+
 pub fn inital_create2(
     contract: InitalContract,
     state: &super::State,
     b: BranchName<'_>,
-) -> Result<()> {
-    let evm_cfg = EvmCfg::istanbul();
+) -> Result<H160> {
+    let gas_limit = contract.gas_limit.unwrap_or_else(|| {
+        let limit = state.block_gas_limit.get_value();
+        limit.min(U256::from(u64::MAX)).as_u64()
+    });
+
+    // contracts are created in the genesis block
+    let evm_cfg = state.fork_schedule.evm_cfg_at(0);
 
-    let metadata = StackSubstateMetadata::new(u64::MAX, &evm_cfg);
+    let precompiles =
+        OvrPrecompileSet::new(&state.fork_schedule.precompiles_at(0), None, None, None);
+
+    let metadata = StackSubstateMetadata::new(gas_limit, &evm_cfg);
     let mut backend = state.get_backend_hdr(b);
     let state = OvrStackState::new(metadata, &backend);
 
-    let precompiles = PRECOMPILE_SET.clone();
     let mut executor =
         StackExecutor::new_with_precompiles(state, &evm_cfg, &precompiles);
 
-    let bytecode_hex = &contract.bytecode[2..].trim();
+    let mut bytecode = parse_hex(&contract.bytecode).c(d!())?;
+    bytecode.append(&mut parse_hex(&contract.constructor_args).c(d!())?);
 
-    // parse hex.
-    let bytecode = hex::decode(bytecode_hex).c(d!())?;
     // get salt.
-    let salt = H256::from_slice(&Keccak256::digest(contract.salt));
+    let salt = H256::from_slice(&Keccak256::digest(&contract.salt));
 
     let code_hash = H256::from_slice(&Keccak256::digest(&bytecode));
 
@@ -520,24 +705,78 @@ pub fn inital_create2(
         code_hash,
     });
 
-    println!("OVR contract address is : {:?}", contract_addr);
-
     let exit_reason = executor.transact_create2(
         contract.from,
-        U256::from(0u64),
+        contract.value,
         bytecode,
         salt,
-        800000000,
+        gas_limit,
         vec![],
     );
 
-    let success = matches!(exit_reason, ExitReason::Succeed(_));
-    if success {
+    if let ExitReason::Succeed(_) = exit_reason {
         let (changes, logs) = executor.into_state().deconstruct();
         backend.apply(changes, logs, false);
     } else {
-        return Err(eg!("inital create false."));
+        return Err(eg!(
+            "failed to create the inital contract '{}': {:?}",
+            contract.name,
+            exit_reason
+        ));
     }
 
+    Ok(contract_addr)
+}
+
+pub fn inital_account(
+    account: InitalAccount,
+    state: &super::State,
+    b: BranchName<'_>,
+) -> Result<()> {
+    let code = parse_hex(&account.code).c(d!())?;
+
+    let mut backend = state.get_backend_hdr(b);
+    let apply = Apply::Modify {
+        address: account.address,
+        basic: Basic {
+            balance: account.balance,
+            nonce: account.nonce,
+        },
+        code: alt!(code.is_empty(), None, Some(code)),
+        storage: account.storage,
+        reset_storage: true,
+    };
+    backend.apply(vec![apply], vec![], false);
+
     Ok(())
 }
+
+pub fn inital_vesting(
+    vesting: InitalVesting,
+    state: &super::State,
+    b: BranchName<'_>,
+) -> Result<()> {
+    let token = &state.OFUEL;
+    let addr = vesting.address;
+
+    if token.vesting.contains_key_by_branch(&addr, b) {
+        return Err(eg!("duplicate vesting account: {:?}", addr));
+    }
+    let schedule =
+        VestingSchedule::new(vesting.total, vesting.start, vesting.cliff, vesting.end)
+            .c(d!())?;
+
+    let mut account = token.accounts.get_by_branch(&addr, b).unwrap_or_default();
+    account.balance = account
+        .balance
+        .checked_add(vesting.total)
+        .c(d!("balance overflow"))?;
+
+    token.accounts.insert_by_branch(addr, account, b).c(d!())?;
+    token.vesting.insert_by_branch(addr, schedule, b).c(d!())
+}
+
+#[inline(always)]
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    hex::decode(s.trim().trim_start_matches("0x")).c(d!())
+}
@@ -1,9 +1,9 @@
-use crate::ethvm::{precompile::idx_to_h160, OvrAccount};
+use crate::ethvm::{precompile::idx_to_h160, vesting::VestingSchedule, OvrAccount};
 use primitive_types::{H160, H256, U256};
 use ruc::*;
 use serde::{Deserialize, Serialize};
 use slices::u8_slice;
-use vsdb::{MapxDkVs, MapxVs, OrphanVs, Vs};
+use vsdb::{BranchName, MapxDkVs, MapxVs, OrphanVs, Vs};
 
 pub const DECIMAL: u32 = 18;
 
@@ -23,8 +23,16 @@ pub struct Erc20Like {
     // (addr, key) => value
     pub storages: MapxDkVs<H160, H256, H256>,
 
+    // code hash => code,
+    // shared by all contracts with the same code
+    pub codes: MapxVs<H256, Vec<u8>>,
+
     // (owner addr, spender addr) => amount
     pub allowances: MapxVs<(H160, H160), U256>,
+
+    // set in the genesis, never changed after that
+    #[serde(default)]
+    pub vesting: MapxVs<H160, VestingSchedule>,
 }
 
 impl Erc20Like {
@@ -44,11 +52,22 @@ impl Erc20Like {
             total_supply: OrphanVs::new(0u8.into()),
             accounts: MapxVs::new(),
             storages: MapxDkVs::new(),
+            codes: MapxVs::new(),
             allowances: MapxVs::new(),
+            vesting: MapxVs::new(),
             contract_addr,
         }
     }
 
+    /// The part of the balance that can not be spent at the time of `now`.
+    #[inline(always)]
+    pub fn locked_by_branch(&self, addr: &H160, b: BranchName, now: u64) -> U256 {
+        self.vesting
+            .get_by_branch(addr, b)
+            .map(|v| v.locked(now))
+            .unwrap_or_default()
+    }
+
     #[inline(always)]
     pub fn ofuel_token() -> Self {
         let name: &[u8; 96] = u8_slice!(
@@ -0,0 +1,62 @@
+//!
+//! # Vesting of the genesis allocations
+//!
+//! The whole amount is credited to the balance at the genesis,
+//! the locked part is hidden from the EVM and can not be spent.
+//!
+//! Nothing is vested before the cliff, then the amount is released
+//! linearly from the start to the end, all of the timestamps are in seconds.
+//!
+
+use primitive_types::U256;
+use ruc::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VestingSchedule {
+    pub total: U256,
+    pub start: u64,
+    pub cliff: u64,
+    pub end: u64,
+}
+
+impl VestingSchedule {
+    pub fn new(total: U256, start: u64, cliff: u64, end: u64) -> Result<Self> {
+        if start > cliff || cliff > end {
+            return Err(eg!(
+                "invalid vesting schedule, start: {}, cliff: {}, end: {}",
+                start,
+                cliff,
+                end
+            ));
+        }
+        Ok(Self {
+            total,
+            start,
+            cliff,
+            end,
+        })
+    }
+
+    /// The released amount at the time of `now`.
+    pub fn vested(&self, now: u64) -> U256 {
+        if now < self.cliff {
+            return U256::zero();
+        }
+        if now >= self.end {
+            return self.total;
+        }
+
+        // `start <= cliff <= now < end`
+        let elapsed = U256::from(now - self.start);
+        let duration = U256::from(self.end - self.start);
+        (self.total.full_mul(elapsed) / duration)
+            .try_into()
+            .unwrap_or(self.total)
+    }
+
+    #[inline(always)]
+    pub fn locked(&self, now: u64) -> U256 {
+        self.total.saturating_sub(self.vested(now))
+    }
+}
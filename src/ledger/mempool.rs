@@ -0,0 +1,333 @@
+//!
+//! # Pending transactions of the 'CheckTx' branch
+//!
+//! The 'CheckTx' branch is rebuilt from the 'main' branch at each block,
+//! then the pending transactions are replayed on it in the order of arrival,
+//! so the nonces of the following ones keep growing from the right place.
+//!
+//! A transaction whose nonce is a little ahead of the expected one is queued,
+//! not applied to the branch, and promoted once the missing nonces are filled.
+//! The queued ones have negative priorities, the nearest nonce first,
+//! so tendermint proposes them after the pending ones, and they are dropped
+//! by the recheck after the next block if they are still waiting.
+//!
+//! A transaction can be replaced by another one with the same sender and nonce,
+//! if the gas price of the new one is high enough, each replacement replays
+//! the whole mempool, so the number of them is limited in each block.
+//!
+//! The priority of a transaction is the lowest gas price of its sender
+//! up to its nonce, so tendermint never proposes the nonces out of order.
+//!
+//! Tendermint evicts transactions without telling the app (TTL, full mempool),
+//! so the pending ones that have not been rechecked since the last block
+//! are dropped at the next one, and can be replaced freely until then.
+//! The recheck of tendermint must be enabled.
+//!
+
+use super::{StateBranch, CHECK_TX_BRANCH_NAME};
+use crate::{
+    common::{halt, HashValue},
+    tx::{error::TxError, Tx},
+};
+use primitive_types::{H160, U256};
+use ruc::*;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
+    result::Result as StdResult,
+};
+use vsdb::VsMgmt;
+
+/// The minimal gas price bump of a replacement, in percent.
+pub const MIN_PRICE_BUMP: u64 = 10;
+
+/// The maximal number of replacements between two blocks.
+pub const MAX_REPLACEMENTS_PER_BLOCK: usize = 64;
+
+/// How far a queued nonce can be ahead of the expected one,
+/// so no more transactions of a sender can be queued.
+pub const MAX_NONCE_GAP: u64 = 16;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Mempool {
+    // applied on the branch, in the order of arrival
+    pending: Vec<Entry>,
+    // waiting for the missing nonces, by sender and nonce
+    queued: HashMap<H160, BTreeMap<U256, Entry>>,
+    // checked or rechecked since the last block,
+    // the other pending ones have been evicted by tendermint
+    confirmed: HashSet<HashValue>,
+    // rejected by the last replay
+    dropped: HashMap<HashValue, TxError>,
+    // since the last block
+    replacements: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    tx_hash: HashValue,
+    tx: Tx,
+    sender: Option<H160>,
+    nonce: U256,
+    gas_price: U256,
+}
+
+impl Entry {
+    fn new(tx: Tx) -> Self {
+        let (sender, nonce, gas_price) = match &tx {
+            Tx::Evm(tx) => (tx.get_from_to().0, tx.nonce(), tx.gas_price()),
+            Tx::Native(_) => (None, U256::zero(), U256::zero()),
+        };
+        Self {
+            tx_hash: tx.hash(),
+            tx,
+            sender,
+            nonce,
+            gas_price,
+        }
+    }
+
+    // Can `self` take the place of `old`?
+    fn replace(&self, old: &Entry) -> StdResult<(), TxError> {
+        let min = old
+            .gas_price
+            .saturating_mul(U256::from(100 + MIN_PRICE_BUMP))
+            / 100;
+        if self.gas_price < min || self.gas_price == old.gas_price {
+            return Err(TxError::ReplacementUnderpriced {
+                min,
+                got: self.gas_price,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Mempool {
+    fn contains(&self, tx_hash: &HashValue) -> bool {
+        self.pending.iter().any(|e| &e.tx_hash == tx_hash)
+    }
+
+    fn queued(&self, tx_hash: &HashValue) -> Option<&Entry> {
+        self.queued
+            .values()
+            .flat_map(|q| q.values())
+            .find(|e| &e.tx_hash == tx_hash)
+    }
+
+    fn take_queued(&mut self, tx_hash: &HashValue) -> Option<Entry> {
+        let (sender, nonce) = self.queued(tx_hash).map(|e| (e.sender, e.nonce))?;
+        let queue = self.queued.get_mut(sender.as_ref()?)?;
+        let entry = queue.remove(&nonce);
+        if queue.is_empty() {
+            self.queued.remove(sender.as_ref()?);
+        }
+        entry
+    }
+}
+
+impl StateBranch {
+    /// Apply a new transaction of the mempool.
+    #[inline(always)]
+    pub(crate) fn check_tx(&mut self, tx: Tx) -> StdResult<(), TxError> {
+        self.admit(Entry::new(tx), true)
+    }
+
+    // `is_new` is false for the replayed ones.
+    fn admit(&mut self, entry: Entry, is_new: bool) -> StdResult<(), TxError> {
+        match self.apply_tx(entry.tx.clone()) {
+            Ok(()) => {
+                if is_new {
+                    self.mempool.confirmed.insert(entry.tx_hash.clone());
+                }
+                let sender = entry.sender;
+                self.mempool.pending.push(entry);
+                if let Some(sender) = sender.filter(|_| is_new) {
+                    self.promote(sender);
+                }
+                Ok(())
+            }
+            Err(TxError::NonceTooHigh { expected, got })
+                if is_new && got - expected <= U256::from(MAX_NONCE_GAP) =>
+            {
+                // the signature has been verified before the nonce
+                let sender = entry.sender.ok_or(TxError::InvalidSignature)?;
+                let queue = self.mempool.queued.entry(sender).or_default();
+                match queue.get(&got) {
+                    // resubmitted, only an evicted one is taken back
+                    Some(old) if old.tx_hash == entry.tx_hash => {
+                        let e = TxError::NonceTooHigh { expected, got };
+                        let evicted = self.mempool.confirmed.insert(entry.tx_hash);
+                        return alt!(evicted, Ok(()), Err(e));
+                    }
+                    Some(old) => entry.replace(old)?,
+                    None => {}
+                }
+                self.mempool.confirmed.insert(entry.tx_hash.clone());
+                queue.insert(got, entry);
+                Ok(())
+            }
+            Err(e @ TxError::NonceTooLow { .. }) if is_new => {
+                let old = self.mempool.pending.iter().position(|old| {
+                    old.sender.is_some()
+                        && old.sender == entry.sender
+                        && old.nonce == entry.nonce
+                });
+                match old {
+                    // resubmitted, only an evicted one is taken back
+                    Some(idx) if self.mempool.pending[idx].tx_hash == entry.tx_hash => {
+                        alt!(
+                            self.mempool.confirmed.insert(entry.tx_hash),
+                            Ok(()),
+                            Err(e)
+                        )
+                    }
+                    Some(idx) => {
+                        if self.mempool.replacements >= MAX_REPLACEMENTS_PER_BLOCK {
+                            return Err(TxError::TooManyReplacements {
+                                max: MAX_REPLACEMENTS_PER_BLOCK,
+                            });
+                        }
+                        let old = &self.mempool.pending[idx];
+                        // an evicted one is not a competitor
+                        if self.mempool.confirmed.contains(&old.tx_hash) {
+                            entry.replace(old)?;
+                        }
+                        let tx_hash = entry.tx_hash.clone();
+                        self.mempool.confirmed.insert(tx_hash.clone());
+                        self.mempool.pending[idx] = entry;
+                        self.mempool.replacements += 1;
+                        self.rebuild();
+                        match self.mempool.dropped.remove(&tx_hash) {
+                            Some(e) => Err(e),
+                            None => Ok(()),
+                        }
+                    }
+                    None => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The priority in the mempool of tendermint, higher first,
+    /// `0` if the transaction is not pending, negative if it is queued.
+    pub(crate) fn priority(&self, tx_hash: &HashValue) -> i64 {
+        if let Some(entry) = self.mempool.queued(tx_hash) {
+            let expected = entry.sender.map(|s| self.nonce_of(s)).unwrap_or_default();
+            let gap = entry.nonce.saturating_sub(expected);
+            return -(gap.min(U256::from(MAX_NONCE_GAP)).as_u64() as i64);
+        }
+
+        let pending = &self.mempool.pending;
+        let price = match pending.iter().find(|e| &e.tx_hash == tx_hash) {
+            Some(entry) if entry.sender.is_some() => pending
+                .iter()
+                .filter(|e| e.sender == entry.sender && e.nonce <= entry.nonce)
+                .map(|e| e.gas_price)
+                .min()
+                .unwrap_or(entry.gas_price),
+            Some(entry) => entry.gas_price,
+            None => U256::zero(),
+        };
+        price.min(U256::from(i64::MAX)).as_u64() as i64
+    }
+
+    /// Tendermint rechecks the remaining transactions after each block,
+    /// the answer comes from the replay, or from a new check if it is unknown.
+    pub(crate) fn recheck_tx(&mut self, tx: Tx) -> StdResult<(), TxError> {
+        let tx_hash = tx.hash();
+        if self.mempool.contains(&tx_hash) {
+            self.mempool.confirmed.insert(tx_hash);
+            return Ok(());
+        }
+        // still waiting after a block, the missing nonces may never come
+        if let Some(entry) = self.mempool.take_queued(&tx_hash) {
+            return self.admit(entry, false);
+        }
+        match self.mempool.dropped.remove(&tx_hash) {
+            Some(e) => Err(e),
+            None => self.check_tx(tx),
+        }
+    }
+
+    // Rebuild the branch of the block in process,
+    // the replaced transaction leaves no trace in it.
+    fn rebuild(&mut self) {
+        halt!(self.state.refresh_check_tx_branch());
+        halt!(self.state.branch_set_default(CHECK_TX_BRANCH_NAME));
+        self.cache.clear();
+
+        let consensus = self.block_in_process.consensus.clone();
+        halt!(self.prepare_next_block(consensus));
+        self.replay_mempool();
+    }
+
+    // Apply the queued transactions whose nonces are not ahead anymore.
+    fn promote(&mut self, sender: H160) {
+        while let Some(mut queue) = self.mempool.queued.remove(&sender) {
+            // the included ones are left to the recheck
+            let mut next = queue.split_off(&self.nonce_of(sender));
+            let entry = match next.keys().next().copied() {
+                Some(n) if n == self.nonce_of(sender) => next.remove(&n),
+                _ => None,
+            };
+            queue.append(&mut next);
+            if !queue.is_empty() {
+                self.mempool.queued.insert(sender, queue);
+            }
+
+            let entry = match entry {
+                Some(entry) => entry,
+                None => return,
+            };
+            let tx_hash = entry.tx_hash.clone();
+            if let Err(e) = self.admit(entry, false) {
+                self.mempool.dropped.insert(tx_hash, e);
+                return;
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn nonce_of(&self, sender: H160) -> U256 {
+        self.state
+            .evm
+            .OFUEL
+            .accounts
+            .get_by_branch(&sender, self.branch.as_slice().into())
+            .map(|a| a.nonce)
+            .unwrap_or_default()
+    }
+
+    // Called after the branch has been rebuilt.
+    pub(super) fn replay_mempool(&mut self) {
+        let pending = mem::take(&mut self.mempool.pending);
+        self.mempool.dropped.clear();
+
+        for entry in pending {
+            let tx_hash = entry.tx_hash.clone();
+            if let Err(e) = self.admit(entry, false) {
+                self.mempool.dropped.insert(tx_hash, e);
+            }
+        }
+
+        // the missing nonces may have been included by the block
+        let senders = self.mempool.queued.keys().copied().collect::<Vec<_>>();
+        senders.into_iter().for_each(|s| self.promote(s));
+    }
+
+    // Drop the pending transactions evicted by tendermint,
+    // called after a block has been committed, before the replay.
+    pub(super) fn prune_mempool(&mut self) {
+        self.mempool.replacements = 0;
+        let confirmed = mem::take(&mut self.mempool.confirmed);
+        self.mempool
+            .pending
+            .retain(|e| confirmed.contains(&e.tx_hash));
+        self.mempool.queued.retain(|_, q| {
+            q.retain(|_, e| confirmed.contains(&e.tx_hash));
+            !q.is_empty()
+        });
+    }
+}
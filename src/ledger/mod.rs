@@ -2,25 +2,33 @@
 //! # Ledger, world state
 //!
 
+mod mempool;
+mod parallel;
 pub mod staking;
+pub mod sync;
+pub mod view;
 
 use crate::common::handle_bloom;
 use crate::{
     common::{
-        block_hash_to_evm_format, hash_sha3_256, tm_proposer_to_evm_format, BlockHeight,
-        HashValue, HashValueRef, TmAddress, TmAddressRef,
+        block_hash_to_evm_format, halt, hash_sha3_256, tm_proposer_to_evm_format,
+        BlockHeight, GenesisContract, HashValue, HashValueRef, TmAddress, TmAddressRef,
     },
-    ethvm::{self, tx::GAS_PRICE_MIN},
-    tx::Tx,
+    ethvm::{self, impls::cache::BackendCache, tx::GAS_PRICE_MIN},
+    tx::{error::TxError, Tx},
 };
 use ethereum::Log as EthLog;
 use ethereum_types::Bloom;
+use mempool::Mempool;
 use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 use primitive_types::{H160, H256, U256};
 use ruc::*;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fs, io::ErrorKind, mem, sync::Arc};
+use std::{
+    collections::BTreeMap, fs, io::ErrorKind, mem, result::Result as StdResult,
+    sync::Arc,
+};
 use vsdb::{
     merkle::{MerkleTree, MerkleTreeStore},
     BranchName, MapxOrd, OrphanVs, ParentBranchName, ValueEn, ValueEnDe, Vecx, Vs,
@@ -37,6 +45,19 @@ static LEDGER_SNAPSHOT_PATH: Lazy<String> = Lazy::new(|| {
     dir + "/ledger.json"
 });
 
+static LEDGER_LAYOUT_PATH: Lazy<String> =
+    Lazy::new(|| LEDGER_SNAPSHOT_PATH.replace("ledger.json", "layout"));
+
+/// Version of the layout of the stored data,
+/// bump it on any change of the encoding of the values in vsdb,
+/// the data of an older layout can not be loaded.
+///
+/// - 0: contract code inline in the accounts
+/// - 1: contract code stored by its hash, out of the accounts
+/// - 2: registered validators of the staking
+/// - 3: state hash in the block headers
+pub const STORAGE_LAYOUT_VERSION: u64 = 3;
+
 #[derive(Clone, Debug)]
 pub struct Ledger {
     // used for web3 APIs
@@ -82,6 +103,10 @@ impl Ledger {
             .block_base_fee_per_gas
             .set_value(block_base_fee_per_gas.unwrap_or_default().into())
             .c(d!())?;
+        state
+            .consensus_params
+            .set_value(ConsensusParams::default())
+            .c(d!())?;
 
         let main = StateBranch::new(&state, MAIN_BRANCH_NAME).c(d!())?;
 
@@ -100,21 +125,16 @@ impl Ledger {
     }
 
     #[inline(always)]
-    pub fn consensus_refresh(&self, proposer: TmAddress, timestamp: u64) -> Result<()> {
-        self.refresh_inner(proposer, timestamp, false).c(d!())
+    pub fn consensus_refresh(&self, consensus: ConsensusInfo) -> Result<()> {
+        self.refresh_inner(consensus, false).c(d!())
     }
 
     #[inline(always)]
     fn loading_refresh(&self) -> Result<()> {
-        self.refresh_inner(Default::default(), Default::default(), true)
+        self.refresh_inner(Default::default(), true)
     }
 
-    fn refresh_inner(
-        &self,
-        proposer: TmAddress,
-        timestamp: u64,
-        is_loading: bool,
-    ) -> Result<()> {
+    fn refresh_inner(&self, consensus: ConsensusInfo, is_loading: bool) -> Result<()> {
         let mut main = self.main.write();
         let mut deliver_tx = self.deliver_tx.write();
         let mut check_tx = self.check_tx.write();
@@ -126,6 +146,10 @@ impl Ledger {
         // Lock all branches before this operation.
         main.state.refresh_branches().c(d!())?;
 
+        main.cache.clear();
+        deliver_tx.cache.clear();
+        check_tx.cache.clear();
+
         let br = deliver_tx.branch.clone();
         deliver_tx.state = main.state.clone();
         deliver_tx
@@ -141,17 +165,38 @@ impl Ledger {
             .c(d!())?;
 
         if !is_loading {
-            main.prepare_next_block(proposer.clone(), timestamp)
-                .c(d!())?;
-            deliver_tx
-                .prepare_next_block(proposer.clone(), timestamp)
-                .c(d!())?;
-            check_tx.prepare_next_block(proposer, timestamp).c(d!())?;
+            main.prepare_next_block(consensus.clone()).c(d!())?;
+            deliver_tx.prepare_next_block(consensus.clone()).c(d!())?;
+            check_tx.prepare_next_block(consensus).c(d!())?;
+            check_tx.replay_mempool();
         }
 
         Ok(())
     }
 
+    // Rebuild the 'CheckTx' branch on the new block,
+    // so the rechecks of tendermint see the right nonces.
+    fn check_tx_refresh(&self, main: &StateBranch) -> Result<()> {
+        let mut check_tx = self.check_tx.write();
+
+        main.state.refresh_check_tx_branch().c(d!())?;
+        check_tx.cache.clear();
+
+        let br = check_tx.branch.clone();
+        check_tx.state = main.state.clone();
+        check_tx
+            .state
+            .branch_set_default(br.as_slice().into())
+            .c(d!())?;
+
+        let consensus = main.last_block().map(|b| b.consensus).unwrap_or_default();
+        check_tx.prepare_next_block(consensus).c(d!())?;
+        check_tx.prune_mempool();
+        check_tx.replay_mempool();
+
+        Ok(())
+    }
+
     #[inline(always)]
     pub fn commit(&self) -> Result<HashValue> {
         let mut main = self.main.write();
@@ -166,8 +211,12 @@ impl Ledger {
             self.state
                 .branch_merge_to_parent(DELIVER_TX_BRANCH_NAME)
                 .c(d!())?;
+            deliver_tx.cache.clear();
         }
-        main.commit().c(d!()).map(|_| main.last_block_hash())
+        main.cache.clear();
+        main.commit().c(d!())?;
+        self.check_tx_refresh(&main).c(d!())?;
+        Ok(main.last_block_hash())
     }
 
     #[inline(always)]
@@ -199,6 +248,12 @@ pub struct StateBranch {
     pub branch: Vec<u8>,
     tx_hashes_in_process: Vec<HashValue>,
     block_in_process: Block,
+    // Evm read cache of the block in process.
+    #[serde(skip)]
+    pub(crate) cache: BackendCache,
+    // Only used by the 'CheckTx' branch.
+    #[serde(skip)]
+    mempool: Mempool,
 }
 
 impl StateBranch {
@@ -212,20 +267,22 @@ impl StateBranch {
             branch: branch.0.to_owned(),
             tx_hashes_in_process: vec![],
             block_in_process: Block::default(),
+            cache: BackendCache::default(),
+            mempool: Mempool::default(),
         })
     }
 
     // NOTE:
     // - Only triggered by the 'main' branch of `Ledger`
     // - Call this in the 'BeginBlock' field of ABCI
-    fn prepare_next_block(&mut self, proposer: TmAddress, timestamp: u64) -> Result<()> {
+    fn prepare_next_block(&mut self, consensus: ConsensusInfo) -> Result<()> {
         self.tx_hashes_in_process.clear();
 
         let (h, prev_hash) = self
             .last_block()
             .map(|b| (b.header.height, b.header_hash))
             .unwrap_or_default();
-        self.block_in_process = Block::new(1 + h, proposer, timestamp, prev_hash);
+        self.block_in_process = Block::new(1 + h, consensus, prev_hash);
 
         let b = self.branch.clone();
         let b = b.as_slice().into();
@@ -235,9 +292,9 @@ impl StateBranch {
             .version_create_by_branch(ver.encode_value().as_ref().into(), b)
             .c(d!())?;
 
-        if b == MAIN_BRANCH_NAME {
-            self.update_evm_aux(b);
-        }
+        // the vicinity is copied with the state,
+        // each branch must see the block in process
+        self.update_evm_aux(b);
 
         Ok(())
     }
@@ -258,76 +315,120 @@ impl StateBranch {
 
     // Deal with each transaction.
     // Will be used by all the 3 branches of `Ledger`.
-    pub fn apply_tx(&mut self, tx: Tx) -> Result<()> {
+    #[inline(always)]
+    pub fn apply_tx(&mut self, tx: Tx) -> StdResult<(), TxError> {
+        self.apply_tx_with_receipt(tx)
+            .map(|_| ())
+            .map_err(|(e, _)| e)
+    }
+
+    /// Same as `apply_tx`, returns the receipt of the transaction,
+    /// or the gas used by it if it failed in the execution.
+    pub fn apply_tx_with_receipt(
+        &mut self,
+        tx: Tx,
+    ) -> StdResult<Option<Receipt>, (TxError, U256)> {
         let b = self.branch.clone();
         let b = b.as_slice().into();
 
+        halt!(self.tx_version_create(b));
+
+        let tx_hash = tx.hash();
+        let receipt = self.exec_tx(tx.clone(), &tx_hash, b)?;
+        self.push_tx(tx, tx_hash.clone(), receipt);
+
+        Ok(self.block_in_process.header.receipts.get(&tx_hash).cloned())
+    }
+
+    // Each transaction has its own version, named by its position.
+    #[inline(always)]
+    fn tx_version_create(&self, b: BranchName) -> Result<()> {
         let ver = VsVersion::new(
             self.block_in_process.header.height,
             1 + self.tx_hashes_in_process.len() as u64,
         );
         self.state
             .version_create_by_branch(ver.encode_value().as_ref().into(), b)
-            .c(d!())?;
-
-        let tx_hash = tx.hash();
+            .c(d!())
+    }
 
+    // Execute a transaction on the current version of the given branch,
+    // the version will be popped if the transaction fails.
+    fn exec_tx(
+        &mut self,
+        tx: Tx,
+        tx_hash: HashValueRef,
+        b: BranchName,
+    ) -> StdResult<Option<Receipt>, (TxError, U256)> {
         macro_rules! create_version_if_first_tx_failed {
             () => {
                 if !self.state.branch_has_versions(b) {
-                    self.state
-                        .version_create_by_branch(
-                            VsVersion::default().encode_value().as_ref().into(),
-                            b,
-                        )
-                        .c(d!())?;
+                    halt!(self.state.version_create_by_branch(
+                        VsVersion::default().encode_value().as_ref().into(),
+                        b,
+                    ));
                 }
             };
         }
 
-        match tx.clone() {
+        match tx {
             Tx::Evm(evm_tx) => evm_tx
                 .apply(self, b, false)
                 .map(|(ret, mut receipt)| {
                     self.charge_fee(ret.caller, ret.fee_used, b);
-                    self.tx_hashes_in_process.push(tx_hash.clone());
-                    self.block_in_process.txs.push(tx);
 
-                    let mut logs = ret.gen_logs(&tx_hash);
+                    let mut logs = ret.gen_logs(tx_hash);
                     receipt.add_logs(logs.as_mut_slice());
-                    receipt.tx_hash = tx_hash.clone();
-                    receipt.tx_index = self.tx_hashes_in_process.len() as u64 - 1;
-                    self.block_in_process
-                        .header
-                        .receipts
-                        .insert(tx_hash, receipt);
+                    receipt.tx_hash = tx_hash.to_vec();
+                    Some(receipt)
                 })
-                .or_else(|e| {
-                    pnk!(self.state.version_pop_by_branch(b));
-                    if let Some(ret) = e.as_ref() {
+                .or_else(|(e, ret)| {
+                    halt!(self.state.version_pop_by_branch(b));
+                    // the cache may hold values of the popped version
+                    self.cache.clear();
+                    if let Some(ret) = ret.as_ref() {
                         create_version_if_first_tx_failed!();
                         self.charge_fee(ret.caller, ret.fee_used, b);
                     }
-                    Err(eg!(e.map(|e| e.to_string()).unwrap_or_default()))
-                })?,
+                    Err((e, ret.map(|r| r.gas_used).unwrap_or_default()))
+                }),
             Tx::Native(native_tx) => native_tx
                 .apply(self, b)
                 .map(|ret| {
                     self.charge_fee(ret.caller, ret.fee_used, b);
-                    self.tx_hashes_in_process.push(tx_hash);
-                    self.block_in_process.txs.push(tx);
+                    None
                 })
                 .or_else(|e| {
-                    pnk!(self.state.version_pop_by_branch(b));
+                    halt!(self.state.version_pop_by_branch(b));
+                    // the cache may hold values of the popped version
+                    self.cache.clear();
                     if let Some(ret) = e.as_ref() {
                         create_version_if_first_tx_failed!();
                         self.charge_fee(ret.caller, ret.fee_used, b);
                     }
-                    Err(eg!(e.map(|e| e.to_string()).unwrap_or_default()))
-                })?,
-        };
+                    let e = TxError::NativeTxFailed(
+                        e.map(|ret| ret.log)
+                            .unwrap_or_else(|| "not supported".to_owned()),
+                    );
+                    Err((e, U256::zero()))
+                }),
+        }
+    }
 
-        Ok(())
+    // Append an applied transaction to the block in process.
+    fn push_tx(&mut self, tx: Tx, tx_hash: HashValue, receipt: Option<Receipt>) {
+        let tx_index = self.tx_hashes_in_process.len() as u64;
+        self.tx_hashes_in_process.push(tx_hash.clone());
+        self.block_in_process.txs.push(tx);
+
+        if let Some(mut receipt) = receipt {
+            receipt.tx_index = tx_index;
+            receipt.logs.iter_mut().for_each(|l| l.tx_index = tx_index);
+            self.block_in_process
+                .header
+                .receipts
+                .insert(tx_hash, receipt);
+        }
     }
 
     // NOTE:
@@ -366,6 +467,19 @@ impl StateBranch {
         }
 
         self.block_in_process.bloom = b.as_bytes().to_vec();
+
+        let height = self.block_in_process.header.height;
+        let itv = self
+            .state
+            .state_hash_itv
+            .get_value_by_branch(MAIN_BRANCH_NAME)
+            .unwrap_or_default();
+        if 0 < itv && 0 == height % itv {
+            let txs = self.block_in_process.txs.len() as u64;
+            self.block_in_process.header.state_hash =
+                self.state.state_hash(height, txs).c(d!())?;
+        }
+
         self.block_in_process.header_hash = self.block_in_process.header.hash();
 
         let block = mem::take(&mut self.block_in_process);
@@ -375,7 +489,7 @@ impl StateBranch {
             block_hash_to_evm_format(&block.header_hash),
         );
 
-        self.state.blocks.insert(block.header.height, block);
+        self.state.index_block(block);
 
         vsdb::vsdb_flush();
         self.write_snapshot().c(d!())
@@ -383,7 +497,7 @@ impl StateBranch {
 
     fn update_evm_aux(&mut self, b: BranchName) {
         self.state.evm.update_vicinity(
-            U256::from(self.state.chain_id.get_value_by_branch(b).unwrap()),
+            U256::from(halt!(self.state.chain_id.get_value_by_branch(b))),
             tm_proposer_to_evm_format(&self.block_in_process.header.proposer),
             U256::from(self.block_in_process.header.timestamp),
         );
@@ -402,15 +516,17 @@ impl StateBranch {
     #[inline(always)]
     fn charge_fee(&self, caller: H160, amount: U256, b: BranchName) {
         alt!(amount.is_zero(), return);
-        let mut account = pnk!(self.state.evm.OFUEL.accounts.get_by_branch(&caller, b));
+        // nothing can be charged from an absent account
+        let accounts = &self.state.evm.OFUEL.accounts;
+        let mut account = match accounts.get_by_branch(&caller, b) {
+            Some(a) => a,
+            None => return,
+        };
+        // never more than the spendable part,
+        // checked by `check_balance` and reserved during the execution
         account.balance = account.balance.saturating_sub(amount);
-        pnk!(
-            self.state
-                .evm
-                .OFUEL
-                .accounts
-                .insert_by_branch(caller, account, b)
-        );
+        halt!(accounts.insert_by_branch(caller, account.clone(), b));
+        self.cache.set_account(caller, Some(account));
     }
 
     // #[inline(always)]
@@ -423,6 +539,21 @@ impl StateBranch {
         self.state.blocks.last().map(|(_, b)| b)
     }
 
+    #[inline(always)]
+    pub(crate) fn block_in_process_height(&self) -> BlockHeight {
+        self.block_in_process.header.height
+    }
+
+    #[inline(always)]
+    pub(crate) fn block_in_process_timestamp(&self) -> u64 {
+        self.block_in_process.header.timestamp
+    }
+
+    #[inline(always)]
+    pub(crate) fn block_in_process_consensus(&self) -> &ConsensusInfo {
+        &self.block_in_process.consensus
+    }
+
     #[inline(always)]
     fn last_block_height(&self) -> BlockHeight {
         self.state.blocks.last().map(|(h, _)| h).unwrap_or(0)
@@ -436,7 +567,10 @@ impl StateBranch {
     #[inline(always)]
     fn load_from_snapshot() -> Result<Option<Self>> {
         match fs::read(&*LEDGER_SNAPSHOT_PATH) {
-            Ok(c) => StateBranch::decode(c.as_slice()).c(d!()).map(Some),
+            Ok(c) => {
+                check_layout_version().c(d!())?;
+                StateBranch::decode(c.as_slice()).c(d!()).map(Some)
+            }
             Err(e) => {
                 if let ErrorKind::NotFound = e.kind() {
                     Ok(None)
@@ -450,10 +584,30 @@ impl StateBranch {
     #[inline(always)]
     fn write_snapshot(&self) -> Result<()> {
         let contents = self.encode();
+        fs::write(&*LEDGER_LAYOUT_PATH, STORAGE_LAYOUT_VERSION.to_string()).c(d!())?;
         fs::write(&*LEDGER_SNAPSHOT_PATH, &contents).c(d!())
     }
 }
 
+// The data written before the layout was versioned has no layout file,
+// it is of the version 0.
+fn check_layout_version() -> Result<()> {
+    let version = match fs::read_to_string(&*LEDGER_LAYOUT_PATH) {
+        Ok(v) => v.trim().parse::<u64>().c(d!("invalid layout version"))?,
+        Err(e) if ErrorKind::NotFound == e.kind() => 0,
+        Err(e) => return Err(e).c(d!()),
+    };
+    if STORAGE_LAYOUT_VERSION != version {
+        return Err(eg!(
+            "the storage layout of the data is {}, but {} is required, \
+            remove the data and resync the node",
+            version,
+            STORAGE_LAYOUT_VERSION
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Vs, Default, Clone, Debug, Deserialize, Serialize)]
 pub struct State {
     pub chain_id: OrphanVs<u64>,
@@ -463,13 +617,34 @@ pub struct State {
     pub evm: ethvm::State,
     pub staking: staking::State,
 
+    // enforced by tendermint,
+    // the `max_gas` of blocks follows `evm.block_gas_limit`
+    #[serde(default)]
+    pub consensus_params: OrphanVs<ConsensusParams>,
+    // who can change the params above and the block gas limit,
+    // zero means nobody
+    #[serde(default)]
+    pub params_admin: OrphanVs<H160>,
+
+    // the contracts created in the genesis block
+    #[serde(default)]
+    pub genesis_contracts: OrphanVs<Vec<GenesisContract>>,
+    // the state is hashed into the header every N blocks,
+    // zero means never, the state sync needs it
+    #[serde(default)]
+    pub state_hash_itv: OrphanVs<u64>,
+
     // maintained by the 'main' branch only
     pub blocks: MapxOrd<BlockHeight, Block>,
+    // tx hash => height of its block,
+    // maintained with the blocks
+    #[serde(default)]
+    pub tx_heights: MapxOrd<HashValue, BlockHeight>,
 }
 
 impl State {
     fn refresh_branches(&self) -> Result<()> {
-        self.branch_remove(CHECK_TX_BRANCH_NAME).c(d!())?;
+        self.refresh_check_tx_branch().c(d!())?;
 
         // The `DELIVER_TX` branch should has been deleted in the process of `commit`,
         // the trial deleting operation here is used to deal with some special scenes.
@@ -481,8 +656,26 @@ impl State {
             DELIVER_TX_BRANCH_NAME,
             ParentBranchName::from(MAIN_BRANCH_NAME.0),
         )
-        .c(d!())?;
+        .c(d!())
+    }
+
+    #[inline(always)]
+    fn index_block(&self, block: Block) {
+        let h = block.header.height;
+        block.header.receipts.keys().for_each(|hash| {
+            self.tx_heights.insert(hash.clone(), h);
+        });
+        self.blocks.insert(h, block);
+    }
 
+    /// Height of the block of a transaction.
+    #[inline(always)]
+    pub fn tx_height(&self, tx_hash: &[u8]) -> Option<BlockHeight> {
+        self.tx_heights.get(&tx_hash.to_vec())
+    }
+
+    fn refresh_check_tx_branch(&self) -> Result<()> {
+        self.branch_remove(CHECK_TX_BRANCH_NAME).c(d!())?;
         self.branch_create_by_base_branch(
             CHECK_TX_BRANCH_NAME,
             ParentBranchName::from(MAIN_BRANCH_NAME.0),
@@ -499,20 +692,19 @@ pub struct Block {
     pub txs: Vecx<Tx>,
     // bloom
     pub bloom: Vec<u8>,
+    // consensus data from tendermint,
+    // NOT a part of the header hash
+    #[serde(default)]
+    pub consensus: ConsensusInfo,
 }
 
 impl Block {
     #[inline(always)]
-    fn new(
-        height: BlockHeight,
-        proposer: TmAddress,
-        timestamp: u64,
-        prev_hash: HashValue,
-    ) -> Self {
+    fn new(height: BlockHeight, consensus: ConsensusInfo, prev_hash: HashValue) -> Self {
         let header = BlockHeader {
             height,
-            proposer,
-            timestamp,
+            proposer: consensus.proposer.clone(),
+            timestamp: consensus.block_time_ns / 1_000_000_000,
             prev_hash,
             ..Default::default()
         };
@@ -520,11 +712,49 @@ impl Block {
             header,
             txs: Vecx::new(),
             bloom: Bloom::default().as_bytes().to_vec(),
+            consensus,
             ..Default::default()
         }
     }
 }
 
+// Consensus data of the current block, reported by tendermint in `BeginBlock`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ConsensusInfo {
+    // full address of the proposer
+    pub proposer: TmAddress,
+    pub validators_hash: HashValue,
+    // nanoseconds since the unix epoch
+    pub block_time_ns: u64,
+    // hash of the tendermint block
+    pub block_hash: HashValue,
+    // voting power that signed the last commit
+    pub last_commit_signed_power: u64,
+    // voting power of all validators in the last commit
+    pub last_commit_total_power: u64,
+}
+
+// Consensus parameters of tendermint, kept in the state to be governable.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConsensusParams {
+    pub block_max_bytes: i64,
+    pub evidence_max_age_num_blocks: i64,
+    pub evidence_max_age_duration_ns: u64,
+    pub evidence_max_bytes: i64,
+}
+
+// The defaults of tendermint.
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self {
+            block_max_bytes: 22020096,
+            evidence_max_age_num_blocks: 100000,
+            evidence_max_age_duration_ns: 48 * 3600 * 1_000_000_000,
+            evidence_max_bytes: 1048576,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct BlockHeader {
     // height of the current block
@@ -539,6 +769,10 @@ pub struct BlockHeader {
     pub prev_hash: HashValue,
     // execution results for each transaction
     pub receipts: BTreeMap<HashValue, Receipt>,
+    // hash of the state after the current block,
+    // empty out of the heights of `State::state_hash_itv`
+    #[serde(default)]
+    pub state_hash: HashValue,
 }
 
 impl BlockHeader {
@@ -552,6 +786,7 @@ impl BlockHeader {
             merkle_root: HashValueRef<'a>,
             prev_hash: HashValueRef<'a>,
             receipts: &'a BTreeMap<HashValue, Receipt>,
+            state_hash: HashValueRef<'a>,
         }
 
         let contents = Contents {
@@ -561,6 +796,7 @@ impl BlockHeader {
             merkle_root: &self.tx_merkle.root_hash,
             prev_hash: &self.prev_hash,
             receipts: &self.receipts,
+            state_hash: &self.state_hash,
         }
         .encode_value();
 
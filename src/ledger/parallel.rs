@@ -0,0 +1,274 @@
+//!
+//! # Optimistic parallel execution
+//!
+//! The transactions of a batch are executed speculatively,
+//! each on its own child branch of the same base state.
+//! Then, in the original order, the writes of a speculation are adopted
+//! if nothing it read has been written by an earlier transaction,
+//! otherwise the transaction is re-executed on the real branch.
+//!
+//! Staking and consensus-params changes are stored outside of the evm state
+//! and can not be tracked, so the transactions after such one are speculated again.
+//!
+
+use super::{Block, Receipt, StateBranch};
+use crate::{
+    common::halt,
+    ethvm::{
+        impls::cache::{AccessRecord, BackendCache, StateKey, StateWrite},
+        precompile::{PARAMS, STAKING},
+    },
+    tx::{error::TxError, Tx},
+};
+use primitive_types::H160;
+use ruc::*;
+use std::{collections::HashSet, mem, result::Result as StdResult, thread};
+use vsdb::{BranchName, ParentBranchName, VersionName, VsMgmt};
+
+struct Speculation {
+    receipt: Option<Receipt>,
+    record: AccessRecord,
+}
+
+impl Speculation {
+    #[inline(always)]
+    fn touches(&self, untracked: &[H160]) -> bool {
+        self.receipt
+            .as_ref()
+            .map(|r| touches_untracked(r, untracked))
+            .unwrap_or(false)
+    }
+}
+
+// Keys written by the transactions applied so far in a round.
+#[derive(Default)]
+struct WriteSet {
+    keys: HashSet<StateKey>,
+    wiped: HashSet<H160>,
+}
+
+impl WriteSet {
+    fn add(&mut self, writes: &[StateWrite]) {
+        for w in writes.iter() {
+            match w {
+                StateWrite::Account(address, _) => {
+                    self.keys.insert(StateKey::Account(*address));
+                }
+                StateWrite::Storage(address, index, _) => {
+                    self.keys.insert(StateKey::Storage(*address, *index));
+                }
+                StateWrite::ResetStorage(address) => {
+                    self.wiped.insert(*address);
+                }
+                // addressed by its hash, never conflicts
+                StateWrite::Code(..) => {}
+            }
+        }
+    }
+
+    fn conflicts(&self, reads: &HashSet<StateKey>) -> bool {
+        reads.iter().any(|k| {
+            self.keys.contains(k)
+                || matches!(k, StateKey::Storage(a, _) if self.wiped.contains(a))
+        })
+    }
+}
+
+impl StateBranch {
+    /// Apply a batch of transactions,
+    /// the state, the receipts and the results are identical to
+    /// calling `apply_tx` on each of them in order.
+    pub fn apply_txs_parallel(&mut self, txs: Vec<Tx>) -> Vec<StdResult<(), TxError>> {
+        // precompiles that write outside of the evm state
+        let untracked = self
+            .state
+            .evm
+            .fork_schedule
+            .precompiles_at(self.block_in_process.header.height)
+            .into_iter()
+            .filter(|(_, name)| name == STAKING || name == PARAMS)
+            .map(|(addr, _)| addr)
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::with_capacity(txs.len());
+        let mut next = 0;
+
+        while next < txs.len() {
+            let pending = &txs[next..];
+            let specs = self.speculate(pending);
+            let mut written = WriteSet::default();
+
+            for (tx, spec) in pending.iter().zip(specs.into_iter()) {
+                next += 1;
+                match spec {
+                    Some(s)
+                        if !s.touches(&untracked)
+                            && !written.conflicts(&s.record.reads) =>
+                    {
+                        written.add(&s.record.writes);
+                        results.push(self.adopt(tx.clone(), s));
+                    }
+                    _ => {
+                        let (ret, record) = self.apply_tx_recorded(tx.clone());
+                        let barrier = ret.is_ok()
+                            && self
+                                .block_in_process
+                                .header
+                                .receipts
+                                .get(&tx.hash())
+                                .map(|r| touches_untracked(r, &untracked))
+                                .unwrap_or(false);
+                        written.add(&record.writes);
+                        results.push(ret);
+                        if barrier {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        // all changes above bypassed the cache
+        self.cache.clear();
+
+        results
+    }
+
+    // Execute each transaction on its own child branch, in parallel,
+    // `None` means that the speculation is unusable.
+    fn speculate(&mut self, txs: &[Tx]) -> Vec<Option<Speculation>> {
+        // the receipts are never needed by the children
+        let receipts = mem::take(&mut self.block_in_process.header.receipts);
+        let block = self.block_in_process.clone();
+        self.block_in_process.header.receipts = receipts;
+
+        let mut children = vec![];
+        for i in 0..txs.len() {
+            match info!(self.child(i, &block)) {
+                Ok(c) => children.push(c),
+                Err(_) => break,
+            }
+        }
+
+        let n = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let chunk_size = ((children.len() + n - 1) / n).max(1);
+
+        let mut specs = Vec::with_capacity(txs.len());
+        thread::scope(|s| {
+            let handles = children
+                .chunks_mut(chunk_size)
+                .zip(txs.chunks(chunk_size))
+                .map(|(children, txs)| {
+                    let cnt = children.len();
+                    let hdr = s.spawn(move || {
+                        children
+                            .iter_mut()
+                            .zip(txs.iter())
+                            .map(|(c, tx)| c.speculate_one(tx.clone()))
+                            .collect::<Vec<_>>()
+                    });
+                    (cnt, hdr)
+                })
+                .collect::<Vec<_>>();
+            for (cnt, hdr) in handles.into_iter() {
+                specs.extend(
+                    hdr.join()
+                        .unwrap_or_else(|_| (0..cnt).map(|_| None).collect::<Vec<_>>()),
+                );
+            }
+        });
+        specs.resize_with(txs.len(), || None);
+
+        for c in children.iter() {
+            info_omit!(self.state.branch_remove(c.branch.as_slice().into()));
+        }
+
+        specs
+    }
+
+    // A child branch based on the current head of this branch,
+    // names are unique within a round of the same block.
+    fn child(&self, i: usize, block: &Block) -> Result<StateBranch> {
+        let name = format!(
+            "{}-ptx-{}-{}-{}",
+            String::from_utf8_lossy(&self.branch),
+            block.header.height,
+            self.tx_hashes_in_process.len(),
+            i
+        );
+        let b = BranchName(name.as_bytes());
+
+        self.state
+            .branch_create_by_base_branch(
+                b,
+                ParentBranchName::from(self.branch.as_slice()),
+            )
+            .c(d!())?;
+        if let Err(e) = self
+            .state
+            .version_create_by_branch(VersionName(name.as_bytes()), b)
+            .c(d!())
+        {
+            info_omit!(self.state.branch_remove(b));
+            return Err(e);
+        }
+
+        let mut state = self.state.clone();
+        state.branch_set_default(b).c(d!())?;
+
+        Ok(StateBranch {
+            state,
+            branch: name.into_bytes(),
+            tx_hashes_in_process: vec![],
+            block_in_process: block.clone(),
+            cache: BackendCache::recording(),
+            mempool: Default::default(),
+        })
+    }
+
+    fn speculate_one(&mut self, tx: Tx) -> Option<Speculation> {
+        let b = self.branch.clone();
+        let tx_hash = tx.hash();
+        let receipt = self.exec_tx(tx, &tx_hash, b.as_slice().into()).ok()?;
+
+        let mut record = self.cache.take_record();
+        // the signature and nonce checks read the caller directly
+        if let Some(from) = receipt.as_ref().and_then(|r| r.from) {
+            record.reads.insert(StateKey::Account(from));
+        }
+
+        Some(Speculation { receipt, record })
+    }
+
+    // Replay the recorded writes on this branch,
+    // as if the transaction has been executed here.
+    fn adopt(&mut self, tx: Tx, spec: Speculation) -> StdResult<(), TxError> {
+        let b = self.branch.clone();
+        let b = b.as_slice().into();
+
+        halt!(self.tx_version_create(b));
+        for w in spec.record.writes.into_iter() {
+            halt!(w.apply(&self.state.evm.OFUEL, b));
+        }
+
+        let tx_hash = tx.hash();
+        self.push_tx(tx, tx_hash, spec.receipt);
+
+        Ok(())
+    }
+
+    // `apply_tx`, and all the writes to the evm state during it.
+    fn apply_tx_recorded(&mut self, tx: Tx) -> (StdResult<(), TxError>, AccessRecord) {
+        let cache = mem::replace(&mut self.cache, BackendCache::recording());
+        let ret = self.apply_tx(tx);
+        let record = mem::replace(&mut self.cache, cache).take_record();
+        (ret, record)
+    }
+}
+
+#[inline(always)]
+fn touches_untracked(receipt: &Receipt, untracked: &[H160]) -> bool {
+    receipt.logs.iter().any(|l| untracked.contains(&l.address))
+}
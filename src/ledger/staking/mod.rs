@@ -2,17 +2,160 @@
 //! # Data structures of staking
 //!
 
+use primitive_types::{H160, U256};
+use ruc::*;
 use serde::{Deserialize, Serialize};
-use vsdb::Vs;
+use vsdb::{BranchName, MapxVs, Vs};
 
-#[derive(Vs, Default, Clone, Debug, Deserialize, Serialize)]
+#[derive(Vs, Clone, Debug, Deserialize, Serialize)]
 pub struct State {
-    // TODO
+    // (delegator, validator) => amount
+    pub delegations: MapxVs<(H160, H160), U256>,
+
+    // validator => total amount delegated to it
+    pub validator_power: MapxVs<H160, U256>,
+
+    // delegator => unclaimed rewards
+    pub rewards: MapxVs<H160, U256>,
+
+    // validators that can be delegated to, registered in the genesis
+    pub validators: MapxVs<H160, bool>,
 }
 
-// impl State {
-//     pub fn new() -> Self {
-//         // TODO
-//         Self {}
-//     }
-// }
+impl State {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {
+            delegations: MapxVs::new(),
+            validator_power: MapxVs::new(),
+            rewards: MapxVs::new(),
+            validators: MapxVs::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_validator(&self, validator: H160, b: BranchName) -> bool {
+        self.validators
+            .get_by_branch(&validator, b)
+            .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn register_validator(&self, validator: H160, b: BranchName) -> Result<()> {
+        self.validators
+            .insert_by_branch(validator, true, b)
+            .c(d!())
+            .map(|_| ())
+    }
+
+    #[inline(always)]
+    pub fn delegation_of(
+        &self,
+        delegator: H160,
+        validator: H160,
+        b: BranchName,
+    ) -> U256 {
+        self.delegations
+            .get_by_branch(&(delegator, validator), b)
+            .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn validator_power_of(&self, validator: H160, b: BranchName) -> U256 {
+        self.validator_power
+            .get_by_branch(&validator, b)
+            .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn rewards_of(&self, delegator: H160, b: BranchName) -> U256 {
+        self.rewards
+            .get_by_branch(&delegator, b)
+            .unwrap_or_default()
+    }
+
+    pub fn delegate(
+        &self,
+        delegator: H160,
+        validator: H160,
+        amount: U256,
+        b: BranchName,
+    ) -> Result<()> {
+        let delegated = self
+            .delegation_of(delegator, validator, b)
+            .checked_add(amount)
+            .c(d!("delegation overflow"))?;
+        let power = self
+            .validator_power_of(validator, b)
+            .checked_add(amount)
+            .c(d!("validator power overflow"))?;
+
+        self.delegations
+            .insert_by_branch((delegator, validator), delegated, b)
+            .c(d!())?;
+        self.validator_power
+            .insert_by_branch(validator, power, b)
+            .c(d!())
+            .map(|_| ())
+    }
+
+    pub fn undelegate(
+        &self,
+        delegator: H160,
+        validator: H160,
+        amount: U256,
+        b: BranchName,
+    ) -> Result<()> {
+        let delegated = self
+            .delegation_of(delegator, validator, b)
+            .checked_sub(amount)
+            .c(d!("insufficient delegation"))?;
+        let power = self.validator_power_of(validator, b).saturating_sub(amount);
+
+        if delegated.is_zero() {
+            self.delegations
+                .remove_by_branch(&(delegator, validator), b)
+                .c(d!())?;
+        } else {
+            self.delegations
+                .insert_by_branch((delegator, validator), delegated, b)
+                .c(d!())?;
+        }
+        self.validator_power
+            .insert_by_branch(validator, power, b)
+            .c(d!())
+            .map(|_| ())
+    }
+
+    // The funds must have been moved to the staking pool.
+    pub fn add_rewards(
+        &self,
+        delegator: H160,
+        amount: U256,
+        b: BranchName,
+    ) -> Result<()> {
+        let rewards = self
+            .rewards_of(delegator, b)
+            .checked_add(amount)
+            .c(d!("rewards overflow"))?;
+        self.rewards
+            .insert_by_branch(delegator, rewards, b)
+            .c(d!())
+            .map(|_| ())
+    }
+
+    // Return the amount of rewards that have been taken away.
+    pub fn take_rewards(&self, delegator: H160, b: BranchName) -> Result<U256> {
+        let amount = self.rewards_of(delegator, b);
+        if !amount.is_zero() {
+            self.rewards.remove_by_branch(&delegator, b).c(d!())?;
+        }
+        Ok(amount)
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
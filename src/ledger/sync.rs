@@ -0,0 +1,444 @@
+//!
+//! # State sync
+//!
+//! The state of a committed block is exported as a portable payload,
+//! without any history, then split into chunks.
+//!
+//! Every `State::state_hash_itv` blocks, the hash of the payload,
+//! the last block excluded, is committed into the block header,
+//! so a payload from a peer is verified against the app hash.
+//! Only the blocks with a state hash can be exported.
+//!
+
+use super::{
+    Block, BlockHeader, ConsensusInfo, ConsensusParams, Ledger, Receipt, State,
+    TxMerkle, VsVersion, MAIN_BRANCH_NAME,
+};
+use crate::{
+    common::{
+        block_hash_to_evm_format, hash_sha3_256, BlockHeight, GenesisContract,
+        HashValue, TmAddress,
+    },
+    ethvm::{
+        fork::EvmFork, precompile::PrecompileCfg, vesting::VestingSchedule, OvrAccount,
+    },
+};
+use once_cell::sync::Lazy;
+use primitive_types::{H160, H256, U256};
+use ruc::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, mem};
+use vsdb::{ValueEn, ValueEnDe, VersionName};
+
+/// Version of the payload encoding.
+pub const SNAPSHOT_FORMAT: u32 = 3;
+
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// enough for the `BLOCKHASH` opcode
+const BLOCK_HASHES_KEPT: usize = 256;
+
+static SNAPSHOT_DIR: Lazy<String> = Lazy::new(|| {
+    let dir = format!("{}/overeality/state_sync", vsdb::vsdb_get_custom_dir());
+    pnk!(fs::create_dir_all(&dir));
+    dir
+});
+
+/// Description of an exported snapshot.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SnapshotMeta {
+    pub height: BlockHeight,
+    pub format: u32,
+    // hash of the whole payload
+    pub hash: HashValue,
+    // hash of the block at `height`
+    pub app_hash: HashValue,
+    pub chunk_hashes: Vec<HashValue>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Payload {
+    chain_id: u64,
+    chain_name: String,
+    chain_version: String,
+
+    gas_price: U256,
+    block_gas_limit: U256,
+    block_base_fee_per_gas: U256,
+    consensus_params: ConsensusParams,
+    params_admin: H160,
+    genesis_contracts: Vec<GenesisContract>,
+    state_hash_itv: u64,
+
+    total_supply: U256,
+    accounts: Vec<(H160, OvrAccount)>,
+    storages: Vec<(H160, H256, H256)>,
+    codes: Vec<(H256, Vec<u8>)>,
+    allowances: Vec<((H160, H160), U256)>,
+    vesting: Vec<(H160, VestingSchedule)>,
+
+    forks: BTreeMap<BlockHeight, EvmFork>,
+    precompiles: BTreeMap<BlockHeight, PrecompileCfg>,
+    block_hashes: Vec<(BlockHeight, H256)>,
+
+    delegations: Vec<((H160, H160), U256)>,
+    validator_power: Vec<(H160, U256)>,
+    rewards: Vec<(H160, U256)>,
+    validators: Vec<(H160, bool)>,
+
+    block: LastBlock,
+}
+
+// The last block, without its transactions.
+#[derive(Default, Deserialize, Serialize)]
+struct LastBlock {
+    height: BlockHeight,
+    proposer: TmAddress,
+    timestamp: u64,
+    tx_root: HashValue,
+    prev_hash: HashValue,
+    receipts: BTreeMap<HashValue, Receipt>,
+    state_hash: HashValue,
+    header_hash: HashValue,
+    bloom: Vec<u8>,
+    consensus: ConsensusInfo,
+}
+
+impl From<Block> for LastBlock {
+    fn from(b: Block) -> Self {
+        Self {
+            height: b.header.height,
+            proposer: b.header.proposer,
+            timestamp: b.header.timestamp,
+            tx_root: b.header.tx_merkle.root_hash,
+            prev_hash: b.header.prev_hash,
+            receipts: b.header.receipts,
+            state_hash: b.header.state_hash,
+            header_hash: b.header_hash,
+            bloom: b.bloom,
+            consensus: b.consensus,
+        }
+    }
+}
+
+impl From<LastBlock> for Block {
+    fn from(b: LastBlock) -> Self {
+        Self {
+            header: BlockHeader {
+                height: b.height,
+                proposer: b.proposer,
+                timestamp: b.timestamp,
+                tx_merkle: TxMerkle {
+                    root_hash: b.tx_root,
+                    ..Default::default()
+                },
+                prev_hash: b.prev_hash,
+                receipts: b.receipts,
+                state_hash: b.state_hash,
+            },
+            header_hash: b.header_hash,
+            bloom: b.bloom,
+            consensus: b.consensus,
+            ..Default::default()
+        }
+    }
+}
+
+impl Payload {
+    // The last block is not a part of the state, and the forks scheduled
+    // after it are a local config of each node, both are out of the hash.
+    fn state_hash(&mut self, height: BlockHeight) -> HashValue {
+        let block = mem::take(&mut self.block);
+        let mut forks = self.forks.split_off(&(1 + height));
+        let mut precompiles = self.precompiles.split_off(&(1 + height));
+
+        let hash = hash_sha3_256(&[&self.encode()]);
+
+        self.block = block;
+        self.forks.append(&mut forks);
+        self.precompiles.append(&mut precompiles);
+        hash
+    }
+}
+
+impl State {
+    /// Hash of the state right after the block at `height`,
+    /// which has `txs` transactions.
+    pub(super) fn state_hash(&self, height: BlockHeight, txs: u64) -> Result<HashValue> {
+        self.export_at(height, txs)
+            .c(d!())
+            .map(|mut p| p.state_hash(height))
+    }
+
+    fn export(&self, height: BlockHeight) -> Result<Payload> {
+        let block = self.blocks.get(&height).c(d!("block not found"))?;
+        let mut payload = self.export_at(height, block.txs.len() as u64).c(d!())?;
+        payload.block = block.into();
+        Ok(payload)
+    }
+
+    // Read at the version of the block, the later blocks may be
+    // committed in the meantime without changing the result.
+    fn export_at(&self, height: BlockHeight, txs: u64) -> Result<Payload> {
+        let b = MAIN_BRANCH_NAME;
+        let evm = &self.evm;
+        let token = &evm.OFUEL;
+
+        let ver = VsVersion::new(height, txs).encode_value();
+        let v = VersionName(&ver);
+
+        let mut storages = vec![];
+        token
+            .storages
+            .iter_op_by_branch_version(b, v, &mut |(addr, key), value| {
+                storages.push((addr, key, value));
+                Ok(())
+            })
+            .c(d!())?;
+
+        let mut block_hashes = evm
+            .block_hashes
+            .iter()
+            .rev()
+            .filter(|(h, _)| *h < height)
+            .take(BLOCK_HASHES_KEPT)
+            .collect::<Vec<_>>();
+        block_hashes.reverse();
+
+        Ok(Payload {
+            chain_id: self.chain_id.get_value_by_branch_version(b, v).c(d!())?,
+            chain_name: self.chain_name.get_value_by_branch_version(b, v).c(d!())?,
+            chain_version: self
+                .chain_version
+                .get_value_by_branch_version(b, v)
+                .c(d!())?,
+            gas_price: evm.gas_price.get_value_by_branch_version(b, v).c(d!())?,
+            block_gas_limit: evm
+                .block_gas_limit
+                .get_value_by_branch_version(b, v)
+                .c(d!())?,
+            block_base_fee_per_gas: evm
+                .block_base_fee_per_gas
+                .get_value_by_branch_version(b, v)
+                .c(d!())?,
+            consensus_params: self
+                .consensus_params
+                .get_value_by_branch_version(b, v)
+                .unwrap_or_default(),
+            params_admin: self
+                .params_admin
+                .get_value_by_branch_version(b, v)
+                .unwrap_or_default(),
+            genesis_contracts: self
+                .genesis_contracts
+                .get_value_by_branch_version(b, v)
+                .unwrap_or_default(),
+            state_hash_itv: self
+                .state_hash_itv
+                .get_value_by_branch_version(b, v)
+                .unwrap_or_default(),
+            total_supply: token
+                .total_supply
+                .get_value_by_branch_version(b, v)
+                .c(d!())?,
+            accounts: token.accounts.iter_by_branch_version(b, v).collect(),
+            storages,
+            codes: token.codes.iter_by_branch_version(b, v).collect(),
+            allowances: token.allowances.iter_by_branch_version(b, v).collect(),
+            vesting: token.vesting.iter_by_branch_version(b, v).collect(),
+            forks: evm.fork_schedule.iter().collect(),
+            precompiles: evm.fork_schedule.iter_precompiles().collect(),
+            block_hashes,
+            delegations: self
+                .staking
+                .delegations
+                .iter_by_branch_version(b, v)
+                .collect(),
+            validator_power: self
+                .staking
+                .validator_power
+                .iter_by_branch_version(b, v)
+                .collect(),
+            rewards: self.staking.rewards.iter_by_branch_version(b, v).collect(),
+            validators: self
+                .staking
+                .validators
+                .iter_by_branch_version(b, v)
+                .collect(),
+            block: LastBlock::default(),
+        })
+    }
+
+    // Write into a new ledger, nothing has been committed in it.
+    fn import(&self, p: Payload) -> Result<()> {
+        let b = MAIN_BRANCH_NAME;
+        let evm = &self.evm;
+        let token = &evm.OFUEL;
+
+        self.chain_id.set_value(p.chain_id).c(d!())?;
+        self.chain_name.set_value(p.chain_name).c(d!())?;
+        self.chain_version.set_value(p.chain_version).c(d!())?;
+        evm.gas_price.set_value(p.gas_price).c(d!())?;
+        evm.block_gas_limit.set_value(p.block_gas_limit).c(d!())?;
+        evm.block_base_fee_per_gas
+            .set_value(p.block_base_fee_per_gas)
+            .c(d!())?;
+        self.consensus_params
+            .set_value(p.consensus_params)
+            .c(d!())?;
+        self.params_admin.set_value(p.params_admin).c(d!())?;
+        self.genesis_contracts
+            .set_value(p.genesis_contracts)
+            .c(d!())?;
+        self.state_hash_itv.set_value(p.state_hash_itv).c(d!())?;
+        token.total_supply.set_value(p.total_supply).c(d!())?;
+
+        for (addr, account) in p.accounts.into_iter() {
+            token.accounts.insert_by_branch(addr, account, b).c(d!())?;
+        }
+        for (addr, key, value) in p.storages.into_iter() {
+            token
+                .storages
+                .insert_by_branch((addr, key), value, b)
+                .c(d!())?;
+        }
+        for (hash, code) in p.codes.into_iter() {
+            token.codes.insert_by_branch(hash, code, b).c(d!())?;
+        }
+        for (k, v) in p.allowances.into_iter() {
+            token.allowances.insert_by_branch(k, v, b).c(d!())?;
+        }
+        for (k, v) in p.vesting.into_iter() {
+            token.vesting.insert_by_branch(k, v, b).c(d!())?;
+        }
+
+        // the whole schedule is restored into an empty state
+        evm.fork_schedule.set(&p.forks, None).c(d!())?;
+        evm.fork_schedule
+            .set_precompiles(&p.precompiles, None)
+            .c(d!())?;
+        for (h, hash) in p.block_hashes.into_iter() {
+            evm.block_hashes.insert(h, hash);
+        }
+        evm.block_hashes.insert(
+            p.block.height,
+            block_hash_to_evm_format(&p.block.header_hash),
+        );
+
+        for (k, v) in p.delegations.into_iter() {
+            self.staking.delegations.insert_by_branch(k, v, b).c(d!())?;
+        }
+        for (k, v) in p.validator_power.into_iter() {
+            self.staking
+                .validator_power
+                .insert_by_branch(k, v, b)
+                .c(d!())?;
+        }
+        for (k, v) in p.rewards.into_iter() {
+            self.staking.rewards.insert_by_branch(k, v, b).c(d!())?;
+        }
+        for (k, v) in p.validators.into_iter() {
+            self.staking.validators.insert_by_branch(k, v, b).c(d!())?;
+        }
+
+        self.index_block(Block::from(p.block));
+
+        Ok(())
+    }
+}
+
+impl Ledger {
+    /// Export the state at the last committed block into chunks,
+    /// and only keep the latest `keep` snapshots.
+    ///
+    /// Slow for a large state, call it outside of the consensus workflow.
+    pub fn export_snapshot(&self, keep: usize) -> Result<SnapshotMeta> {
+        let (height, app_hash) = {
+            let main = self.main.read();
+            let block = main.last_block().c(d!("no block"))?;
+            if block.header.state_hash.is_empty() {
+                return Err(eg!("no state hash at {}", block.header.height));
+            }
+            (block.header.height, block.header_hash)
+        };
+
+        let payload = self.state.export(height).c(d!())?.encode();
+
+        let dir = format!("{}/{}", &*SNAPSHOT_DIR, height);
+        fs::create_dir_all(&dir).c(d!())?;
+
+        let mut chunk_hashes = vec![];
+        for (i, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+            fs::write(format!("{}/{}", dir, i), chunk).c(d!())?;
+            chunk_hashes.push(hash_sha3_256(&[chunk]));
+        }
+
+        let meta = SnapshotMeta {
+            height,
+            format: SNAPSHOT_FORMAT,
+            hash: hash_sha3_256(&[&payload]),
+            app_hash,
+            chunk_hashes,
+        };
+
+        // written at last, a snapshot without it is incomplete
+        fs::write(format!("{}/meta", dir), meta.encode()).c(d!())?;
+
+        let snapshots = list_snapshots().c(d!())?;
+        for s in snapshots.iter().rev().skip(keep) {
+            fs::remove_dir_all(format!("{}/{}", &*SNAPSHOT_DIR, s.height)).c(d!())?;
+        }
+
+        Ok(meta)
+    }
+
+    /// Import a payload exported by `export_snapshot` into a new ledger,
+    /// `meta.app_hash` must be the trusted app hash at `meta.height`.
+    pub fn import_snapshot(&self, meta: &SnapshotMeta, payload: &[u8]) -> Result<()> {
+        if hash_sha3_256(&[payload]) != meta.hash {
+            return Err(eg!("hash mismatch"));
+        }
+
+        let mut payload = Payload::decode(payload).c(d!())?;
+        let block = Block::from(mem::take(&mut payload.block));
+        if block.header.height != meta.height
+            || block.header_hash != meta.app_hash
+            || block.header.hash() != block.header_hash
+        {
+            return Err(eg!("the last block mismatch"));
+        }
+        if payload.state_hash(meta.height) != block.header.state_hash {
+            return Err(eg!("the state mismatch"));
+        }
+        payload.block = block.into();
+
+        let main = self.main.write();
+        if main.last_block().is_some() {
+            return Err(eg!("the ledger is not empty"));
+        }
+        main.state.import(payload).c(d!())?;
+
+        vsdb::vsdb_flush();
+        main.write_snapshot().c(d!())?;
+
+        // the 'CheckTx' branch was forked from the empty state
+        self.check_tx_refresh(&main).c(d!())
+    }
+}
+
+/// All the complete snapshots, sorted by height.
+pub fn list_snapshots() -> Result<Vec<SnapshotMeta>> {
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(&*SNAPSHOT_DIR).c(d!())? {
+        let path = entry.c(d!())?.path().join("meta");
+        if let Ok(meta) = fs::read(path) {
+            snapshots.push(SnapshotMeta::decode(&meta).c(d!())?);
+        }
+    }
+    snapshots.sort_by_key(|s| s.height);
+    Ok(snapshots)
+}
+
+pub fn load_snapshot_chunk(height: BlockHeight, index: u32) -> Result<Vec<u8>> {
+    fs::read(format!("{}/{}/{}", &*SNAPSHOT_DIR, height, index)).c(d!())
+}
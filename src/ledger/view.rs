@@ -0,0 +1,234 @@
+//!
+//! # Read-only views of the world state
+//!
+//! A view reads the 'main' branch at the version of a (height, tx position),
+//! nothing is created or removed in the branch metadata,
+//! so views never race with each other, nor with the consensus writes.
+//!
+
+use super::{ConsensusInfo, State, VsVersion, MAIN_BRANCH_NAME};
+use crate::{
+    common::{tm_proposer_to_evm_format, BlockHeight, GenesisContract},
+    ethvm::{
+        impls::backend::OvrBackend, vesting::VestingSchedule, CallContractResp,
+        OvrVicinity,
+    },
+};
+use evm::backend::Backend;
+use primitive_types::{H160, H256, U256};
+use ruc::*;
+use vsdb::{OrphanVs, ValueEn, ValueEnDe, VersionName, VsMgmt};
+use web3_rpc_core::types::CallRequest;
+
+pub struct StateView<'a> {
+    state: &'a State,
+    height: BlockHeight,
+    // `None` means the head of the 'main' branch
+    version: Option<Vec<u8>>,
+}
+
+impl State {
+    /// The state after the first `tx_position` transactions of the block,
+    /// or after the whole block if `tx_position` is `None`.
+    ///
+    /// Height 0 means the latest committed state.
+    pub fn view_at(
+        &self,
+        height: BlockHeight,
+        tx_position: Option<u64>,
+    ) -> Result<StateView> {
+        if 0 == height {
+            return Ok(StateView {
+                state: self,
+                height: self.blocks.last().map(|(h, _)| h).unwrap_or(0),
+                version: None,
+            });
+        }
+
+        let txs = self.blocks.get(&height).c(d!("block not found"))?.txs.len() as u64;
+        let position = tx_position.unwrap_or(txs);
+        if position > txs {
+            return Err(eg!(
+                "tx position out of range: {}, total: {}",
+                position,
+                txs
+            ));
+        }
+
+        let version = VsVersion::new(height, position).encode_value().to_vec();
+        if !self
+            .evm
+            .version_exists_on_branch(VersionName(&version), MAIN_BRANCH_NAME)
+        {
+            return Err(eg!("state not found"));
+        }
+
+        Ok(StateView {
+            state: self,
+            height,
+            version: Some(version),
+        })
+    }
+
+    /// The state right before the tx at `index` of the block.
+    #[inline(always)]
+    pub fn view_before_tx(&self, height: BlockHeight, index: u64) -> Result<StateView> {
+        self.view_tx(height, index, false).c(d!())
+    }
+
+    /// The state right after the tx at `index` of the block.
+    #[inline(always)]
+    pub fn view_after_tx(&self, height: BlockHeight, index: u64) -> Result<StateView> {
+        self.view_tx(height, index, true).c(d!())
+    }
+
+    // tx `i` is executed in the version `(height, i + 1)`
+    fn view_tx(
+        &self,
+        height: BlockHeight,
+        index: u64,
+        after: bool,
+    ) -> Result<StateView> {
+        let txs = self.blocks.get(&height).c(d!("block not found"))?.txs.len() as u64;
+        if index >= txs {
+            return Err(eg!("tx index out of range: {}, total: {}", index, txs));
+        }
+        self.view_at(height, Some(index + after as u64)).c(d!())
+    }
+}
+
+impl<'a> StateView<'a> {
+    #[inline(always)]
+    pub fn height(&self) -> BlockHeight {
+        self.height
+    }
+
+    #[inline(always)]
+    pub fn backend(&self) -> OvrBackend<'_> {
+        let mut backend = self.state.evm.get_backend_hdr(MAIN_BRANCH_NAME);
+        // the vesting is released by the time of the block,
+        // and calls see the block of the view
+        backend.vicinity = self.vicinity();
+        match self.version.as_ref() {
+            Some(v) => backend.at_version(VersionName(v)),
+            None => backend,
+        }
+    }
+
+    #[inline(always)]
+    pub fn balance(&self, address: H160) -> U256 {
+        self.backend().total_balance(address)
+    }
+
+    // without the vesting part locked at the time of the block
+    #[inline(always)]
+    pub fn spendable_balance(&self, address: H160) -> U256 {
+        self.backend().basic(address).balance
+    }
+
+    #[inline(always)]
+    pub fn nonce(&self, address: H160) -> U256 {
+        self.backend().basic(address).nonce
+    }
+
+    #[inline(always)]
+    pub fn code(&self, address: H160) -> Vec<u8> {
+        self.backend().code(address)
+    }
+
+    #[inline(always)]
+    pub fn storage(&self, address: H160, index: H256) -> H256 {
+        self.backend().storage(address, index)
+    }
+
+    #[inline(always)]
+    pub fn timestamp(&self) -> u64 {
+        self.state
+            .blocks
+            .get(&self.height)
+            .map(|b| b.header.timestamp)
+            .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn vesting(&self, address: H160) -> Option<VestingSchedule> {
+        let vesting = &self.state.evm.OFUEL.vesting;
+        match self.version.as_ref() {
+            Some(v) => {
+                vesting.get_by_branch_version(&address, MAIN_BRANCH_NAME, VersionName(v))
+            }
+            None => vesting.get_by_branch(&address, MAIN_BRANCH_NAME),
+        }
+    }
+
+    #[inline(always)]
+    pub fn consensus(&self) -> ConsensusInfo {
+        self.state
+            .blocks
+            .get(&self.height)
+            .map(|b| b.consensus)
+            .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn params_admin(&self) -> Option<H160> {
+        Some(self.value(&self.state.params_admin)).filter(|a| !a.is_zero())
+    }
+
+    // The same as `ethvm::State::update_vicinity` does for the block.
+    fn vicinity(&self) -> OvrVicinity {
+        let evm = &self.state.evm;
+        let header = self
+            .state
+            .blocks
+            .get(&self.height)
+            .map(|b| b.header)
+            .unwrap_or_default();
+        OvrVicinity {
+            gas_price: self.value(&evm.gas_price),
+            origin: H160::zero(),
+            chain_id: U256::from(self.value(&self.state.chain_id)),
+            block_number: U256::from(self.height),
+            block_coinbase: tm_proposer_to_evm_format(&header.proposer),
+            block_timestamp: U256::from(header.timestamp),
+            block_difficulty: U256::zero(),
+            block_gas_limit: self.value(&evm.block_gas_limit),
+            block_base_fee_per_gas: self.value(&evm.block_base_fee_per_gas),
+        }
+    }
+
+    #[inline(always)]
+    fn value<T: ValueEnDe + Default>(&self, v: &OrphanVs<T>) -> T {
+        match self.version.as_ref() {
+            Some(ver) => {
+                v.get_value_by_branch_version(MAIN_BRANCH_NAME, VersionName(ver))
+            }
+            None => v.get_value_by_branch(MAIN_BRANCH_NAME),
+        }
+        .unwrap_or_default()
+    }
+
+    #[inline(always)]
+    pub fn genesis_contracts(&self) -> Vec<GenesisContract> {
+        self.state
+            .genesis_contracts
+            .get_value_by_branch(MAIN_BRANCH_NAME)
+            .unwrap_or_default()
+    }
+
+    // The staking precompile reads the version of the view,
+    // through the version of the backend.
+    pub fn call_contract(&self, req: CallRequest) -> Result<CallContractResp> {
+        self.state
+            .evm
+            .call_contract(
+                &self.state.staking,
+                &self.consensus(),
+                self.params_admin(),
+                &self.backend(),
+                self.height,
+                req,
+            )
+            .c(d!())
+    }
+}
@@ -9,7 +9,9 @@ pub mod rpc;
 pub mod tx;
 
 pub use cfg::{Cfg, Commands, DaemonCfg};
-pub use common::{InitalContract, InitalState};
-pub use consensus::App;
+pub use common::{
+    GenesisContract, InitalAccount, InitalContract, InitalState, InitalVesting,
+};
+pub use consensus::{App, APP_VERSION};
 pub use ethvm::tx::{token::DECIMAL, Tx as EvmTx};
 pub use tx::native::Tx as NativeTx;
@@ -1,3 +1,4 @@
+use crate::tx::error::*;
 use jsonrpc_core::{Error, ErrorCode};
 use serde_json::Value;
 
@@ -16,3 +17,30 @@ pub fn new_jsonrpc_error(msg: &str, data: Value) -> Error {
         data: Some(data),
     }
 }
+
+// The error of a rejected transaction, in the wording of geth,
+// so that wallets can recognize it.
+pub fn tx_rejected_error(code: u32, log: &str) -> Error {
+    let (code, message) = match code {
+        CODE_NONCE_TOO_LOW => (-32000, "nonce too low"),
+        CODE_NONCE_TOO_HIGH => (-32000, "nonce too high"),
+        CODE_INSUFFICIENT_FUNDS => {
+            (-32000, "insufficient funds for gas * price + value")
+        }
+        CODE_GAS_PRICE_TOO_LOW => (-32000, "transaction underpriced"),
+        CODE_REPLACEMENT_UNDERPRICED => (-32000, "replacement transaction underpriced"),
+        CODE_INTRINSIC_GAS_TOO_LOW => (-32000, "intrinsic gas too low"),
+        CODE_GAS_LIMIT_EXCEEDS_BLOCK => (-32000, "exceeds block gas limit"),
+        CODE_INVALID_SIGNATURE => (-32000, "invalid sender"),
+        CODE_INVALID_CHAIN_ID => (-32000, "invalid chain id"),
+        CODE_TOO_LARGE => (-32000, "oversized data"),
+        CODE_VALUE_OVERFLOW => (-32000, "value overflow"),
+        CODE_REVERTED => (3, "execution reverted"),
+        _ => (-32000, "transaction rejected"),
+    };
+    Error {
+        code: ErrorCode::ServerError(code),
+        message: message.to_owned(),
+        data: Some(Value::String(log.to_owned())),
+    }
+}
@@ -1,22 +1,20 @@
 use crate::{
     common::{
-        block_hash_to_evm_format, block_number_to_height, rollback_to_height,
-        tm_proposer_to_evm_format, HashValue,
+        block_hash_to_evm_format, block_number_to_height, tm_proposer_to_evm_format,
+        HashValue,
     },
-    ledger::{State, MAIN_BRANCH_NAME},
+    ledger::State,
     rpc::{
         error::new_jsonrpc_error,
-        utils::{
-            filter_block_logs, remove_branch_by_name, rollback_by_height, tx_to_web3_tx,
-            txs_to_web3_txs,
-        },
+        utils::{filter_block_logs, state_view, tx_to_web3_tx, txs_to_web3_txs},
     },
-    tx::Tx,
+    tx::{error::CODESPACE, Tx},
     EvmTx,
 };
 use byte_slice_cast::AsByteSlice;
 use ethereum::TransactionAny;
 use ethereum_types::{Bloom, H160, H256, H64, U256, U64};
+use evm::backend::Backend;
 use jsonrpc_core::{BoxFuture, Result};
 use rlp::{Decodable, Rlp};
 use serde_json::Value;
@@ -54,27 +52,13 @@ impl EthApi for EthApiImpl {
         address: H160,
         bn: Option<BlockNumber>,
     ) -> BoxFuture<Result<U256>> {
-        let new_branch_name =
-            match rollback_by_height(bn, None, Some(&self.state.evm), "balance") {
-                Ok(name) => name,
-                Err(e) => {
-                    return Box::pin(async { Err(e) });
-                }
-            };
-
-        let balance = if let Some(balance) = self.state.evm.OFUEL.accounts.get(&address)
-        {
-            balance.balance
-        } else {
-            U256::zero()
+        let balance = match state_view(&self.state, bn) {
+            Ok(view) => view.balance(address),
+            Err(e) => {
+                return Box::pin(async { Err(e) });
+            }
         };
 
-        if let Err(e) =
-            remove_branch_by_name(new_branch_name, None, Some(&self.state.evm))
-        {
-            return Box::pin(async { Err(e) });
-        }
-
         Box::pin(async move { Ok(balance) })
     }
 
@@ -91,16 +75,14 @@ impl EthApi for EthApiImpl {
         bn: Option<BlockNumber>,
     ) -> BoxFuture<Result<Bytes>> {
         let r;
-        let resp = self
-            .state
-            .evm
-            .call_contract(MAIN_BRANCH_NAME, req, bn)
-            .map_err(|e| {
+        let resp = state_view(&self.state, bn).and_then(|view| {
+            view.call_contract(req).map_err(|e| {
                 error::new_jsonrpc_error(
                     "call contract failed",
                     Value::String(e.to_string()),
                 )
-            });
+            })
+        });
 
         ruc::d!(format!("{:?}", resp));
 
@@ -209,28 +191,17 @@ impl EthApi for EthApiImpl {
         index: U256,
         bn: Option<BlockNumber>,
     ) -> BoxFuture<Result<H256>> {
-        let new_branch_name =
-            match rollback_by_height(bn, None, Some(&self.state.evm), "storage_at") {
-                Ok(name) => name,
-                Err(e) => {
-                    return Box::pin(async { Err(e) });
-                }
-            };
-
-        // TODO: I'm not sure if this is the right thing to do
-        let key = (&addr, &H256::from_slice(index.as_byte_slice()));
-
-        let val = if let Some(val) = self.state.evm.OFUEL.storages.get(&key) {
-            val
-        } else {
-            H256::default()
+        let view = match state_view(&self.state, bn) {
+            Ok(v) => v,
+            Err(e) => {
+                return Box::pin(async { Err(e) });
+            }
         };
 
-        if let Err(e) =
-            remove_branch_by_name(new_branch_name, None, Some(&self.state.evm))
-        {
-            return Box::pin(async { Err(e) });
-        }
+        // TODO: I'm not sure if this is the right thing to do
+        let val = view
+            .backend()
+            .storage(addr, H256::from_slice(index.as_byte_slice()));
 
         Box::pin(async move { Ok(val) })
     }
@@ -337,23 +308,6 @@ impl EthApi for EthApiImpl {
     ) -> BoxFuture<Result<Option<RichBlock>>> {
         let height = block_number_to_height(Some(bn), None, Some(&self.state.evm));
 
-        let new_branch_name = match rollback_to_height(
-            height,
-            None,
-            Some(&self.state.evm),
-            "block_by_number",
-        ) {
-            Ok(name) => name,
-            Err(e) => {
-                return Box::pin(async move {
-                    Err(new_jsonrpc_error(
-                        "rollback to height",
-                        Value::String(e.to_string()),
-                    ))
-                });
-            }
-        };
-
         let op = if let Some(block) = self.state.blocks.get(&height) {
             let proposer = tm_proposer_to_evm_format(&block.header.proposer);
 
@@ -367,20 +321,7 @@ impl EthApi for EthApiImpl {
             let chain_id = self.state.chain_id.get_value();
             let web3_txs = match txs_to_web3_txs(&block, chain_id, height) {
                 Ok(v) => v,
-                Err(e) => {
-                    // You must delete the branch before returning,
-                    // otherwise the next time you come in,
-                    // the same branch will exist and an error will be reported.
-                    if let Err(e) = remove_branch_by_name(
-                        new_branch_name,
-                        None,
-                        Some(&self.state.evm),
-                    ) {
-                        return Box::pin(async { Err(e) });
-                    }
-
-                    return Box::pin(async { Err(e) });
-                }
+                Err(e) => return Box::pin(async { Err(e) }),
             };
 
             let b = if is_complete {
@@ -442,12 +383,6 @@ impl EthApi for EthApiImpl {
             None
         };
 
-        if let Err(e) =
-            remove_branch_by_name(new_branch_name, None, Some(&self.state.evm))
-        {
-            return Box::pin(async { Err(e) });
-        }
-
         Box::pin(async { Ok(op) })
     }
 
@@ -456,34 +391,13 @@ impl EthApi for EthApiImpl {
         addr: H160,
         bn: Option<BlockNumber>,
     ) -> BoxFuture<Result<U256>> {
-        let height = block_number_to_height(bn, Some(&self.state), None);
-        let new_branch_name = match rollback_to_height(
-            height,
-            Some(&self.state),
-            None,
-            "transaction_count",
-        ) {
-            Ok(name) => name,
+        let nonce = match state_view(&self.state, bn) {
+            Ok(view) => view.backend().basic(addr).nonce,
             Err(e) => {
-                return Box::pin(async move {
-                    Err(new_jsonrpc_error(
-                        "rollback to height",
-                        Value::String(e.to_string()),
-                    ))
-                });
+                return Box::pin(async { Err(e) });
             }
         };
 
-        let mut nonce = U256::zero();
-
-        if let Some(account) = self.state.evm.OFUEL.accounts.get(&addr) {
-            nonce = account.nonce
-        }
-
-        if let Err(e) = remove_branch_by_name(new_branch_name, Some(&self.state), None) {
-            return Box::pin(async { Err(e) });
-        }
-
         Box::pin(async move { Ok(nonce) })
     }
 
@@ -508,59 +422,23 @@ impl EthApi for EthApiImpl {
     ) -> BoxFuture<Result<Option<U256>>> {
         let height = block_number_to_height(Some(bn), Some(&self.state), None);
 
-        let new_branch_name = match rollback_to_height(
-            height,
-            Some(&self.state),
-            None,
-            "block_transaction_count_by_number",
-        ) {
-            Ok(name) => name,
-            Err(e) => {
-                return Box::pin(async move {
-                    Err(new_jsonrpc_error(
-                        "rollback to height",
-                        Value::String(e.to_string()),
-                    ))
-                });
-            }
-        };
-
         let tx_count = if let Some(block) = self.state.blocks.get(&height) {
             block.txs.len()
         } else {
             Default::default()
         };
 
-        if let Err(e) =
-            remove_branch_by_name(new_branch_name, None, Some(&self.state.evm))
-        {
-            return Box::pin(async { Err(e) });
-        }
-
         Box::pin(async move { Ok(Some(U256::from(tx_count))) })
     }
 
     fn code_at(&self, addr: H160, bn: Option<BlockNumber>) -> BoxFuture<Result<Bytes>> {
-        let new_branch_name =
-            match rollback_by_height(bn, None, Some(&self.state.evm), "code_at") {
-                Ok(name) => name,
-                Err(e) => {
-                    return Box::pin(async { Err(e) });
-                }
-            };
-
-        let bytes = if let Some(account) = self.state.evm.OFUEL.accounts.get(&addr) {
-            account.code
-        } else {
-            Default::default()
+        let bytes = match state_view(&self.state, bn) {
+            Ok(view) => view.backend().code(addr),
+            Err(e) => {
+                return Box::pin(async { Err(e) });
+            }
         };
 
-        if let Err(e) =
-            remove_branch_by_name(new_branch_name, None, Some(&self.state.evm))
-        {
-            return Box::pin(async { Err(e) });
-        }
-
         Box::pin(async { Ok(Bytes::new(bytes)) })
     }
 
@@ -622,10 +500,18 @@ impl EthApi for EthApiImpl {
                 resp.clone(),
             ));
             if let Some(result) = resp.get("result") {
-                if let Some(code) = result.get("code") {
-                    if code.eq(&0) {
-                        r = Ok(block_hash_to_evm_format(&tx.hash()))
+                let code = result.get("code").and_then(|c| c.as_u64());
+                let codespace = result.get("codespace").and_then(|c| c.as_str());
+                match (code, codespace) {
+                    (Some(0), _) => r = Ok(block_hash_to_evm_format(&tx.hash())),
+                    (Some(code), Some(CODESPACE)) => {
+                        let log = result.get("log").and_then(|l| l.as_str());
+                        r = Err(error::tx_rejected_error(
+                            code as u32,
+                            log.unwrap_or_default(),
+                        ));
                     }
+                    _ => {}
                 }
             }
 
@@ -639,16 +525,14 @@ impl EthApi for EthApiImpl {
         bn: Option<BlockNumber>,
     ) -> BoxFuture<Result<U256>> {
         let r;
-        let resp = self
-            .state
-            .evm
-            .call_contract(MAIN_BRANCH_NAME, req, bn)
-            .map_err(|e| {
+        let resp = state_view(&self.state, bn).and_then(|view| {
+            view.call_contract(req).map_err(|e| {
                 error::new_jsonrpc_error(
                     "call contract failed",
                     Value::String(e.to_string()),
                 )
-            });
+            })
+        });
 
         ruc::d!(format!("{:?}", resp));
 
@@ -733,23 +617,6 @@ impl EthApi for EthApiImpl {
         index: Index,
     ) -> BoxFuture<Result<Option<Transaction>>> {
         let height = block_number_to_height(Some(bn), Some(&self.state), None);
-        let new_branch_name = match rollback_to_height(
-            height,
-            Some(&self.state),
-            None,
-            "transaction_by_block_number_and_index",
-        ) {
-            Ok(name) => name,
-            Err(e) => {
-                return Box::pin(async move {
-                    Err(new_jsonrpc_error(
-                        "rollback to height",
-                        Value::String(e.to_string()),
-                    ))
-                });
-            }
-        };
-
         let mut transaction = None;
 
         if let Some(block) = self.state.blocks.get(&height) {
@@ -771,12 +638,6 @@ impl EthApi for EthApiImpl {
             }
         }
 
-        if let Err(e) =
-            remove_branch_by_name(new_branch_name, None, Some(&self.state.evm))
-        {
-            return Box::pin(async { Err(e) });
-        }
-
         Box::pin(async { Ok(transaction) })
     }
 
@@ -8,5 +8,6 @@ pub use server::*;
 mod error;
 mod eth;
 mod net;
+mod ovr;
 mod utils;
 mod web3;
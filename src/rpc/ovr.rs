@@ -0,0 +1,147 @@
+//!
+//! # Ovr specific APIs
+//!
+//! State queries inside a block, at the position of a transaction.
+//!
+
+use crate::{
+    ledger::{view::StateView, State},
+    rpc::error::new_jsonrpc_error,
+};
+use ethereum_types::{H160, H256, U256, U64};
+use jsonrpc_core::{BoxFuture, IoDelegate, Params, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use web3_rpc_core::types::Bytes;
+
+pub(crate) struct OvrApiImpl {
+    pub state: State,
+}
+
+/// The state right before, or right after, a transaction of a block.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxPosition {
+    pub height: U64,
+    pub tx_index: U64,
+    #[serde(default)]
+    pub after: bool,
+}
+
+impl TxPosition {
+    fn view<'a>(&self, state: &'a State) -> Result<StateView<'a>> {
+        let (height, index) = (self.height.as_u64(), self.tx_index.as_u64());
+        if self.after {
+            state.view_after_tx(height, index)
+        } else {
+            state.view_before_tx(height, index)
+        }
+        .map_err(|e| new_jsonrpc_error("state view error", Value::String(e.to_string())))
+    }
+}
+
+impl OvrApiImpl {
+    pub fn to_delegate(self) -> IoDelegate<Self, ()> {
+        let mut delegate = IoDelegate::new(Arc::new(self));
+        delegate.add_method("ovr_getBalance", sync(Self::balance));
+        delegate.add_method("ovr_getTransactionCount", sync(Self::transaction_count));
+        delegate.add_method("ovr_getCode", sync(Self::code_at));
+        delegate.add_method("ovr_getStorageAt", sync(Self::storage_at));
+        delegate.add_method("ovr_getVestingSchedule", sync(Self::vesting_schedule));
+        delegate.add_method("ovr_getSpendableBalance", sync(Self::spendable_balance));
+        delegate
+    }
+
+    fn balance(&self, params: Params) -> Result<Value> {
+        let (address, pos): (H160, TxPosition) = params.parse()?;
+        to_value(pos.view(&self.state)?.balance(address))
+    }
+
+    fn transaction_count(&self, params: Params) -> Result<Value> {
+        let (address, pos): (H160, TxPosition) = params.parse()?;
+        to_value(pos.view(&self.state)?.nonce(address))
+    }
+
+    fn code_at(&self, params: Params) -> Result<Value> {
+        let (address, pos): (H160, TxPosition) = params.parse()?;
+        to_value(Bytes::new(pos.view(&self.state)?.code(address)))
+    }
+
+    // `null` for the accounts without vesting,
+    // read at the latest block if the height is absent.
+    fn vesting_schedule(&self, params: Params) -> Result<Value> {
+        let (address, view) = self.view_at(params)?;
+
+        let now = view.timestamp();
+        let info = view.vesting(address).map(|v| VestingInfo {
+            total: v.total,
+            start: U64::from(v.start),
+            cliff: U64::from(v.cliff),
+            end: U64::from(v.end),
+            vested: v.vested(now),
+            locked: v.locked(now),
+            block_number: U64::from(view.height()),
+            timestamp: U64::from(now),
+        });
+        to_value(info)
+    }
+
+    // `eth_getBalance` gives the total balance,
+    // this one leaves out the part locked at the time of the block.
+    fn spendable_balance(&self, params: Params) -> Result<Value> {
+        let (address, view) = self.view_at(params)?;
+        to_value(view.spendable_balance(address))
+    }
+
+    // `(address)` or `(address, height)`, 0 is the latest height
+    fn view_at(&self, params: Params) -> Result<(H160, StateView<'_>)> {
+        let (address, height) = params
+            .clone()
+            .parse::<(H160, U64)>()
+            .map(|(a, h)| (a, h.as_u64()))
+            .or_else(|_| params.parse::<(H160,)>().map(|(a,)| (a, 0)))?;
+        let view = self.state.view_at(height, None).map_err(|e| {
+            new_jsonrpc_error("state view error", Value::String(e.to_string()))
+        })?;
+        Ok((address, view))
+    }
+
+    fn storage_at(&self, params: Params) -> Result<Value> {
+        let (address, index, pos): (H160, U256, TxPosition) = params.parse()?;
+        let mut buf = [0; 32];
+        index.to_big_endian(&mut buf);
+        to_value(pos.view(&self.state)?.storage(address, H256::from(buf)))
+    }
+}
+
+/// A vesting schedule, with the released amount at the time of the block.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VestingInfo {
+    pub total: U256,
+    pub start: U64,
+    pub cliff: U64,
+    pub end: U64,
+    pub vested: U256,
+    pub locked: U256,
+    pub block_number: U64,
+    pub timestamp: U64,
+}
+
+fn sync<F>(
+    f: F,
+) -> impl Fn(&OvrApiImpl, Params) -> BoxFuture<Result<Value>> + Send + Sync + 'static
+where
+    F: Fn(&OvrApiImpl, Params) -> Result<Value> + Send + Sync + 'static,
+{
+    move |api: &OvrApiImpl, params: Params| -> BoxFuture<Result<Value>> {
+        let r = f(api, params);
+        Box::pin(async { r })
+    }
+}
+
+fn to_value<T: Serialize>(v: T) -> Result<Value> {
+    serde_json::to_value(v)
+        .map_err(|e| new_jsonrpc_error("serialize error", Value::String(e.to_string())))
+}
@@ -2,7 +2,7 @@ use std::{net::SocketAddr, thread};
 
 use crate::ledger::State;
 
-use super::{eth::EthApiImpl, net::NetApiImpl, web3::Web3ApiImpl};
+use super::{eth::EthApiImpl, net::NetApiImpl, ovr::OvrApiImpl, web3::Web3ApiImpl};
 use web3_rpc_core::{EthApi, NetApi, Web3Api};
 
 pub struct Web3ServerBuilder {
@@ -31,9 +31,14 @@ impl Web3ServerBuilder {
 
             let web3 = Web3ApiImpl {};
 
+            let ovr = OvrApiImpl {
+                state: self.state.clone(),
+            };
+
             io.extend_with(eth.to_delegate());
             io.extend_with(net.to_delegate());
             io.extend_with(web3.to_delegate());
+            io.extend_with(ovr.to_delegate());
 
             let s = jsonrpc_http_server::ServerBuilder::new(io.clone())
                 .start_http(http)
@@ -62,9 +67,14 @@ impl Web3ServerBuilder {
 
             let web3 = Web3ApiImpl {};
 
+            let ovr = OvrApiImpl {
+                state: self.state.clone(),
+            };
+
             io.extend_with(eth.to_delegate());
             io.extend_with(net.to_delegate());
             io.extend_with(web3.to_delegate());
+            io.extend_with(ovr.to_delegate());
 
             let s = jsonrpc_ws_server::ServerBuilder::new(io.clone())
                 .start(ws)
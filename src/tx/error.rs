@@ -0,0 +1,171 @@
+//!
+//! # Rejections of transactions
+//!
+//! Each kind has a stable ABCI code in the `ovr` codespace,
+//! `0` is reserved for success by tendermint.
+//!
+
+use primitive_types::U256;
+use std::fmt;
+
+pub const CODESPACE: &str = "ovr";
+
+pub const CODE_INTERNAL: u32 = 1;
+pub const CODE_DECODE: u32 = 2;
+pub const CODE_TOO_LARGE: u32 = 3;
+pub const CODE_INVALID_SIGNATURE: u32 = 4;
+pub const CODE_INVALID_CHAIN_ID: u32 = 5;
+pub const CODE_INTRINSIC_GAS_TOO_LOW: u32 = 6;
+pub const CODE_GAS_LIMIT_EXCEEDS_BLOCK: u32 = 7;
+pub const CODE_VALUE_OVERFLOW: u32 = 8;
+pub const CODE_GAS_PRICE_TOO_LOW: u32 = 9;
+pub const CODE_NONCE_TOO_LOW: u32 = 10;
+pub const CODE_NONCE_TOO_HIGH: u32 = 11;
+pub const CODE_INSUFFICIENT_FUNDS: u32 = 12;
+pub const CODE_REVERTED: u32 = 13;
+pub const CODE_OUT_OF_GAS: u32 = 14;
+pub const CODE_EVM_ERROR: u32 = 15;
+pub const CODE_NATIVE_TX_FAILED: u32 = 16;
+pub const CODE_REPLACEMENT_UNDERPRICED: u32 = 17;
+pub const CODE_TOO_MANY_REPLACEMENTS: u32 = 18;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TxError {
+    // failures of the node itself
+    Internal(String),
+    Decode(String),
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+    InvalidSignature,
+    InvalidChainId {
+        expected: u64,
+        got: u64,
+    },
+    IntrinsicGasTooLow {
+        intrinsic: u64,
+        gas_limit: U256,
+    },
+    GasLimitExceedsBlock {
+        gas_limit: U256,
+        block_gas_limit: U256,
+    },
+    ValueOverflow,
+    GasPriceTooLow {
+        min: U256,
+        got: U256,
+    },
+    NonceTooLow {
+        expected: U256,
+        got: U256,
+    },
+    NonceTooHigh {
+        expected: U256,
+        got: U256,
+    },
+    InsufficientFunds {
+        needed: U256,
+        balance: U256,
+    },
+    // the decoded `Error(string)` reason, if any
+    Reverted(String),
+    OutOfGas,
+    EvmError(String),
+    NativeTxFailed(String),
+    ReplacementUnderpriced {
+        min: U256,
+        got: U256,
+    },
+    TooManyReplacements {
+        max: usize,
+    },
+}
+
+impl TxError {
+    /// The ABCI code, never changed once assigned.
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Internal(_) => CODE_INTERNAL,
+            Self::Decode(_) => CODE_DECODE,
+            Self::TooLarge { .. } => CODE_TOO_LARGE,
+            Self::InvalidSignature => CODE_INVALID_SIGNATURE,
+            Self::InvalidChainId { .. } => CODE_INVALID_CHAIN_ID,
+            Self::IntrinsicGasTooLow { .. } => CODE_INTRINSIC_GAS_TOO_LOW,
+            Self::GasLimitExceedsBlock { .. } => CODE_GAS_LIMIT_EXCEEDS_BLOCK,
+            Self::ValueOverflow => CODE_VALUE_OVERFLOW,
+            Self::GasPriceTooLow { .. } => CODE_GAS_PRICE_TOO_LOW,
+            Self::NonceTooLow { .. } => CODE_NONCE_TOO_LOW,
+            Self::NonceTooHigh { .. } => CODE_NONCE_TOO_HIGH,
+            Self::InsufficientFunds { .. } => CODE_INSUFFICIENT_FUNDS,
+            Self::Reverted(_) => CODE_REVERTED,
+            Self::OutOfGas => CODE_OUT_OF_GAS,
+            Self::EvmError(_) => CODE_EVM_ERROR,
+            Self::NativeTxFailed(_) => CODE_NATIVE_TX_FAILED,
+            Self::ReplacementUnderpriced { .. } => CODE_REPLACEMENT_UNDERPRICED,
+            Self::TooManyReplacements { .. } => CODE_TOO_MANY_REPLACEMENTS,
+        }
+    }
+
+    #[inline(always)]
+    pub fn codespace(&self) -> &'static str {
+        CODESPACE
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Internal(e) => write!(f, "Internal error: {}", e),
+            Self::Decode(e) => write!(f, "Invalid data format: {}", e),
+            Self::TooLarge { size, max } => {
+                write!(f, "Tx too large, size: {}, max: {}", size, max)
+            }
+            Self::InvalidSignature => write!(f, "Invalid signature"),
+            Self::InvalidChainId { expected, got } => {
+                write!(f, "Invalid chain id: {}, should be: {}", got, expected)
+            }
+            Self::IntrinsicGasTooLow {
+                intrinsic,
+                gas_limit,
+            } => write!(
+                f,
+                "Intrinsic gas too low, needed: {}, gas limit: {}",
+                intrinsic, gas_limit
+            ),
+            Self::GasLimitExceedsBlock {
+                gas_limit,
+                block_gas_limit,
+            } => write!(
+                f,
+                "Gas limit exceeds the block gas limit: {} > {}",
+                gas_limit, block_gas_limit
+            ),
+            Self::ValueOverflow => write!(f, "Value overflow"),
+            Self::GasPriceTooLow { min, got } => {
+                write!(f, "Gas price is too low: {}, min: {}", got, min)
+            }
+            Self::NonceTooLow { expected, got } => {
+                write!(f, "Invalid nonce: {}, should be: {}", got, expected)
+            }
+            Self::NonceTooHigh { expected, got } => {
+                write!(f, "Invalid nonce: {}, should be: {}", got, expected)
+            }
+            Self::InsufficientFunds { needed, balance } => write!(
+                f,
+                "Insufficient balance, needed: {}, total: {}",
+                needed, balance
+            ),
+            Self::Reverted(reason) => write!(f, "Execution reverted: {}", reason),
+            Self::OutOfGas => write!(f, "Out of gas"),
+            Self::EvmError(e) => write!(f, "Evm error: {}", e),
+            Self::NativeTxFailed(e) => write!(f, "Native tx failed: {}", e),
+            Self::ReplacementUnderpriced { min, got } => {
+                write!(f, "Replacement gas price is too low: {}, min: {}", got, min)
+            }
+            Self::TooManyReplacements { max } => {
+                write!(f, "Too many replacements in this block, max: {}", max)
+            }
+        }
+    }
+}
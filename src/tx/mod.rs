@@ -2,16 +2,24 @@
 //! # Transaction logic
 //!
 
+pub mod error;
 pub mod native;
 
 use crate::{
     common::{hash_sha3_256, HashValue},
     ethvm,
+    ledger::StateBranch,
 };
+use error::TxError;
 use ruc::*;
 use serde::{Deserialize, Serialize};
+use std::result::Result as StdResult;
 use vsdb::ValueEn;
 
+/// Max size of an encoded transaction, in bytes,
+/// the json encoding takes about 4 bytes for each byte of the evm input.
+pub const MAX_TX_SIZE: usize = 512 * 1024;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Tx {
     Evm(ethvm::tx::Tx),
@@ -29,9 +37,25 @@ impl Tx {
         serde_json::from_slice(bytes).c(d!())
     }
 
-    // TODO
+    // The size is checked before the parsing,
+    // an oversized one is never parsed.
+    #[inline(always)]
+    pub(crate) fn decode_in_abci(bytes: &[u8]) -> StdResult<Tx, TxError> {
+        if bytes.len() > MAX_TX_SIZE {
+            return Err(TxError::TooLarge {
+                size: bytes.len(),
+                max: MAX_TX_SIZE,
+            });
+        }
+        Self::deserialize(bytes).map_err(|e| TxError::Decode(e.to_string()))
+    }
+
+    // Stateless checks, cheap enough to run before any execution.
     #[inline(always)]
-    pub(crate) fn valid_in_abci(&self) -> bool {
-        true
+    pub(crate) fn valid_in_abci(&self, sb: &StateBranch) -> StdResult<(), TxError> {
+        match self {
+            Tx::Evm(tx) => tx.valid_in_abci(sb),
+            Tx::Native(_) => Ok(()),
+        }
     }
 }
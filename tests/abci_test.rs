@@ -0,0 +1,1139 @@
+#![allow(warnings)]
+
+use abci::Application;
+use clap::Parser;
+use ethereum::{
+    LegacyTransaction, LegacyTransactionMessage, TransactionAction, TransactionAny,
+    TransactionSignature,
+};
+use evm::backend::{Apply, ApplyBackend, Backend, Basic};
+use libsecp256k1::{Message, PublicKey, SecretKey};
+use ovr::{
+    ethvm::{
+        fork::{EvmFork, ForkUpgrade},
+        precompile::DEFAULT_PRECOMPILE_CFG,
+        OvrAccount,
+    },
+    ledger::{ConsensusInfo, Ledger, MAIN_BRANCH_NAME},
+    tx::Tx,
+    App, DaemonCfg, EvmTx, GenesisContract, InitalAccount, InitalContract, InitalState,
+    InitalVesting, APP_VERSION,
+};
+use primitive_types::{H160, H256, U256};
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeMap;
+use tmtypes::{
+    abci::{
+        response_apply_snapshot_chunk::Result as ApplyResult,
+        response_offer_snapshot::Result as OfferResult, BlockParams, CheckTxType,
+        ConsensusParams, LastCommitInfo, RequestApplySnapshotChunk, RequestBeginBlock,
+        RequestCheckTx, RequestDeliverTx, RequestEndBlock, RequestInitChain,
+        RequestLoadSnapshotChunk, RequestOfferSnapshot, RequestQuery, Snapshot,
+        Validator, VoteInfo,
+    },
+    google::protobuf::Timestamp,
+    types::Header,
+};
+
+const CHAIN_ID: u64 = 1234;
+const GAS_PRICE: u64 = 10000000000;
+const BLOCK_GAS_LIMIT: u64 = 3000000;
+
+fn key(i: u8) -> SecretKey {
+    SecretKey::parse(&[i; 32]).unwrap()
+}
+
+fn addr(i: u8) -> H160 {
+    let pk = PublicKey::from_secret_key(&key(i)).serialize();
+    H160::from_slice(&Keccak256::digest(&pk[1..])[12..])
+}
+
+struct Params {
+    chain_id: u64,
+    nonce: u64,
+    gas_price: u64,
+    gas_limit: u64,
+    to: H160,
+    value: U256,
+    input: Vec<u8>,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            chain_id: CHAIN_ID,
+            nonce: 0,
+            gas_price: GAS_PRICE,
+            gas_limit: 21000,
+            to: addr(2),
+            value: U256::from(1000u64),
+            input: vec![],
+        }
+    }
+}
+
+fn sign(p: Params) -> Vec<u8> {
+    let msg = LegacyTransactionMessage {
+        nonce: p.nonce.into(),
+        gas_price: p.gas_price.into(),
+        gas_limit: p.gas_limit.into(),
+        action: TransactionAction::Call(p.to),
+        value: p.value,
+        input: p.input.clone(),
+        chain_id: Some(p.chain_id),
+    };
+    let (sig, rec) =
+        libsecp256k1::sign(&Message::parse(msg.hash().as_fixed_bytes()), &key(1));
+    let sig = sig.serialize();
+
+    let tx = LegacyTransaction {
+        nonce: msg.nonce,
+        gas_price: msg.gas_price,
+        gas_limit: msg.gas_limit,
+        action: msg.action,
+        value: msg.value,
+        input: p.input,
+        signature: TransactionSignature::new(
+            p.chain_id * 2 + 35 + rec.serialize() as u64,
+            H256::from_slice(&sig[..32]),
+            H256::from_slice(&sig[32..]),
+        )
+        .unwrap(),
+    };
+    let tx = Tx::Evm(EvmTx {
+        tx: TransactionAny::Legacy(tx),
+    });
+    serde_json::to_vec(&tx).unwrap()
+}
+
+fn new_app() -> App {
+    let ledger = Ledger::new(
+        CHAIN_ID,
+        String::from("TEST"),
+        String::from("1"),
+        Some(GAS_PRICE as u128),
+        Some(BLOCK_GAS_LIMIT as u128),
+        Some(1),
+    )
+    .unwrap();
+
+    let account = OvrAccount {
+        nonce: U256::zero(),
+        balance: U256::from(1000000000000000000000000u128),
+        code_hash: H256::zero(),
+    };
+    ledger
+        .state
+        .evm
+        .OFUEL
+        .accounts
+        .insert_by_branch(addr(1), account, MAIN_BRANCH_NAME)
+        .unwrap();
+    ledger.consensus_refresh(ConsensusInfo::default()).unwrap();
+
+    App {
+        cfg: DaemonCfg::parse_from(["ovrd"]),
+        ledger,
+    }
+}
+
+fn check(app: &App, tx: Vec<u8>) -> u32 {
+    app.check_tx(RequestCheckTx { tx, r#type: 0 }).code
+}
+
+#[test]
+fn test_check_tx_stateless_rejections() {
+    let app = new_app();
+
+    // decode error
+    assert_eq!(2, check(&app, b"not a tx".to_vec()));
+
+    // too large, never parsed
+    let garbage = vec![b'x'; 512 * 1024 + 1];
+    assert_eq!(3, check(&app, garbage.clone()));
+    let resp = app.deliver_tx(RequestDeliverTx { tx: garbage });
+    assert_eq!(3, resp.code);
+
+    // too large
+    let input = vec![1; 512 * 1024];
+    assert_eq!(
+        3,
+        check(
+            &app,
+            sign(Params {
+                input,
+                ..Default::default()
+            })
+        )
+    );
+
+    // chain id
+    assert_eq!(
+        5,
+        check(
+            &app,
+            sign(Params {
+                chain_id: 1,
+                ..Default::default()
+            })
+        )
+    );
+
+    // intrinsic gas
+    assert_eq!(
+        6,
+        check(
+            &app,
+            sign(Params {
+                gas_limit: 20999,
+                ..Default::default()
+            })
+        )
+    );
+    assert_eq!(
+        6,
+        check(
+            &app,
+            sign(Params {
+                gas_limit: 21000,
+                input: vec![1],
+                ..Default::default()
+            })
+        )
+    );
+
+    // block gas limit
+    assert_eq!(
+        7,
+        check(
+            &app,
+            sign(Params {
+                gas_limit: BLOCK_GAS_LIMIT + 1,
+                ..Default::default()
+            })
+        )
+    );
+
+    // value overflow
+    assert_eq!(
+        8,
+        check(
+            &app,
+            sign(Params {
+                value: U256::MAX,
+                ..Default::default()
+            })
+        )
+    );
+
+    // none of the above has been executed
+    assert_eq!(0, check(&app, sign(Params::default())));
+}
+
+#[test]
+fn test_check_tx_stateful_rejections() {
+    let app = new_app();
+
+    let resp = app.check_tx(RequestCheckTx {
+        tx: sign(Params {
+            nonce: 65,
+            ..Default::default()
+        }),
+        r#type: 0,
+    });
+    assert_eq!(11, resp.code);
+    assert_eq!("ovr", resp.codespace);
+
+    // insufficient funds
+    assert_eq!(
+        12,
+        check(
+            &app,
+            sign(Params {
+                value: U256::from(10).pow(U256::from(30)),
+                ..Default::default()
+            })
+        )
+    );
+
+    assert_eq!(0, check(&app, sign(Params::default())));
+
+    // the nonce has been taken by the previous one
+    assert_eq!(10, check(&app, sign(Params::default())));
+}
+
+fn recheck(app: &App, tx: Vec<u8>) -> u32 {
+    let r#type = CheckTxType::Recheck as i32;
+    app.check_tx(RequestCheckTx { tx, r#type }).code
+}
+
+fn nonce(n: u64) -> Vec<u8> {
+    sign(Params {
+        nonce: n,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_check_tx_nonce_gaps() {
+    let app = new_app();
+    let priority = |tx| {
+        let resp = app.check_tx(RequestCheckTx { tx, r#type: 0 });
+        assert_eq!(0, resp.code);
+        resp.priority
+    };
+
+    // queued behind the pending ones, the nearest nonce first
+    assert_eq!(-2, priority(nonce(2)));
+    assert_eq!(-1, priority(nonce(1)));
+    // out of the window
+    assert_eq!(11, check(&app, nonce(17)));
+
+    // the missing nonce promotes the queued ones
+    assert_eq!(GAS_PRICE as i64, priority(nonce(0)));
+    assert_eq!(GAS_PRICE as i64, priority(nonce(3)));
+    assert_eq!(10, check(&app, nonce(2)));
+    // another one of the same nonce needs a price bump
+    assert_eq!(
+        17,
+        check(
+            &app,
+            sign(Params {
+                nonce: 2,
+                value: 1.into(),
+                ..Default::default()
+            })
+        )
+    );
+
+    // an empty block, the rechecked ones survive it
+    app.ledger.commit().unwrap();
+    for n in 0..4 {
+        assert_eq!(0, recheck(&app, nonce(n)));
+    }
+
+    // still waiting after the next block, dropped by the recheck
+    assert_eq!(-1, priority(nonce(5)));
+    app.ledger.commit().unwrap();
+    for n in 0..4 {
+        assert_eq!(0, recheck(&app, nonce(n)));
+    }
+    assert_eq!(11, recheck(&app, nonce(5)));
+    assert_eq!(0, check(&app, nonce(4)));
+
+    // the first two are included by the next block
+    app.ledger
+        .consensus_refresh(ConsensusInfo::default())
+        .unwrap();
+    for n in 0..2 {
+        let tx = serde_json::from_slice(&nonce(n)).unwrap();
+        app.ledger.deliver_tx.write().apply_tx(tx).unwrap();
+    }
+    app.ledger.commit().unwrap();
+
+    assert_eq!(10, recheck(&app, nonce(1)));
+    for n in 2..5 {
+        assert_eq!(0, recheck(&app, nonce(n)));
+    }
+    assert_eq!(GAS_PRICE as i64, priority(nonce(5)));
+}
+
+#[test]
+fn test_deliver_tx_nonce_gap() {
+    let app = new_app();
+
+    let (_, before) = query(&app, format!("/balance/{:?}", addr(1)), false);
+
+    // a proposer running other code
+    let resp = app.deliver_tx(RequestDeliverTx { tx: nonce(1) });
+    assert_eq!(11, resp.code);
+    assert_eq!(0, resp.gas_used);
+    app.ledger.commit().unwrap();
+
+    let (_, after) = query(&app, format!("/balance/{:?}", addr(1)), false);
+    assert_eq!(before, after);
+
+    // queued by the honest nodes, proposed after the pending ones,
+    // and dropped by the recheck after the next block
+    let resp = app.check_tx(RequestCheckTx {
+        tx: nonce(1),
+        r#type: 0,
+    });
+    assert_eq!(0, resp.code);
+    assert!(resp.priority < 0);
+    app.ledger.commit().unwrap();
+    assert_eq!(11, recheck(&app, nonce(1)));
+}
+
+#[test]
+fn test_check_tx_eviction() {
+    let app = new_app();
+
+    assert_eq!(0, check(&app, nonce(0)));
+    assert_eq!(0, check(&app, nonce(1)));
+
+    // only the first one is rechecked, the other one has been evicted
+    app.ledger.commit().unwrap();
+    assert_eq!(0, recheck(&app, nonce(0)));
+
+    // resubmitted, no price bump is needed
+    assert_eq!(0, check(&app, nonce(1)));
+
+    // evicted both, then resubmitted in any order
+    app.ledger.commit().unwrap();
+    app.ledger.commit().unwrap();
+    assert_eq!(0, check(&app, nonce(1)));
+    assert_eq!(0, check(&app, nonce(0)));
+    assert_eq!(10, check(&app, nonce(1)));
+}
+
+fn priced(nonce: u64, gas_price: u64) -> Vec<u8> {
+    sign(Params {
+        nonce,
+        gas_price,
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_check_tx_priority_and_replacement() {
+    let app = new_app();
+
+    let resp = app.check_tx(RequestCheckTx {
+        tx: nonce(0),
+        r#type: 0,
+    });
+    assert_eq!(0, resp.code);
+    assert_eq!(GAS_PRICE as i64, resp.priority);
+
+    // the bump is too small
+    assert_eq!(17, check(&app, priced(0, GAS_PRICE / 100 * 105)));
+
+    let resp = app.check_tx(RequestCheckTx {
+        tx: priced(0, GAS_PRICE / 100 * 110),
+        r#type: 0,
+    });
+    assert_eq!(0, resp.code);
+    assert_eq!((GAS_PRICE / 100 * 110) as i64, resp.priority);
+
+    // the replaced one
+    assert_eq!(17, recheck(&app, nonce(0)));
+    assert_eq!(0, check(&app, nonce(1)));
+}
+
+#[test]
+fn test_check_tx_priority_in_nonce_order() {
+    let app = new_app();
+
+    let priority = |tx| {
+        let resp = app.check_tx(RequestCheckTx { tx, r#type: 0 });
+        assert_eq!(0, resp.code);
+        resp.priority
+    };
+
+    // never above the ones of the lower nonces
+    assert_eq!((GAS_PRICE * 2) as i64, priority(priced(0, GAS_PRICE * 2)));
+    assert_eq!((GAS_PRICE * 2) as i64, priority(priced(1, GAS_PRICE * 3)));
+    assert_eq!(GAS_PRICE as i64, priority(priced(2, GAS_PRICE)));
+    assert_eq!(GAS_PRICE as i64, priority(priced(3, GAS_PRICE * 4)));
+
+    // the replacements are limited in each block
+    let mut price = GAS_PRICE;
+    for _ in 0..64 {
+        price = price * 11 / 10 + 1;
+        assert_eq!(0, check(&app, priced(3, price)));
+    }
+    assert_eq!(18, check(&app, priced(3, price * 2)));
+
+    app.ledger.commit().unwrap();
+    assert_eq!(0, recheck(&app, priced(0, GAS_PRICE * 2)));
+    assert_eq!(0, recheck(&app, priced(1, GAS_PRICE * 3)));
+    assert_eq!(0, recheck(&app, priced(2, GAS_PRICE)));
+    assert_eq!(0, check(&app, priced(3, price * 2)));
+}
+
+#[test]
+fn test_deliver_tx_events_and_gas() {
+    let app = new_app();
+
+    let resp = app.deliver_tx(RequestDeliverTx {
+        tx: sign(Params {
+            gas_limit: 30000,
+            ..Default::default()
+        }),
+    });
+    assert_eq!(0, resp.code);
+    assert_eq!(30000, resp.gas_wanted);
+    assert_eq!(21000, resp.gas_used);
+
+    let event = &resp.events[0];
+    assert_eq!("ethereum_tx", event.r#type);
+    let attr = |key: &str| {
+        event
+            .attributes
+            .iter()
+            .find(|a| a.key == key.as_bytes())
+            .map(|a| String::from_utf8(a.value.clone()).unwrap())
+    };
+    assert_eq!(Some(format!("{:?}", addr(1))), attr("from"));
+    assert_eq!(Some(format!("{:?}", addr(2))), attr("to"));
+    assert_eq!(Some("1".to_owned()), attr("status"));
+    assert_eq!(None, attr("contract"));
+
+    // rejected before the execution
+    let resp = app.deliver_tx(RequestDeliverTx { tx: nonce(0) });
+    assert_eq!(10, resp.code);
+    assert_eq!(0, resp.gas_used);
+    assert_eq!(1, resp.events.len());
+}
+
+fn query(app: &App, path: String, prove: bool) -> (u32, serde_json::Value) {
+    let resp = app.query(RequestQuery {
+        data: vec![],
+        path,
+        height: 0,
+        prove,
+    });
+    let value = serde_json::from_slice(&resp.value).unwrap_or_default();
+    (resp.code, value)
+}
+
+#[test]
+fn test_query_paths() {
+    let app = new_app();
+
+    let tx = sign(Params::default());
+    assert_eq!(0, app.deliver_tx(RequestDeliverTx { tx }).code);
+    app.ledger.commit().unwrap();
+
+    let (code, balance) = query(&app, format!("/balance/{:?}", addr(2)), false);
+    assert_eq!(0, code);
+    assert_eq!(serde_json::to_value(U256::from(1000)).unwrap(), balance);
+
+    let (code, account) = query(&app, format!("/account/{:?}", addr(1)), false);
+    assert_eq!(0, code);
+    assert_eq!(serde_json::to_value(U256::one()).unwrap(), account["nonce"]);
+
+    let (code, block) = query(&app, "/block/1".to_owned(), false);
+    assert_eq!(0, code);
+    assert_eq!(1, block["txs"].as_array().unwrap().len());
+
+    let hash = block["txs"][0].as_str().unwrap().to_owned();
+    let (code, receipt) = query(&app, format!("/receipt/{}", hash), false);
+    assert_eq!(0, code);
+    assert_eq!(1, receipt["height"]);
+
+    assert_eq!(1, query(&app, "/unknown".to_owned(), false).0);
+    assert_eq!(1, query(&app, format!("/balance/{:?}", addr(2)), true).0);
+}
+
+#[test]
+fn test_view_vicinity() {
+    let app = new_app();
+
+    let tx = sign(Params::default());
+    assert_eq!(0, app.deliver_tx(RequestDeliverTx { tx }).code);
+    app.ledger.commit().unwrap();
+
+    app.ledger
+        .consensus_refresh(ConsensusInfo {
+            block_time_ns: 10_000_000_000,
+            ..Default::default()
+        })
+        .unwrap();
+    let tx = sign(Params {
+        nonce: 1,
+        ..Default::default()
+    });
+    assert_eq!(0, app.deliver_tx(RequestDeliverTx { tx }).code);
+    app.ledger.commit().unwrap();
+
+    // calls see the block of the view, not the latest one
+    let block = |height| {
+        let view = app.ledger.state.view_at(height, None).unwrap();
+        let backend = view.backend();
+        (backend.block_number(), backend.block_timestamp())
+    };
+    assert_eq!((U256::from(1), U256::zero()), block(1));
+    assert_eq!((U256::from(2), U256::from(10)), block(2));
+    assert_eq!(block(2), block(0));
+}
+
+fn query_call(app: &App, to: H160, input: Vec<u8>) -> serde_json::Value {
+    let call = serde_json::json!({
+        "to": format!("{:?}", to),
+        "data": format!("0x{}", hex::encode(input)),
+    });
+    let resp = app.query(RequestQuery {
+        data: serde_json::to_vec(&call).unwrap(),
+        path: "/call".to_owned(),
+        height: 0,
+        prove: false,
+    });
+    assert_eq!(0, resp.code);
+    serde_json::from_slice(&resp.value).unwrap()
+}
+
+#[test]
+fn test_query_call_gas_cap() {
+    let app = new_app();
+
+    // JUMPDEST, PUSH1 0, JUMP
+    let looping = Apply::Modify {
+        address: addr(3),
+        basic: Basic {
+            balance: U256::zero(),
+            nonce: U256::one(),
+        },
+        code: Some(vec![0x5b, 0x60, 0x00, 0x56]),
+        storage: Vec::<(H256, H256)>::new(),
+        reset_storage: false,
+    };
+    app.ledger
+        .state
+        .evm
+        .get_backend_hdr(MAIN_BRANCH_NAME)
+        .apply(vec![looping], vec![], false);
+    app.ledger.commit().unwrap();
+
+    // no more than the block gas limit without a gas given
+    let resp = query_call(&app, addr(3), vec![]);
+    assert_eq!(BLOCK_GAS_LIMIT, resp["gas_used"].as_u64().unwrap());
+}
+
+#[test]
+fn test_block_ctx_commit_power() {
+    let app = new_app();
+    app.ledger.commit().unwrap();
+
+    let vote = |power, signed_last_block| VoteInfo {
+        validator: Some(Validator {
+            address: vec![],
+            power,
+        }),
+        signed_last_block,
+    };
+    app.begin_block(RequestBeginBlock {
+        header: Some(Header {
+            height: 2,
+            time: Some(Timestamp::default()),
+            ..Default::default()
+        }),
+        last_commit_info: Some(LastCommitInfo {
+            round: 0,
+            votes: vec![vote(10, true), vote(20, false), vote(30, true)],
+        }),
+        ..Default::default()
+    });
+    app.ledger.commit().unwrap();
+
+    let resp = query_call(
+        &app,
+        H160::from_low_u64_be(0x1002),
+        abi_call("lastCommitPower()", &[]),
+    );
+    let data = resp["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|b| b.as_u64().unwrap() as u8)
+        .collect::<Vec<_>>();
+    assert_eq!(64, data.len());
+    assert_eq!(U256::from(40), U256::from_big_endian(&data[..32]));
+    assert_eq!(U256::from(60), U256::from_big_endian(&data[32..]));
+}
+
+#[test]
+fn test_fork_schedule_reload() {
+    let path =
+        std::env::temp_dir().join(format!("ovr_forks_{}.json", std::process::id()));
+    let mut app = new_app();
+    app.cfg = DaemonCfg::parse_from(["ovrd", "--fork-schedule", path.to_str().unwrap()]);
+    app.ledger.commit().unwrap();
+    app.ledger.commit().unwrap();
+
+    let identity = H160::from_low_u64_be(4);
+    let mut precompiles = DEFAULT_PRECOMPILE_CFG.clone();
+    precompiles.remove(&identity);
+    let write = |height: u64| {
+        let upgrade = ForkUpgrade {
+            hard_forks: [(height, EvmFork::Berlin)].into_iter().collect(),
+            precompiles: [(height, precompiles.clone())].into_iter().collect(),
+        };
+        std::fs::write(&path, serde_json::to_vec(&upgrade).unwrap()).unwrap();
+    };
+
+    // the committed blocks keep their rules
+    write(2);
+    assert!(app.load_fork_schedule().is_err());
+
+    write(3);
+    app.load_fork_schedule().unwrap();
+    let schedule = &app.ledger.state.evm.fork_schedule;
+    assert_eq!(EvmFork::Istanbul, schedule.fork_at(2));
+    assert_eq!(EvmFork::Berlin, schedule.fork_at(3));
+
+    let echo = |app: &App| query_call(app, identity, vec![7])["data"].clone();
+    assert_eq!(serde_json::json!([7]), echo(&app));
+    app.ledger.commit().unwrap();
+    assert_eq!(serde_json::json!([]), echo(&app));
+
+    // reloaded at the next startup, after the activation
+    app.load_fork_schedule().unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_state_sync() {
+    // no state hash in the header, nothing can be verified
+    let plain = new_app();
+    plain.ledger.commit().unwrap();
+    assert!(plain.ledger.export_snapshot(2).is_err());
+
+    let app = new_app();
+    let contracts = vec![GenesisContract {
+        name: "answer".to_owned(),
+        address: addr(3),
+        ..Default::default()
+    }];
+    app.ledger
+        .state
+        .genesis_contracts
+        .set_value(contracts)
+        .unwrap();
+    app.ledger.state.state_hash_itv.set_value(1).unwrap();
+
+    let tx = sign(Params::default());
+    assert_eq!(0, app.deliver_tx(RequestDeliverTx { tx }).code);
+    app.ledger.commit().unwrap();
+    let meta = app.ledger.export_snapshot(2).unwrap();
+    assert_eq!(
+        meta.app_hash,
+        app.info(Default::default()).last_block_app_hash
+    );
+
+    let snapshot = app
+        .list_snapshots(Default::default())
+        .snapshots
+        .into_iter()
+        .find(|s| s.height == meta.height && s.hash == meta.hash)
+        .unwrap();
+    let chunks = (0..snapshot.chunks)
+        .map(|index| {
+            app.load_snapshot_chunk(RequestLoadSnapshotChunk {
+                height: snapshot.height,
+                format: snapshot.format,
+                chunk: index,
+            })
+            .chunk
+        })
+        .collect::<Vec<_>>();
+
+    let restored = App {
+        cfg: DaemonCfg::parse_from(["ovrd"]),
+        ledger: Ledger::new(
+            CHAIN_ID,
+            String::from("TEST"),
+            String::from("1"),
+            Some(GAS_PRICE as u128),
+            Some(BLOCK_GAS_LIMIT as u128),
+            Some(1),
+        )
+        .unwrap(),
+    };
+    let offer = |snapshot: &Snapshot, app_hash: &[u8]| {
+        restored
+            .offer_snapshot(RequestOfferSnapshot {
+                snapshot: Some(snapshot.clone()),
+                app_hash: app_hash.to_vec(),
+            })
+            .result
+    };
+    let apply = |index: u32, chunk: Vec<u8>| {
+        restored.apply_snapshot_chunk(RequestApplySnapshotChunk {
+            index,
+            chunk,
+            sender: "peer".to_owned(),
+        })
+    };
+
+    // nothing offered yet
+    assert_eq!(
+        ApplyResult::Abort as i32,
+        apply(0, chunks[0].clone()).result
+    );
+
+    // verified against the app hash from the light client
+    assert_eq!(OfferResult::Reject as i32, offer(&snapshot, &[0; 32]));
+    let mut other = snapshot.clone();
+    other.format += 1;
+    assert_eq!(
+        OfferResult::RejectFormat as i32,
+        offer(&other, &meta.app_hash)
+    );
+    assert_eq!(OfferResult::Accept as i32, offer(&snapshot, &meta.app_hash));
+
+    // a corrupted chunk is fetched again from another peer
+    let mut corrupted = chunks[0].clone();
+    corrupted[0] ^= 1;
+    let resp = apply(0, corrupted);
+    assert_eq!(ApplyResult::Retry as i32, resp.result);
+    assert_eq!(vec![0], resp.refetch_chunks);
+    assert_eq!(vec!["peer".to_owned()], resp.reject_senders);
+
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        assert_eq!(
+            ApplyResult::Accept as i32,
+            apply(index as u32, chunk).result
+        );
+    }
+
+    let info = restored.info(Default::default());
+    assert_eq!(meta.height as i64, info.last_block_height);
+    assert_eq!(meta.app_hash, info.last_block_app_hash);
+
+    // only into an empty ledger
+    assert_eq!(OfferResult::Abort as i32, offer(&snapshot, &meta.app_hash));
+
+    assert_eq!(
+        query(&app, format!("/balance/{:?}", addr(2)), false),
+        query(&restored, format!("/balance/{:?}", addr(2)), false)
+    );
+    assert_eq!(
+        query(&app, format!("/nonce/{:?}", addr(1)), false),
+        query(&restored, format!("/nonce/{:?}", addr(1)), false)
+    );
+    let (_, contracts) = query(&restored, "/genesis/contracts".to_owned(), false);
+    let contracts: Vec<GenesisContract> = serde_json::from_value(contracts).unwrap();
+    assert_eq!(addr(3), contracts[0].address);
+}
+
+#[test]
+fn test_info_handshake() {
+    let app = new_app();
+
+    let resp = app.info(Default::default());
+    assert_eq!(0, resp.last_block_height);
+    assert!(resp.last_block_app_hash.is_empty());
+    assert_eq!(APP_VERSION, resp.app_version);
+    assert_eq!(env!("CARGO_PKG_VERSION"), resp.version);
+    assert_eq!("TEST 1", resp.data);
+
+    app.ledger.commit().unwrap();
+
+    let resp = app.info(Default::default());
+    assert_eq!(1, resp.last_block_height);
+    assert_eq!(
+        app.ledger.main.read().last_block_hash(),
+        resp.last_block_app_hash
+    );
+}
+
+#[test]
+fn test_consensus_params() {
+    let app = new_app();
+
+    let mut req = RequestInitChain::default();
+    req.consensus_params = Some(ConsensusParams {
+        block: Some(BlockParams {
+            max_bytes: 1024 * 1024,
+            max_gas: -1,
+        }),
+        ..Default::default()
+    });
+    let params = app.init_chain(req).consensus_params.unwrap();
+    let block = params.block.unwrap();
+    assert_eq!(1024 * 1024, block.max_bytes);
+    assert_eq!(BLOCK_GAS_LIMIT as i64, block.max_gas);
+    assert_eq!(100000, params.evidence.unwrap().max_age_num_blocks);
+
+    // nothing changed
+    let resp = app.end_block(RequestEndBlock::default());
+    assert!(resp.consensus_param_updates.is_none());
+
+    // changed by the block in process
+    app.ledger
+        .deliver_tx
+        .read()
+        .state
+        .evm
+        .block_gas_limit
+        .set_value(U256::from(BLOCK_GAS_LIMIT * 2))
+        .unwrap();
+    let resp = app.end_block(RequestEndBlock::default());
+    let block = resp.consensus_param_updates.unwrap().block.unwrap();
+    assert_eq!(1024 * 1024, block.max_bytes);
+    assert_eq!(BLOCK_GAS_LIMIT as i64 * 2, block.max_gas);
+}
+
+fn abi_call(signature: &str, words: &[U256]) -> Vec<u8> {
+    let mut input = Keccak256::digest(signature.as_bytes())[..4].to_vec();
+    for w in words.iter() {
+        let mut buf = [0u8; 32];
+        w.to_big_endian(&mut buf);
+        input.extend_from_slice(&buf);
+    }
+    input
+}
+
+#[test]
+fn test_consensus_params_governance() {
+    let app = new_app();
+
+    let inital_state = InitalState {
+        params_admin: Some(addr(1)),
+        ..Default::default()
+    };
+    let mut req = RequestInitChain::default();
+    req.app_state_bytes = serde_json::to_vec(&inital_state).unwrap();
+    app.init_chain(req);
+
+    let params = H160::from_low_u64_be(0x1003);
+    let call = |nonce: u64, input: Vec<u8>| {
+        app.deliver_tx(RequestDeliverTx {
+            tx: sign(Params {
+                nonce,
+                gas_limit: 100000,
+                to: params,
+                value: U256::zero(),
+                input,
+                ..Default::default()
+            }),
+        })
+        .code
+    };
+    let set_params = |evidence_max_bytes: u64| {
+        let words = [
+            2 * 1024 * 1024,
+            BLOCK_GAS_LIMIT * 2,
+            1000,
+            3600 * 1_000_000_000,
+            evidence_max_bytes,
+        ];
+        abi_call(
+            "setConsensusParams(uint256,uint256,uint256,uint256,uint256)",
+            &words.map(U256::from),
+        )
+    };
+
+    // evidences can not be larger than blocks
+    assert_eq!(13, call(0, set_params(4 * 1024 * 1024)));
+    let resp = app.end_block(RequestEndBlock::default());
+    assert!(resp.consensus_param_updates.is_none());
+
+    assert_eq!(0, call(1, set_params(1024)));
+    let resp = app.end_block(RequestEndBlock::default());
+    let updates = resp.consensus_param_updates.unwrap();
+    let block = updates.block.unwrap();
+    assert_eq!(2 * 1024 * 1024, block.max_bytes);
+    assert_eq!(BLOCK_GAS_LIMIT as i64 * 2, block.max_gas);
+    let evidence = updates.evidence.unwrap();
+    assert_eq!(1000, evidence.max_age_num_blocks);
+    assert_eq!(3600, evidence.max_age_duration.unwrap().seconds);
+    assert_eq!(1024, evidence.max_bytes);
+
+    // hand over to another admin
+    let set_admin = abi_call(
+        "setAdmin(address)",
+        &[U256::from_big_endian(H256::from(addr(2)).as_bytes())],
+    );
+    assert_eq!(0, call(2, set_admin));
+    assert_eq!(13, call(3, set_params(1024)));
+    app.ledger.commit().unwrap();
+
+    // reported once
+    app.ledger
+        .consensus_refresh(ConsensusInfo::default())
+        .unwrap();
+    let resp = app.end_block(RequestEndBlock::default());
+    assert!(resp.consensus_param_updates.is_none());
+}
+
+#[test]
+fn test_rich_genesis() {
+    let app = new_app();
+
+    // returns `602a60005260206000f3`, which returns 42
+    let runtime = "602a60005260206000f3";
+    let init_code = format!("600a600c600039600a6000f3{}", runtime);
+
+    let mut storage = BTreeMap::new();
+    storage.insert(H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+
+    let inital_state = InitalState {
+        addr_to_amount: [(addr(3), U256::from(1000))].into_iter().collect(),
+        inital_accounts: vec![InitalAccount {
+            address: H160::from_low_u64_be(0x1000),
+            balance: U256::from(5),
+            nonce: U256::one(),
+            code: format!("0x{}", runtime),
+            storage,
+        }],
+        inital_contracts: vec![InitalContract {
+            name: "answer".to_owned(),
+            from: addr(3),
+            salt: "salt".to_owned(),
+            bytecode: init_code,
+            constructor_args: format!("{:064x}", 7),
+            value: U256::from(100),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let mut req = RequestInitChain::default();
+    req.app_state_bytes = serde_json::to_vec(&inital_state).unwrap();
+    app.init_chain(req);
+
+    let fixed = format!("{:?}", H160::from_low_u64_be(0x1000));
+    let (_, code) = query(&app, format!("/code/{}", fixed), false);
+    assert_eq!(format!("0x{}", runtime), code);
+    let (_, nonce) = query(&app, format!("/nonce/{}", fixed), false);
+    assert_eq!(serde_json::to_value(U256::one()).unwrap(), nonce);
+    let (_, value) = query(&app, format!("/storage/{}/0x1", fixed), false);
+    assert_eq!(
+        serde_json::to_value(H256::from_low_u64_be(2)).unwrap(),
+        value
+    );
+
+    let (code, contracts) = query(&app, "/genesis/contracts".to_owned(), false);
+    assert_eq!(0, code);
+    let contracts: Vec<GenesisContract> = serde_json::from_value(contracts).unwrap();
+    assert_eq!(1, contracts.len());
+    assert_eq!("answer", contracts[0].name);
+
+    let contract = format!("{:?}", contracts[0].address);
+    let (_, code) = query(&app, format!("/code/{}", contract), false);
+    assert_eq!(format!("0x{}", runtime), code);
+    let (_, balance) = query(&app, format!("/balance/{}", contract), false);
+    assert_eq!(serde_json::to_value(U256::from(100)).unwrap(), balance);
+    let (_, balance) = query(&app, format!("/balance/{:?}", addr(3)), false);
+    assert_eq!(serde_json::to_value(U256::from(900)).unwrap(), balance);
+}
+
+#[test]
+fn test_vesting_accounts() {
+    let ledger = Ledger::new(
+        CHAIN_ID,
+        String::from("TEST"),
+        String::from("1"),
+        Some(GAS_PRICE as u128),
+        Some(BLOCK_GAS_LIMIT as u128),
+        Some(1),
+    )
+    .unwrap();
+    let app = App {
+        cfg: DaemonCfg::parse_from(["ovrd"]),
+        ledger,
+    };
+
+    let liquid = U256::from(1000000000000000000000000u128);
+    let inital_state = InitalState {
+        addr_to_amount: [(addr(1), liquid)].into_iter().collect(),
+        vesting_accounts: vec![InitalVesting {
+            address: addr(1),
+            total: liquid * 10,
+            start: 0,
+            cliff: 0,
+            end: 1000,
+        }],
+        ..Default::default()
+    };
+    let mut req = RequestInitChain::default();
+    req.app_state_bytes = serde_json::to_vec(&inital_state).unwrap();
+    app.init_chain(req);
+
+    // the block time is 0, nothing has been vested
+    let (_, balance) = query(&app, format!("/spendable/{:?}", addr(1)), false);
+    assert_eq!(serde_json::to_value(liquid).unwrap(), balance);
+
+    // the locked part is still counted in the total
+    let (_, balance) = query(&app, format!("/balance/{:?}", addr(1)), false);
+    assert_eq!(serde_json::to_value(liquid * 11).unwrap(), balance);
+
+    app.ledger
+        .consensus_refresh(ConsensusInfo::default())
+        .unwrap();
+
+    let resp = app.deliver_tx(RequestDeliverTx {
+        tx: sign(Params {
+            value: liquid * 2,
+            ..Default::default()
+        }),
+    });
+    assert_eq!(12, resp.code);
+
+    let resp = app.deliver_tx(RequestDeliverTx {
+        tx: sign(Params::default()),
+    });
+    assert_eq!(0, resp.code);
+}
+
+#[test]
+fn test_vesting_cliff() {
+    let ledger = Ledger::new(
+        CHAIN_ID,
+        String::from("TEST"),
+        String::from("1"),
+        Some(GAS_PRICE as u128),
+        Some(BLOCK_GAS_LIMIT as u128),
+        Some(1),
+    )
+    .unwrap();
+    let app = App {
+        cfg: DaemonCfg::parse_from(["ovrd"]),
+        ledger,
+    };
+
+    let total = U256::from(2000000000000000000000000u128);
+    let inital_state = InitalState {
+        vesting_accounts: vec![InitalVesting {
+            address: addr(1),
+            total,
+            start: 0,
+            cliff: 100,
+            end: 200,
+        }],
+        ..Default::default()
+    };
+    let mut req = RequestInitChain::default();
+    req.app_state_bytes = serde_json::to_vec(&inital_state).unwrap();
+    app.init_chain(req);
+
+    let block_at = |secs: u64| {
+        app.ledger
+            .consensus_refresh(ConsensusInfo {
+                block_time_ns: secs * 1_000_000_000,
+                ..Default::default()
+            })
+            .unwrap();
+    };
+    let transfer = |value: U256| {
+        app.deliver_tx(RequestDeliverTx {
+            tx: sign(Params {
+                value,
+                ..Default::default()
+            }),
+        })
+        .code
+    };
+
+    // one second before the cliff
+    block_at(99);
+    assert_eq!(12, transfer(1.into()));
+    app.ledger.commit().unwrap();
+
+    // at the cliff, the evm and the fee see the same time
+    block_at(100);
+    let fee = U256::from(21000 * GAS_PRICE);
+    let spendable = total / 2 - fee;
+    // no fee is waived
+    assert_eq!(12, transfer(spendable + 1));
+    assert_eq!(0, transfer(spendable));
+    app.ledger.commit().unwrap();
+
+    let (_, balance) = query(&app, format!("/spendable/{:?}", addr(1)), false);
+    assert_eq!(serde_json::to_value(U256::zero()).unwrap(), balance);
+    let (_, balance) = query(&app, format!("/balance/{:?}", addr(1)), false);
+    assert_eq!(serde_json::to_value(total / 2).unwrap(), balance);
+}
@@ -0,0 +1,243 @@
+#![allow(warnings)]
+
+use evm::backend::{Apply, ApplyBackend, Backend, Basic};
+use ovr::{
+    ethvm::impls::{backend::CodeHashBackend, cache::BackendCache},
+    ledger::{ConsensusInfo, Ledger},
+};
+use primitive_types::{H160, H256, U256};
+use sha3::{Digest, Keccak256};
+use vsdb::{BranchName, ParentBranchName, VersionName, VsMgmt, INITIAL_VERSION};
+
+const MAIN: BranchName = BranchName(b"Main");
+const DELIVER_TX: BranchName = BranchName(b"DeliverTx");
+const CHECK_TX: BranchName = BranchName(b"CheckTx");
+const RPC: BranchName = BranchName(b"storage_at_2");
+
+fn slot(i: u64) -> H256 {
+    H256::from_low_u64_be(i)
+}
+
+fn modify(
+    address: H160,
+    storage: &[(u64, u64)],
+    reset_storage: bool,
+) -> Apply<Vec<(H256, H256)>> {
+    Apply::Modify {
+        address,
+        basic: Basic {
+            balance: U256::one(),
+            nonce: U256::one(),
+        },
+        code: None,
+        storage: storage.iter().map(|(k, v)| (slot(*k), slot(*v))).collect(),
+        reset_storage,
+    }
+}
+
+fn apply(ledger: &Ledger, b: BranchName, values: Vec<Apply<Vec<(H256, H256)>>>) {
+    let mut backend = ledger.state.evm.get_backend_hdr(b);
+    backend.apply(values, vec![], false);
+}
+
+fn read(ledger: &Ledger, b: BranchName, address: H160, k: u64) -> H256 {
+    ledger
+        .state
+        .evm
+        .get_backend_hdr(b)
+        .storage(address, slot(k))
+}
+
+fn new_ledger() -> Ledger {
+    Ledger::new(
+        1234,
+        String::from("TEST"),
+        String::from("1"),
+        Some(10000000000),
+        Some(3000000),
+        Some(1),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_backend_branch_isolation() {
+    let ledger = new_ledger();
+    let addr = H160::from_low_u64_be(0x1234);
+
+    apply(&ledger, MAIN, vec![modify(addr, &[(1, 1)], false)]);
+
+    // DeliverTx and CheckTx are re-created from Main here
+    ledger.consensus_refresh(ConsensusInfo::default()).unwrap();
+
+    // a temporary child branch of Main
+    ledger
+        .state
+        .evm
+        .branch_create_by_base_branch(RPC, ParentBranchName::from(MAIN.0))
+        .unwrap();
+    ledger
+        .state
+        .evm
+        .version_create_by_branch(VersionName(b"rpc"), RPC)
+        .unwrap();
+
+    apply(
+        &ledger,
+        DELIVER_TX,
+        vec![modify(addr, &[(1, 2), (2, 3)], false)],
+    );
+    apply(&ledger, CHECK_TX, vec![modify(addr, &[(1, 0)], false)]);
+    apply(&ledger, RPC, vec![modify(addr, &[(1, 9)], false)]);
+
+    assert_eq!(slot(1), read(&ledger, MAIN, addr, 1));
+    assert_eq!(slot(0), read(&ledger, MAIN, addr, 2));
+
+    assert_eq!(slot(2), read(&ledger, DELIVER_TX, addr, 1));
+    assert_eq!(slot(3), read(&ledger, DELIVER_TX, addr, 2));
+
+    // writing zero deletes the slot
+    assert_eq!(slot(0), read(&ledger, CHECK_TX, addr, 1));
+    assert!(ledger
+        .state
+        .evm
+        .OFUEL
+        .storages
+        .get_by_branch(&(&addr, &slot(1)), CHECK_TX)
+        .is_none());
+
+    assert_eq!(slot(9), read(&ledger, RPC, addr, 1));
+
+    ledger.state.evm.branch_remove(RPC).unwrap();
+    assert_eq!(slot(1), read(&ledger, MAIN, addr, 1));
+    assert_eq!(slot(2), read(&ledger, DELIVER_TX, addr, 1));
+}
+
+#[test]
+fn test_backend_storage_wipes() {
+    let ledger = new_ledger();
+    let addr = H160::from_low_u64_be(0x5678);
+
+    apply(&ledger, MAIN, vec![modify(addr, &[(1, 1), (2, 2)], false)]);
+    ledger.consensus_refresh(ConsensusInfo::default()).unwrap();
+
+    // SELFDESTRUCT
+    apply(&ledger, DELIVER_TX, vec![Apply::Delete { address: addr }]);
+    assert!(!ledger.state.evm.get_backend_hdr(DELIVER_TX).exists(addr));
+    assert_eq!(slot(0), read(&ledger, DELIVER_TX, addr, 1));
+    assert_eq!(slot(0), read(&ledger, DELIVER_TX, addr, 2));
+
+    // re-created with fresh storage
+    apply(&ledger, CHECK_TX, vec![modify(addr, &[(3, 3)], true)]);
+    assert_eq!(slot(0), read(&ledger, CHECK_TX, addr, 1));
+    assert_eq!(slot(3), read(&ledger, CHECK_TX, addr, 3));
+
+    // nothing leaks back to Main
+    assert!(ledger.state.evm.get_backend_hdr(MAIN).exists(addr));
+    assert_eq!(slot(1), read(&ledger, MAIN, addr, 1));
+    assert_eq!(slot(2), read(&ledger, MAIN, addr, 2));
+    assert_eq!(slot(0), read(&ledger, MAIN, addr, 3));
+}
+
+#[test]
+fn test_backend_code_by_hash() {
+    let ledger = new_ledger();
+    let (a, b) = (H160::from_low_u64_be(0x9a), H160::from_low_u64_be(0x9b));
+    let code = vec![0x60, 0x00, 0x60, 0x00, 0xf3];
+    let hash = H256::from_slice(Keccak256::digest(&code).as_slice());
+
+    let with_code = |address| Apply::Modify {
+        address,
+        basic: Basic {
+            balance: U256::zero(),
+            nonce: U256::one(),
+        },
+        code: Some(code.clone()),
+        storage: vec![],
+        reset_storage: false,
+    };
+    apply(&ledger, MAIN, vec![with_code(a), with_code(b)]);
+
+    let accounts = &ledger.state.evm.OFUEL.accounts;
+    assert_eq!(hash, accounts.get_by_branch(&a, MAIN).unwrap().code_hash);
+    assert_eq!(hash, accounts.get_by_branch(&b, MAIN).unwrap().code_hash);
+
+    let codes = &ledger.state.evm.OFUEL.codes;
+    assert_eq!(Some(code.clone()), codes.get_by_branch(&hash, MAIN));
+
+    let backend = ledger.state.evm.get_backend_hdr(MAIN);
+    assert_eq!(code, backend.code(a));
+    assert_eq!(code, backend.code(b));
+    assert!(backend.code(H160::from_low_u64_be(0x9c)).is_empty());
+
+    // served from the accounts, and the same as hashing the code
+    let empty = H256::from_slice(Keccak256::digest(&[]).as_slice());
+    assert_eq!(hash, backend.code_hash(a));
+    assert_eq!(empty, backend.code_hash(H160::from_low_u64_be(0x9c)));
+}
+
+#[test]
+fn test_backend_cache() {
+    let ledger = new_ledger();
+    let cache = BackendCache::default();
+    let addr = H160::from_low_u64_be(0xca);
+
+    let cached = || ledger.state.evm.get_backend_hdr(MAIN).with_cache(&cache);
+
+    // write through
+    cached().apply(vec![modify(addr, &[(1, 1), (2, 2)], false)], vec![], false);
+    assert_eq!(slot(1), read(&ledger, MAIN, addr, 1));
+    assert_eq!(slot(1), cached().storage(addr, slot(1)));
+    assert_eq!(U256::one(), cached().basic(addr).nonce);
+
+    cached().apply(vec![modify(addr, &[(3, 3)], true)], vec![], false);
+    assert_eq!(slot(0), cached().storage(addr, slot(1)));
+    assert_eq!(slot(3), cached().storage(addr, slot(3)));
+
+    cached().apply(vec![Apply::Delete { address: addr }], vec![], false);
+    assert!(!cached().exists(addr));
+    assert_eq!(slot(0), cached().storage(addr, slot(3)));
+    assert!(!ledger.state.evm.get_backend_hdr(MAIN).exists(addr));
+
+    // writes that bypass the cache are invisible until it is cleared
+    apply(&ledger, MAIN, vec![modify(addr, &[(3, 9)], false)]);
+    assert!(!cached().exists(addr));
+    cache.clear();
+    assert!(cached().exists(addr));
+    assert_eq!(slot(9), cached().storage(addr, slot(3)));
+}
+
+#[test]
+fn test_backend_at_version() {
+    let ledger = new_ledger();
+    let addr = H160::from_low_u64_be(0xa7);
+
+    apply(&ledger, MAIN, vec![modify(addr, &[(1, 1)], false)]);
+    ledger
+        .state
+        .evm
+        .version_create_by_branch(VersionName(b"v2"), MAIN)
+        .unwrap();
+    apply(&ledger, MAIN, vec![modify(addr, &[(1, 2), (2, 2)], false)]);
+
+    let at = |v| ledger.state.evm.get_backend_hdr(MAIN).at_version(v);
+
+    assert_eq!(slot(1), at(INITIAL_VERSION).storage(addr, slot(1)));
+    assert_eq!(slot(0), at(INITIAL_VERSION).storage(addr, slot(2)));
+    assert_eq!(slot(2), at(VersionName(b"v2")).storage(addr, slot(1)));
+    assert_eq!(slot(2), read(&ledger, MAIN, addr, 1));
+
+    // historical versions are read-only
+    at(INITIAL_VERSION).apply(vec![modify(addr, &[(1, 9)], false)], vec![], false);
+    assert_eq!(slot(1), at(INITIAL_VERSION).storage(addr, slot(1)));
+    assert_eq!(slot(2), read(&ledger, MAIN, addr, 1));
+}
+
+#[test]
+fn test_backend_block_hash_out_of_range() {
+    let ledger = new_ledger();
+    let backend = ledger.state.evm.get_backend_hdr(MAIN);
+
+    assert_eq!(H256::zero(), backend.block_hash(U256::MAX));
+    assert_eq!(H256::zero(), backend.block_hash(U256::from(u64::MAX) + 1));
+}
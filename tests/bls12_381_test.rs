@@ -0,0 +1,227 @@
+#![allow(warnings)]
+
+//!
+//! The official EIP-2537 vectors(`assets/eip-2537/*.json` of the EIPs repo)
+//! are vendored in `tests/bls12_381/`, `$BLS12_381_VECTORS` overrides the path.
+//!
+//! The vendored revision predates the repricing of EIP-2537,
+//! so only the outputs are compared, the gas is checked by `bls12_381_gas`.
+//!
+
+use evm::{executor::stack::PrecompileFailure, Context};
+use fevm::Precompile;
+use ovr::ethvm::precompile::bls12_381::{
+    Bls12G1Add, Bls12G1Msm, Bls12G2Add, Bls12G2Msm, Bls12MapFp2ToG2, Bls12MapFpToG1,
+    Bls12Pairing,
+};
+use primitive_types::H160;
+use serde::Deserialize;
+use std::{env, fs, path::PathBuf};
+
+type Exec = fn(
+    &[u8],
+    Option<u64>,
+    &Context,
+    bool,
+) -> Result<evm::executor::stack::PrecompileOutput, PrecompileFailure>;
+
+const G1_X: &str = "17f1d3a73197d7942695638c4fa9ac0fc3688c4f9774b905a14e3a3f171bac586c55e83ff97a1aeffb3af00adb22c6bb";
+const G1_Y: &str = "08b3f481e3aaa0f1a09e30ed741d8ae4fcf5e095d5d00af600db18cb2c04b3edd03cc744a2888ae40caa232946c5e7e1";
+const G2_X0: &str = "024aa2b2f08f0a91260805272dc51051c6e47ad4fa403b02b4510b647ae3d1770bac0326a805bbefd48056c8c121bdb8";
+const G2_X1: &str = "13e02b6052719f607dacd3a088274f65596bd0d09920b61ab5da61bbdc7f5049334cf11213945d57e5ac7d055d042b7e";
+const G2_Y0: &str = "0ce5d527727d6e118cc9cdc6da2e351aadfd9baa8cbdd3a76d429a695160d12c923ac9cc3baca289e193548608b82801";
+const G2_Y1: &str = "0606c4a02ea734cc32acd2b02bc28b99cb3e287e85a763af267492ab572e99ab3f370d275cec1da1aaa9075ff05f79be";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Vector {
+    input: String,
+    #[serde(default)]
+    expected: Option<String>,
+    #[serde(default)]
+    expected_error: Option<String>,
+    name: String,
+}
+
+fn ctx() -> Context {
+    Context {
+        address: H160::zero(),
+        caller: H160::zero(),
+        apparent_value: Default::default(),
+    }
+}
+
+fn fp(be: &str) -> Vec<u8> {
+    let mut ret = vec![0u8; 16];
+    ret.extend(hex::decode(be).unwrap());
+    ret
+}
+
+fn g1() -> Vec<u8> {
+    [fp(G1_X), fp(G1_Y)].concat()
+}
+
+fn g2() -> Vec<u8> {
+    [fp(G2_X0), fp(G2_X1), fp(G2_Y0), fp(G2_Y1)].concat()
+}
+
+fn scalar(n: u8) -> Vec<u8> {
+    let mut ret = vec![0u8; 32];
+    ret[31] = n;
+    ret
+}
+
+fn exec(f: Exec, input: &[u8]) -> Vec<u8> {
+    f(input, None, &ctx(), false).unwrap().output
+}
+
+#[test]
+fn bls12_381_generators() {
+    let inf_g1 = vec![0u8; 128];
+    let inf_g2 = vec![0u8; 256];
+
+    // G + 0 == G
+    assert_eq!(
+        g1(),
+        exec(Bls12G1Add::execute, &[g1(), inf_g1.clone()].concat())
+    );
+    assert_eq!(
+        g2(),
+        exec(Bls12G2Add::execute, &[g2(), inf_g2.clone()].concat())
+    );
+
+    // G + G == 2 * G
+    let double_g1 = exec(Bls12G1Add::execute, &[g1(), g1()].concat());
+    assert_eq!(
+        double_g1,
+        exec(Bls12G1Msm::execute, &[g1(), scalar(2)].concat())
+    );
+    let double_g2 = exec(Bls12G2Add::execute, &[g2(), g2()].concat());
+    assert_eq!(
+        double_g2,
+        exec(Bls12G2Msm::execute, &[g2(), scalar(2)].concat())
+    );
+
+    // 1 * G + 2 * G == 3 * G
+    assert_eq!(
+        exec(Bls12G1Msm::execute, &[g1(), scalar(3)].concat()),
+        exec(
+            Bls12G1Msm::execute,
+            &[g1(), scalar(1), g1(), scalar(2)].concat()
+        )
+    );
+
+    // 0 * G == 0
+    assert_eq!(
+        inf_g1,
+        exec(Bls12G1Msm::execute, &[g1(), scalar(0)].concat())
+    );
+
+    // e(2 * G1, G2) * e(G1, -2 * G2) == 1, where `-2 * G2 == (r - 2) * G2`
+    let r_minus_2 =
+        hex::decode("73eda753299d7d483339d80809a1d80553bda402fffe5bfefffffffeffffffff")
+            .unwrap();
+    let neg_double_g2 = exec(Bls12G2Msm::execute, &[g2(), r_minus_2].concat());
+    let mut ok = vec![0u8; 32];
+    ok[31] = 1;
+    assert_eq!(
+        ok,
+        exec(
+            Bls12Pairing::execute,
+            &[double_g1.clone(), g2(), g1(), neg_double_g2].concat()
+        )
+    );
+    assert_eq!(
+        vec![0u8; 32],
+        exec(Bls12Pairing::execute, &[double_g1, g2()].concat())
+    );
+
+    // not on curve
+    let mut bad = g1();
+    bad[127] ^= 1;
+    assert!(Bls12G1Add::execute(&[bad, g1()].concat(), None, &ctx(), false).is_err());
+
+    // non-zero padding
+    let mut bad = g1();
+    bad[0] = 1;
+    assert!(Bls12G1Add::execute(&[bad, g1()].concat(), None, &ctx(), false).is_err());
+
+    // out of gas
+    assert!(Bls12MapFpToG1::execute(&fp(G1_X), Some(5499), &ctx(), false).is_err());
+}
+
+#[test]
+fn bls12_381_gas() {
+    let cost = |f: Exec, input: &[u8]| f(input, None, &ctx(), false).unwrap().cost;
+
+    assert_eq!(375, cost(Bls12G1Add::execute, &[g1(), g1()].concat()));
+    assert_eq!(600, cost(Bls12G2Add::execute, &[g2(), g2()].concat()));
+    assert_eq!(
+        12000,
+        cost(Bls12G1Msm::execute, &[g1(), scalar(2)].concat())
+    );
+    assert_eq!(
+        22500,
+        cost(Bls12G2Msm::execute, &[g2(), scalar(2)].concat())
+    );
+    assert_eq!(
+        37700 + 2 * 32600,
+        cost(Bls12Pairing::execute, &[g1(), g2(), g1(), g2()].concat())
+    );
+    assert_eq!(5500, cost(Bls12MapFpToG1::execute, &fp(G1_X)));
+    assert_eq!(
+        23800,
+        cost(Bls12MapFp2ToG2::execute, &[fp(G2_X0), fp(G2_X1)].concat())
+    );
+}
+
+#[test]
+fn bls12_381_official_vectors() {
+    let dir = env::var("BLS12_381_VECTORS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/bls12_381")
+        });
+
+    let cases: [(&str, Exec); 9] = [
+        ("add_G1_bls", Bls12G1Add::execute),
+        ("add_G2_bls", Bls12G2Add::execute),
+        ("mul_G1_bls", Bls12G1Msm::execute),
+        ("mul_G2_bls", Bls12G2Msm::execute),
+        ("multiexp_G1_bls", Bls12G1Msm::execute),
+        ("multiexp_G2_bls", Bls12G2Msm::execute),
+        ("pairing_check_bls", Bls12Pairing::execute),
+        ("map_fp_to_G1_bls", Bls12MapFpToG1::execute),
+        ("map_fp2_to_G2_bls", Bls12MapFp2ToG2::execute),
+    ];
+
+    for (name, f) in cases.iter() {
+        for prefix in ["", "fail-"] {
+            let path = dir.join(format!("{}{}.json", prefix, name));
+            let vectors =
+                fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            let vectors = serde_json::from_slice::<Vec<Vector>>(&vectors).unwrap();
+            assert!(!vectors.is_empty(), "{}", path.display());
+
+            for v in vectors.iter() {
+                let input = hex::decode(v.input.trim_start_matches("0x")).unwrap();
+                let ret = f(&input, None, &ctx(), false);
+                match (v.expected.as_ref(), v.expected_error.as_ref()) {
+                    (Some(expected), _) => {
+                        let ret = ret.unwrap_or_else(|e| {
+                            panic!("{}: {:?}", v.name, e);
+                        });
+                        assert_eq!(
+                            hex::decode(expected.trim_start_matches("0x")).unwrap(),
+                            ret.output,
+                            "{}",
+                            v.name
+                        );
+                    }
+                    (None, Some(_)) => assert!(ret.is_err(), "{}", v.name),
+                    _ => panic!("{}: invalid vector", v.name),
+                }
+            }
+        }
+    }
+}
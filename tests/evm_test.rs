@@ -5,15 +5,18 @@ use ethereum::{
     TransactionAction, TransactionAny, TransactionSignature, TransactionV1,
     TransactionV2,
 };
-use evm::backend::ApplyBackend;
+use evm::backend::{Apply, ApplyBackend, Basic};
+use evm::executor::stack::{StackExecutor, StackSubstateMetadata};
 use evm::ExitReason;
 use ovr::{
     ethvm::{
-        impls::backend::OvrBackend,
+        fork::EvmFork,
+        impls::{backend::OvrBackend, stack::OvrStackState},
+        precompile::OvrPrecompileSet,
         tx::{ExecRet, Tx},
         OvrAccount, OvrVicinity,
     },
-    ledger::{Ledger, StateBranch},
+    ledger::{Ledger, StateBranch, MAIN_BRANCH_NAME},
     tx, EvmTx,
 };
 use primitive_types::{H160, H256, U256};
@@ -28,14 +31,14 @@ fn init_state() -> Ledger {
     let acc1 = OvrAccount {
         nonce: U256::from(1u128),
         balance: U256::from(1000000000000000000000000u128),
-        code: vec![],
+        code_hash: H256::zero(),
     };
 
     let addr2 = H160::from_str(ADDR2).unwrap();
     let acc2 = OvrAccount {
         nonce: U256::from(10u128),
         balance: U256::from(1000000000000000000000000u128),
-        code: vec![],
+        code_hash: H256::zero(),
     };
 
     let mut ledger = Ledger::new(
@@ -725,3 +728,48 @@ fn test_eip1559_tx() {
     );
     println!("tx fee: {:?}\n", acc2_balance_without_tx_fee - acc2.balance);
 }
+
+// A slot cleared and then restored in the same transaction,
+// the EIP-2200/EIP-3529 metering must see the value at the start of the tx.
+#[test]
+fn test_evm_sstore_refund() {
+    let ledger = init_state();
+    let contract = H160::from_low_u64_be(0x5570);
+
+    let deploy = Apply::Modify {
+        address: contract,
+        basic: Basic {
+            balance: U256::zero(),
+            nonce: U256::one(),
+        },
+        // PUSH1 0, PUSH1 0, SSTORE, PUSH1 1, PUSH1 0, SSTORE, STOP
+        code: Some(vec![
+            0x60, 0x00, 0x60, 0x00, 0x55, 0x60, 0x01, 0x60, 0x00, 0x55, 0x00,
+        ]),
+        storage: vec![(H256::zero(), H256::from_low_u64_be(1))],
+        reset_storage: false,
+    };
+    let mut backend = ledger.state.evm.get_backend_hdr(MAIN_BRANCH_NAME);
+    backend.apply(vec![deploy], vec![], false);
+
+    let cfg = EvmFork::London.evm_cfg();
+    let precompiles = OvrPrecompileSet::new(&Default::default(), None, None, None);
+    let metadata = StackSubstateMetadata::new(100_000, &cfg);
+    let state = OvrStackState::new(metadata, &backend);
+    let mut executor = StackExecutor::new_with_precompiles(state, &cfg, &precompiles);
+
+    let (exit_reason, _) = executor.transact_call(
+        H160::from_str(ADDR1).unwrap(),
+        contract,
+        U256::zero(),
+        vec![],
+        100_000,
+        vec![],
+    );
+    assert!(exit_reason.is_succeed());
+
+    // 21000 + 4 * PUSH1
+    // + (2100 + 2900), cold and reset, 4800 refunded for the clear
+    // + 100, dirty, 4800 taken back and 2800 refunded for the restore
+    assert_eq!(21000 + 12 + 5000 + 100 - 2800, executor.used_gas());
+}
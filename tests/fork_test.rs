@@ -0,0 +1,62 @@
+#![allow(warnings)]
+
+use ovr::ethvm::fork::{EvmFork, ForkSchedule};
+use std::collections::BTreeMap;
+
+fn schedule(forks: &[(u64, EvmFork)]) -> BTreeMap<u64, EvmFork> {
+    forks.iter().copied().collect()
+}
+
+#[test]
+fn test_fork_schedule_set() {
+    let s = ForkSchedule::new();
+    s.set(
+        &schedule(&[(0, EvmFork::Istanbul), (10, EvmFork::Berlin)]),
+        None,
+    )
+    .unwrap();
+
+    // extended forward
+    s.set(&schedule(&[(20, EvmFork::London)]), Some(5)).unwrap();
+
+    // committed blocks keep their rules
+    assert!(s.set(&schedule(&[(5, EvmFork::London)]), Some(5)).is_err());
+    assert!(s.set(&schedule(&[(3, EvmFork::London)]), Some(5)).is_err());
+
+    // never back to an older fork
+    assert!(s.set(&schedule(&[(30, EvmFork::Berlin)]), Some(5)).is_err());
+
+    assert_eq!(
+        vec![
+            (0, EvmFork::Istanbul),
+            (10, EvmFork::Berlin),
+            (20, EvmFork::London)
+        ],
+        s.iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_fork_schedule_evm_cfg_at() {
+    let s = ForkSchedule::new();
+
+    // Istanbul if nothing is scheduled
+    assert_eq!(EvmFork::Istanbul, s.fork_at(100));
+    assert!(!s.evm_cfg_at(100).increase_state_access_gas);
+
+    s.set(
+        &schedule(&[(10, EvmFork::Berlin), (20, EvmFork::London)]),
+        None,
+    )
+    .unwrap();
+
+    assert_eq!(EvmFork::Istanbul, s.fork_at(9));
+    assert_eq!(EvmFork::Berlin, s.fork_at(10));
+    assert_eq!(EvmFork::Berlin, s.fork_at(19));
+    assert_eq!(EvmFork::London, s.fork_at(20));
+
+    let (berlin, london) = (s.evm_cfg_at(10), s.evm_cfg_at(u64::MAX));
+    assert!(berlin.increase_state_access_gas);
+    assert!(!berlin.decrease_clears_refund);
+    assert!(london.decrease_clears_refund);
+}
@@ -0,0 +1,296 @@
+#![allow(warnings)]
+
+use ethereum::{
+    LegacyTransaction, LegacyTransactionMessage, TransactionAction, TransactionAny,
+    TransactionSignature,
+};
+use evm::backend::Backend;
+use libsecp256k1::{Message, PublicKey, SecretKey};
+use ovr::{
+    ethvm::OvrAccount,
+    ledger::{ConsensusInfo, Ledger, MAIN_BRANCH_NAME},
+    tx::Tx,
+    EvmTx,
+};
+use primitive_types::{H160, H256, U256};
+use rlp::RlpStream;
+use sha3::{Digest, Keccak256};
+
+const CHAIN_ID: u64 = 1234;
+const GAS_PRICE: u64 = 10000000000;
+
+// `SSTORE(0, 1)`, then deploys a counter: `SSTORE(0, SLOAD(0) + 1)`
+const INIT_CODE: [u8; 27] = [
+    0x60, 0x01, 0x60, 0x00, 0x55, // SSTORE(0, 1)
+    0x60, 0x0a, 0x60, 0x11, 0x60, 0x00, 0x39, // CODECOPY(0, 17, 10)
+    0x60, 0x0a, 0x60, 0x00, 0xf3, // RETURN(0, 10)
+    0x60, 0x00, 0x54, 0x60, 0x01, 0x01, 0x60, 0x00, 0x55, 0x00,
+];
+
+fn staking() -> H160 {
+    H160::from_low_u64_be(0x1001)
+}
+
+fn validator() -> H160 {
+    H160::from_low_u64_be(0xb0)
+}
+
+fn key(i: u8) -> SecretKey {
+    SecretKey::parse(&[i; 32]).unwrap()
+}
+
+fn addr(i: u8) -> H160 {
+    let pk = PublicKey::from_secret_key(&key(i)).serialize();
+    H160::from_slice(&Keccak256::digest(&pk[1..])[12..])
+}
+
+fn sign(from: u8, nonce: u64, action: TransactionAction, input: Vec<u8>) -> Tx {
+    let msg = LegacyTransactionMessage {
+        nonce: nonce.into(),
+        gas_price: GAS_PRICE.into(),
+        gas_limit: U256::from(100000u64),
+        action,
+        value: U256::from(1000u64),
+        input: input.clone(),
+        chain_id: Some(CHAIN_ID),
+    };
+    let (sig, rec) =
+        libsecp256k1::sign(&Message::parse(msg.hash().as_fixed_bytes()), &key(from));
+    let sig = sig.serialize();
+
+    let tx = LegacyTransaction {
+        nonce: msg.nonce,
+        gas_price: msg.gas_price,
+        gas_limit: msg.gas_limit,
+        action: msg.action,
+        value: msg.value,
+        input,
+        signature: TransactionSignature::new(
+            CHAIN_ID * 2 + 35 + rec.serialize() as u64,
+            H256::from_slice(&sig[..32]),
+            H256::from_slice(&sig[32..]),
+        )
+        .unwrap(),
+    };
+    Tx::Evm(EvmTx {
+        tx: TransactionAny::Legacy(tx),
+    })
+}
+
+fn transfer(from: u8, nonce: u64, to: u8) -> Tx {
+    sign(from, nonce, TransactionAction::Call(addr(to)), vec![])
+}
+
+fn blocks(counter: H160) -> Vec<Vec<Tx>> {
+    let delegate = [
+        Keccak256::digest(b"delegate(address)")[..4].to_vec(),
+        H256::from(validator()).as_bytes().to_vec(),
+    ]
+    .concat();
+
+    vec![
+        vec![
+            transfer(1, 0, 2),
+            transfer(3, 0, 4),
+            // the same sender as the first one
+            transfer(1, 1, 5),
+            // spends what it received from the first one
+            transfer(2, 0, 6),
+            // invalid nonce
+            transfer(5, 5, 1),
+            sign(4, 0, TransactionAction::Create, INIT_CODE.to_vec()),
+            transfer(6, 0, 3),
+        ],
+        vec![
+            // the same storage slot
+            sign(1, 2, TransactionAction::Call(counter), vec![]),
+            sign(2, 1, TransactionAction::Call(counter), vec![]),
+            transfer(5, 0, 6),
+            // written outside of the evm state
+            sign(3, 1, TransactionAction::Call(staking()), delegate),
+            transfer(3, 2, 4),
+            transfer(6, 1, 1),
+        ],
+    ]
+}
+
+// Results, receipts, state hash and app hash of each block.
+type BlockRet = (Vec<bool>, serde_json::Value, Vec<u8>, Vec<u8>);
+
+fn run(parallel: bool) -> (Vec<BlockRet>, Vec<(U256, U256)>, H256, U256) {
+    let ledger = Ledger::new(
+        CHAIN_ID,
+        String::from("TEST"),
+        String::from("1"),
+        Some(GAS_PRICE as u128),
+        Some(3000000),
+        Some(1),
+    )
+    .unwrap();
+
+    for i in 1..=6 {
+        let account = OvrAccount {
+            nonce: U256::zero(),
+            balance: U256::from(1000000000000000000000000u128),
+            code_hash: H256::zero(),
+        };
+        ledger
+            .state
+            .evm
+            .OFUEL
+            .accounts
+            .insert_by_branch(addr(i), account, MAIN_BRANCH_NAME)
+            .unwrap();
+    }
+    ledger
+        .state
+        .staking
+        .register_validator(validator(), MAIN_BRANCH_NAME)
+        .unwrap();
+    ledger.state.state_hash_itv.set_value(1).unwrap();
+
+    // created by the first tx of `addr(4)`
+    let counter = {
+        let mut s = RlpStream::new_list(2);
+        s.append(&addr(4));
+        s.append(&U256::zero());
+        H160::from_slice(&Keccak256::digest(&s.out())[12..])
+    };
+
+    let mut rets = vec![];
+    for txs in blocks(counter) {
+        ledger.consensus_refresh(ConsensusInfo::default()).unwrap();
+        let results = {
+            let mut sb = ledger.deliver_tx.write();
+            if parallel {
+                sb.apply_txs_parallel(txs)
+            } else {
+                txs.into_iter().map(|tx| sb.apply_tx(tx)).collect()
+            }
+        };
+        let app_hash = ledger.commit().unwrap();
+
+        let block = ledger.state.blocks.last().unwrap().1;
+        rets.push((
+            results.iter().map(|r| r.is_ok()).collect(),
+            serde_json::to_value(&block.header.receipts).unwrap(),
+            block.header.state_hash,
+            app_hash,
+        ));
+    }
+
+    let accounts = (1..=6)
+        .map(|i| {
+            let a = ledger
+                .state
+                .evm
+                .OFUEL
+                .accounts
+                .get_by_branch(&addr(i), MAIN_BRANCH_NAME)
+                .unwrap();
+            (a.nonce, a.balance)
+        })
+        .collect();
+    let slot = ledger
+        .state
+        .evm
+        .get_backend_hdr(MAIN_BRANCH_NAME)
+        .storage(counter, H256::zero());
+    let delegated =
+        ledger
+            .state
+            .staking
+            .delegation_of(addr(3), validator(), MAIN_BRANCH_NAME);
+
+    (rets, accounts, slot, delegated)
+}
+
+#[test]
+fn test_parallel_same_as_sequential() {
+    let sequential = run(false);
+    let parallel = run(true);
+
+    let results = sequential
+        .0
+        .iter()
+        .map(|(r, ..)| r.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            vec![true, true, true, true, false, true, true],
+            vec![true; 6]
+        ],
+        results
+    );
+    assert!(sequential
+        .0
+        .iter()
+        .all(|(.., state_hash, _)| !state_hash.is_empty()));
+    assert_eq!(H256::from_low_u64_be(3), sequential.2);
+    assert_eq!(U256::from(1000u64), sequential.3);
+
+    for (s, p) in sequential.0.iter().zip(parallel.0.iter()) {
+        assert_eq!(s.0, p.0);
+        assert_eq!(s.1, p.1, "receipts");
+        assert_eq!(s.2, p.2, "state hash");
+        assert_eq!(s.3, p.3, "app hash");
+    }
+    assert_eq!(sequential, parallel);
+}
+
+#[test]
+fn test_state_views_inside_block() {
+    let ledger = Ledger::new(
+        CHAIN_ID,
+        String::from("TEST"),
+        String::from("1"),
+        Some(GAS_PRICE as u128),
+        Some(3000000),
+        Some(1),
+    )
+    .unwrap();
+
+    let initial = U256::from(1000000000000000000000000u128);
+    let account = OvrAccount {
+        nonce: U256::zero(),
+        balance: initial,
+        code_hash: H256::zero(),
+    };
+    ledger
+        .state
+        .evm
+        .OFUEL
+        .accounts
+        .insert_by_branch(addr(1), account, MAIN_BRANCH_NAME)
+        .unwrap();
+
+    ledger.consensus_refresh(ConsensusInfo::default()).unwrap();
+    {
+        let mut sb = ledger.deliver_tx.write();
+        sb.apply_tx(transfer(1, 0, 2)).unwrap();
+        sb.apply_tx(transfer(1, 1, 2)).unwrap();
+    }
+    ledger.commit().unwrap();
+
+    let state = &ledger.state;
+    let height = state.blocks.last().unwrap().0;
+
+    let before = state.view_before_tx(height, 1).unwrap();
+    let after = state.view_after_tx(height, 1).unwrap();
+
+    assert_eq!(U256::one(), before.nonce(addr(1)));
+    assert_eq!(U256::from(2u64), after.nonce(addr(1)));
+    assert_eq!(U256::from(1000u64), before.balance(addr(2)));
+    assert_eq!(U256::from(2000u64), after.balance(addr(2)));
+    assert!(before.balance(addr(1)) > after.balance(addr(1)));
+
+    assert_eq!(
+        U256::zero(),
+        state.view_before_tx(height, 0).unwrap().nonce(addr(1))
+    );
+    assert_eq!(
+        after.balance(addr(1)),
+        state.view_at(0, None).unwrap().balance(addr(1))
+    );
+    assert!(state.view_after_tx(height, 2).is_err());
+}
@@ -0,0 +1,113 @@
+#![allow(warnings)]
+
+use evm::{
+    executor::stack::{PrecompileFailure, PrecompileOutput, PrecompileSet},
+    Context, ExitSucceed,
+};
+use ovr::ethvm::{
+    fork::ForkSchedule,
+    precompile::{
+        check_cfg, register, registered, OvrPrecompileSet, PrecompileCfg,
+        DEFAULT_PRECOMPILE_CFG, ECRECOVER, SHA256, STAKING,
+    },
+};
+use primitive_types::H160;
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeMap;
+
+fn answer(
+    _: &[u8],
+    _: Option<u64>,
+    _: &Context,
+    _: bool,
+) -> Result<PrecompileOutput, PrecompileFailure> {
+    Ok(PrecompileOutput {
+        exit_status: ExitSucceed::Returned,
+        cost: 15,
+        output: vec![42],
+        logs: vec![],
+    })
+}
+
+fn ctx(address: H160) -> Context {
+    Context {
+        address,
+        caller: H160::zero(),
+        apparent_value: Default::default(),
+    }
+}
+
+#[test]
+fn test_precompile_register() {
+    register("answer_register", answer).unwrap();
+    assert!(registered().contains(&"answer_register".to_owned()));
+
+    // names are unique, and the stateful ones are reserved
+    assert!(register("answer_register", answer).is_err());
+    assert!(register(ECRECOVER, answer).is_err());
+    assert!(register(STAKING, answer).is_err());
+}
+
+#[test]
+fn test_precompile_check_cfg() {
+    check_cfg(&DEFAULT_PRECOMPILE_CFG).unwrap();
+
+    let mut cfg = PrecompileCfg::new();
+    cfg.insert(H160::from_low_u64_be(1), SHA256.to_owned());
+    check_cfg(&cfg).unwrap();
+
+    // one name at two addresses
+    cfg.insert(H160::from_low_u64_be(2), SHA256.to_owned());
+    assert!(check_cfg(&cfg).is_err());
+
+    // not registered
+    let mut cfg = PrecompileCfg::new();
+    cfg.insert(H160::from_low_u64_be(1), "answer_unknown".to_owned());
+    assert!(check_cfg(&cfg).is_err());
+}
+
+#[test]
+fn test_precompile_activation() {
+    let addr = H160::from_low_u64_be(0x2000);
+    register("answer_activation", answer).unwrap();
+
+    let mut cfg = DEFAULT_PRECOMPILE_CFG.clone();
+    cfg.insert(addr, "answer_activation".to_owned());
+    let schedule = ForkSchedule::new();
+    schedule
+        .set_precompiles(&BTreeMap::from([(10, cfg)]), None)
+        .unwrap();
+
+    // a plain account before the activation height
+    let before = OvrPrecompileSet::new(&schedule.precompiles_at(9), None, None, None);
+    assert!(!before.is_precompile(addr));
+    assert!(before.execute(addr, &[], None, &ctx(addr), false).is_none());
+
+    let after = OvrPrecompileSet::new(&schedule.precompiles_at(10), None, None, None);
+    assert!(after.is_precompile(addr));
+    let ret = after
+        .execute(addr, &[], None, &ctx(addr), false)
+        .unwrap()
+        .unwrap();
+    assert_eq!(vec![42], ret.output);
+    assert_eq!(15, ret.cost);
+
+    // the other ones are kept
+    assert!(after.is_precompile(H160::from_low_u64_be(1)));
+}
+
+#[test]
+fn test_params_without_admin() {
+    let addr = H160::from_low_u64_be(0x1003);
+    let set = OvrPrecompileSet::new(&DEFAULT_PRECOMPILE_CFG, None, None, None);
+    let mut input = Keccak256::digest(b"setAdmin(address)")[..4].to_vec();
+    input.extend([0u8; 32]);
+
+    // the zero address, the default caller of `eth_call`, is not an admin
+    match set.execute(addr, &input, None, &ctx(addr), false) {
+        Some(Err(PrecompileFailure::Revert { output, .. })) => {
+            assert!(output.windows(13).any(|w| w == b"admin not set"));
+        }
+        _ => panic!("not reverted"),
+    }
+}
@@ -0,0 +1,432 @@
+#![allow(warnings)]
+
+use ethereum::{
+    LegacyTransaction, LegacyTransactionMessage, TransactionAction, TransactionAny,
+    TransactionSignature,
+};
+use evm::backend::{Apply, ApplyBackend, Backend, Basic};
+use libsecp256k1::{Message, PublicKey, SecretKey};
+use ovr::{
+    ethvm::OvrAccount,
+    ledger::{ConsensusInfo, Ledger, MAIN_BRANCH_NAME},
+    tx::Tx,
+    EvmTx,
+};
+use primitive_types::{H160, H256, U256};
+use sha3::{Digest, Keccak256};
+
+const CHAIN_ID: u64 = 1234;
+const GAS_PRICE: u64 = 10000000000;
+
+fn staking() -> H160 {
+    H160::from_low_u64_be(0x1001)
+}
+
+// Runs the steps of the test.
+fn driver() -> H160 {
+    H160::from_low_u64_be(0xa0)
+}
+
+// The delegator, calls the precompile with its calldata after the first byte,
+// then reverts if that byte is zero, or returns the output.
+fn proxy() -> H160 {
+    H160::from_low_u64_be(0xa1)
+}
+
+fn validator() -> H160 {
+    H160::from_low_u64_be(0xb0)
+}
+
+fn key() -> SecretKey {
+    SecretKey::parse(&[1; 32]).unwrap()
+}
+
+fn sender() -> H160 {
+    let pk = PublicKey::from_secret_key(&key()).serialize();
+    H160::from_slice(&Keccak256::digest(&pk[1..])[12..])
+}
+
+fn selector(signature: &str) -> Vec<u8> {
+    Keccak256::digest(signature.as_bytes())[..4].to_vec()
+}
+
+fn word(v: U256) -> Vec<u8> {
+    let mut buf = [0u8; 32];
+    v.to_big_endian(&mut buf);
+    buf.to_vec()
+}
+
+fn address(a: H160) -> Vec<u8> {
+    word(U256::from_big_endian(a.as_bytes()))
+}
+
+fn delegate() -> Vec<u8> {
+    delegate_to(validator())
+}
+
+fn delegate_to(validator: H160) -> Vec<u8> {
+    [selector("delegate(address)"), address(validator)].concat()
+}
+
+fn undelegate(amount: u64) -> Vec<u8> {
+    [
+        selector("undelegate(address,uint256)"),
+        address(validator()),
+        word(amount.into()),
+    ]
+    .concat()
+}
+
+fn claim() -> Vec<u8> {
+    selector("claimRewards()")
+}
+
+fn fund() -> Vec<u8> {
+    [selector("fundRewards(address)"), address(proxy())].concat()
+}
+
+fn delegation_of() -> Vec<u8> {
+    [
+        selector("delegationOf(address,address)"),
+        address(proxy()),
+        address(validator()),
+    ]
+    .concat()
+}
+
+fn validator_power() -> Vec<u8> {
+    [selector("validatorPower(address)"), address(validator())].concat()
+}
+
+fn rewards_of() -> Vec<u8> {
+    [selector("rewardsOf(address)"), address(proxy())].concat()
+}
+
+// Through the proxy, `commit` or revert the frame after the call.
+fn by_proxy(commit: bool, input: Vec<u8>) -> Vec<u8> {
+    [vec![commit as u8], input].concat()
+}
+
+// The success flag of step `i` is stored at slot `2 * i`,
+// the first word of its output at slot `2 * i + 1`.
+fn driver_code(steps: &[(H160, u64, Vec<u8>)]) -> Vec<u8> {
+    let mut code = vec![];
+    for (i, (to, value, input)) in steps.iter().enumerate() {
+        for (j, chunk) in input.chunks(32).enumerate() {
+            let mut chunk = chunk.to_vec();
+            chunk.resize(32, 0);
+            code.push(0x7f); // PUSH32
+            code.extend(chunk);
+            code.extend([0x61, 0, (j * 32) as u8, 0x52]); // PUSH2 offset, MSTORE
+        }
+        code.extend([0x60, 0x20, 0x60, 0x00]); // retLength, retOffset
+        code.extend([0x61, 0, input.len() as u8, 0x60, 0x00]); // argsLength, argsOffset
+        code.push(0x7f); // PUSH32 value
+        code.extend(word((*value).into()));
+        code.push(0x73); // PUSH20 to
+        code.extend(to.as_bytes());
+        code.extend([0x5a, 0xf1]); // GAS, CALL
+        code.extend([0x60, (2 * i) as u8, 0x55]); // SSTORE success
+        code.extend([0x60, 0x00, 0x51, 0x60, (2 * i + 1) as u8, 0x55]); // SSTORE output
+    }
+    code.push(0x00);
+    code
+}
+
+fn proxy_code() -> Vec<u8> {
+    let mut code = vec![
+        0x60, 0x01, 0x36, 0x03, // len = CALLDATASIZE - 1
+        0x80, 0x60, 0x01, 0x60, 0x00, 0x37, // CALLDATACOPY(0, 1, len)
+        0x60, 0x20, 0x60, 0x00, 0x82, 0x60, 0x00, 0x34, // ..., CALLVALUE
+        0x73, // PUSH20 staking
+    ];
+    code.extend(staking().as_bytes());
+    code.extend([0x5a, 0xf1, 0x50, 0x50]); // GAS, CALL, POP, POP
+    code.extend([0x60, 0x00, 0x35, 0x60, 0xf8, 0x1c]); // the first byte
+    let label = code.len() as u8 + 8;
+    code.extend([0x60, label, 0x57]); // JUMPI
+    code.extend([0x60, 0x00, 0x60, 0x00, 0xfd]); // REVERT
+    code.extend([0x5b, 0x60, 0x20, 0x60, 0x00, 0xf3]); // JUMPDEST, RETURN
+    code
+}
+
+fn sign(nonce: u64) -> Tx {
+    let msg = LegacyTransactionMessage {
+        nonce: nonce.into(),
+        gas_price: GAS_PRICE.into(),
+        gas_limit: 1000000.into(),
+        action: TransactionAction::Call(driver()),
+        value: U256::zero(),
+        input: vec![],
+        chain_id: Some(CHAIN_ID),
+    };
+    let (sig, rec) =
+        libsecp256k1::sign(&Message::parse(msg.hash().as_fixed_bytes()), &key());
+    let sig = sig.serialize();
+
+    let tx = LegacyTransaction {
+        nonce: msg.nonce,
+        gas_price: msg.gas_price,
+        gas_limit: msg.gas_limit,
+        action: msg.action,
+        value: msg.value,
+        input: msg.input,
+        signature: TransactionSignature::new(
+            CHAIN_ID * 2 + 35 + rec.serialize() as u64,
+            H256::from_slice(&sig[..32]),
+            H256::from_slice(&sig[32..]),
+        )
+        .unwrap(),
+    };
+    Tx::Evm(EvmTx {
+        tx: TransactionAny::Legacy(tx),
+    })
+}
+
+fn new_ledger() -> Ledger {
+    let ledger = Ledger::new(
+        CHAIN_ID,
+        String::from("TEST"),
+        String::from("1"),
+        Some(GAS_PRICE as u128),
+        Some(3000000),
+        Some(1),
+    )
+    .unwrap();
+
+    let account = OvrAccount {
+        nonce: U256::zero(),
+        balance: U256::from(1000000000000000000000000u128),
+        code_hash: H256::zero(),
+    };
+    ledger
+        .state
+        .evm
+        .OFUEL
+        .accounts
+        .insert_by_branch(sender(), account, MAIN_BRANCH_NAME)
+        .unwrap();
+    ledger
+        .state
+        .staking
+        .register_validator(validator(), MAIN_BRANCH_NAME)
+        .unwrap();
+
+    ledger
+}
+
+// Run the steps by one transaction, return the success flags and the outputs.
+fn run(ledger: &Ledger, steps: Vec<(H160, u64, Vec<u8>)>) -> Vec<(bool, U256)> {
+    let code = |address, balance: u64, code| Apply::Modify {
+        address,
+        basic: Basic {
+            balance: balance.into(),
+            nonce: U256::one(),
+        },
+        code: Some(code),
+        storage: Vec::<(H256, H256)>::new(),
+        reset_storage: true,
+    };
+    let mut backend = ledger.state.evm.get_backend_hdr(MAIN_BRANCH_NAME);
+    backend.apply(
+        vec![
+            code(driver(), 1000, driver_code(&steps)),
+            code(proxy(), 0, proxy_code()),
+        ],
+        vec![],
+        false,
+    );
+
+    ledger.consensus_refresh(ConsensusInfo::default()).unwrap();
+    let nonce = ledger
+        .state
+        .evm
+        .OFUEL
+        .accounts
+        .get_by_branch(&sender(), MAIN_BRANCH_NAME)
+        .unwrap()
+        .nonce;
+    let receipt = ledger
+        .deliver_tx
+        .write()
+        .apply_tx_with_receipt(sign(nonce.as_u64()))
+        .unwrap()
+        .unwrap();
+    assert!(receipt.status_code);
+    ledger.commit().unwrap();
+
+    let backend = ledger.state.evm.get_backend_hdr(MAIN_BRANCH_NAME);
+    let slot = |i: usize| backend.storage(driver(), H256::from_low_u64_be(i as u64));
+    (0..steps.len())
+        .map(|i| {
+            (
+                !slot(2 * i).is_zero(),
+                U256::from_big_endian(slot(2 * i + 1).as_bytes()),
+            )
+        })
+        .collect()
+}
+
+fn balance(ledger: &Ledger, address: H160) -> U256 {
+    ledger
+        .state
+        .evm
+        .get_backend_hdr(MAIN_BRANCH_NAME)
+        .basic(address)
+        .balance
+}
+
+#[test]
+fn test_staking_queries_in_the_same_tx() {
+    let ledger = new_ledger();
+
+    let ret = run(
+        &ledger,
+        vec![
+            (proxy(), 100, by_proxy(true, delegate())),
+            (staking(), 0, delegation_of()),
+            (staking(), 0, validator_power()),
+            (proxy(), 0, by_proxy(true, undelegate(40))),
+            (staking(), 0, delegation_of()),
+            (staking(), 0, validator_power()),
+        ],
+    );
+    assert!(ret.iter().all(|(ok, _)| *ok));
+    assert_eq!(U256::from(100), ret[1].1);
+    assert_eq!(U256::from(100), ret[2].1);
+    assert_eq!(U256::from(60), ret[4].1);
+    assert_eq!(U256::from(60), ret[5].1);
+
+    let s = &ledger.state.staking;
+    let b = MAIN_BRANCH_NAME;
+    assert_eq!(U256::from(60), s.delegation_of(proxy(), validator(), b));
+    assert_eq!(U256::from(60), s.validator_power_of(validator(), b));
+    assert_eq!(U256::from(40), balance(&ledger, proxy()));
+    assert_eq!(U256::from(60), balance(&ledger, staking()));
+}
+
+#[test]
+fn test_staking_unknown_validator() {
+    let ledger = new_ledger();
+    let unknown = H160::from_low_u64_be(0xb1);
+
+    let ret = run(
+        &ledger,
+        vec![
+            (staking(), 100, delegate_to(unknown)),
+            (staking(), 100, delegate()),
+        ],
+    );
+    assert!(!ret[0].0);
+    assert!(ret[1].0);
+
+    let s = &ledger.state.staking;
+    let b = MAIN_BRANCH_NAME;
+    assert_eq!(U256::zero(), s.validator_power_of(unknown, b));
+    assert_eq!(U256::from(100), s.validator_power_of(validator(), b));
+    assert_eq!(U256::from(900), balance(&ledger, driver()));
+    assert_eq!(U256::from(100), balance(&ledger, staking()));
+}
+
+#[test]
+fn test_staking_reverted_frames() {
+    let ledger = new_ledger();
+
+    let ret = run(
+        &ledger,
+        vec![
+            (proxy(), 100, by_proxy(true, delegate())),
+            (proxy(), 0, by_proxy(false, undelegate(100))),
+            (staking(), 0, delegation_of()),
+            (proxy(), 0, by_proxy(true, undelegate(100))),
+            (staking(), 0, delegation_of()),
+        ],
+    );
+    assert!(ret[0].0);
+    assert!(!ret[1].0);
+    assert_eq!(U256::from(100), ret[2].1);
+    // the debit of the reverted frame is not counted
+    assert!(ret[3].0);
+    assert_eq!(U256::zero(), ret[4].1);
+
+    let s = &ledger.state.staking;
+    let b = MAIN_BRANCH_NAME;
+    assert_eq!(U256::zero(), s.delegation_of(proxy(), validator(), b));
+    assert_eq!(U256::from(100), balance(&ledger, proxy()));
+    assert_eq!(U256::zero(), balance(&ledger, staking()));
+}
+
+#[test]
+fn test_staking_funded_rewards() {
+    let ledger = new_ledger();
+
+    let ret = run(
+        &ledger,
+        vec![
+            (staking(), 50, fund()),
+            (staking(), 0, rewards_of()),
+            (proxy(), 0, by_proxy(false, claim())),
+            (staking(), 0, rewards_of()),
+            (proxy(), 0, by_proxy(true, claim())),
+            (staking(), 0, rewards_of()),
+        ],
+    );
+    assert!(ret[0].0);
+    assert_eq!(U256::from(50), ret[1].1);
+    // the claim of the reverted frame is not counted
+    assert!(!ret[2].0);
+    assert_eq!(U256::from(50), ret[3].1);
+    assert!(ret[4].0);
+    assert_eq!(U256::from(50), ret[4].1);
+    assert_eq!(U256::zero(), ret[5].1);
+
+    // paid from the funds, nothing is minted
+    assert_eq!(U256::from(50), balance(&ledger, proxy()));
+    assert_eq!(U256::zero(), balance(&ledger, staking()));
+    assert_eq!(U256::from(950), balance(&ledger, driver()));
+    let s = &ledger.state.staking;
+    assert_eq!(U256::zero(), s.rewards_of(proxy(), MAIN_BRANCH_NAME));
+}
+
+#[test]
+fn test_staking_unfunded_rewards() {
+    let ledger = new_ledger();
+
+    // not backed by the staking pool
+    ledger
+        .state
+        .staking
+        .rewards
+        .insert_by_branch(proxy(), U256::from(50), MAIN_BRANCH_NAME)
+        .unwrap();
+
+    let code = |address, code| Apply::Modify {
+        address,
+        basic: Basic {
+            balance: U256::zero(),
+            nonce: U256::one(),
+        },
+        code: Some(code),
+        storage: Vec::<(H256, H256)>::new(),
+        reset_storage: true,
+    };
+    let steps = vec![(proxy(), 0, by_proxy(true, claim()))];
+    ledger.state.evm.get_backend_hdr(MAIN_BRANCH_NAME).apply(
+        vec![
+            code(driver(), driver_code(&steps)),
+            code(proxy(), proxy_code()),
+        ],
+        vec![],
+        false,
+    );
+    ledger.consensus_refresh(ConsensusInfo::default()).unwrap();
+
+    // the payout is reverted with its frame, and the tx fails before it is applied
+    let ret = ledger.deliver_tx.write().apply_tx_with_receipt(sign(0));
+    assert!(ret.is_err());
+    ledger.commit().unwrap();
+
+    assert_eq!(U256::zero(), balance(&ledger, proxy()));
+    let s = &ledger.state.staking;
+    assert_eq!(U256::from(50), s.rewards_of(proxy(), MAIN_BRANCH_NAME));
+}
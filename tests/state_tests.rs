@@ -0,0 +1,539 @@
+#![allow(warnings)]
+
+//!
+//! Runner of the Ethereum `GeneralStateTests`,
+//! the filled fixtures of [ethereum/tests](https://github.com/ethereum/tests)
+//! are loaded from `$ETH_STATE_TESTS`, or from `tests/GeneralStateTests/`.
+//!
+//! Run by `make statetest`, which enables the `jsontests` feature.
+//!
+//! Ported from the `jsontests` of [evm](https://github.com/rust-blockchain/evm).
+//!
+
+use evm::{
+    backend::{Apply, ApplyBackend, Backend, Basic},
+    executor::stack::{StackExecutor, StackSubstateMetadata},
+};
+use ovr::ethvm::{
+    fork::EvmFork,
+    impls::stack::OvrStackState,
+    precompile::{
+        OvrPrecompileSet, PrecompileCfg, BLAKE2F, BN128_ADD, BN128_MUL, BN128_PAIRING,
+        ECRECOVER, IDENTITY, MODEXP, RIPEMD160, SHA256,
+    },
+    OvrVicinity, State,
+};
+use primitive_types::{H160, H256, U256};
+use ruc::*;
+use serde::Deserialize;
+use sha3::{Digest, Keccak256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::{Path, PathBuf},
+};
+use vsdb::{BranchName, VersionName, VsMgmt};
+
+const BRANCH: BranchName = BranchName(b"Main");
+
+const FORKS: [(&str, EvmFork); 3] = [
+    ("Istanbul", EvmFork::Istanbul),
+    ("Berlin", EvmFork::Berlin),
+    ("London", EvmFork::London),
+];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Env {
+    current_coinbase: String,
+    current_difficulty: String,
+    current_gas_limit: String,
+    current_number: String,
+    current_timestamp: String,
+    #[serde(default)]
+    current_base_fee: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    balance: String,
+    code: String,
+    nonce: String,
+    storage: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessListItem {
+    address: String,
+    storage_keys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Transaction {
+    data: Vec<String>,
+    gas_limit: Vec<String>,
+    #[serde(default)]
+    gas_price: Option<String>,
+    #[serde(default)]
+    max_fee_per_gas: Option<String>,
+    #[serde(default)]
+    max_priority_fee_per_gas: Option<String>,
+    nonce: String,
+    secret_key: String,
+    #[serde(default)]
+    sender: Option<String>,
+    to: String,
+    value: Vec<String>,
+    #[serde(default)]
+    access_lists: Vec<Option<Vec<AccessListItem>>>,
+}
+
+#[derive(Deserialize)]
+struct Indexes {
+    data: usize,
+    gas: usize,
+    value: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PostState {
+    hash: H256,
+    logs: H256,
+    indexes: Indexes,
+    #[serde(default)]
+    expect_exception: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StateTest {
+    env: Env,
+    pre: BTreeMap<String, Account>,
+    transaction: Transaction,
+    post: BTreeMap<String, Vec<PostState>>,
+}
+
+#[test]
+fn general_state_tests() {
+    let dir = env::var("ETH_STATE_TESTS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/GeneralStateTests")
+        });
+
+    assert!(dir.exists(), "fixtures not found: {}", dir.display());
+
+    let mut files = vec![];
+    collect_json(&dir, &mut files);
+
+    let mut cnt = 0;
+    let mut failures = vec![];
+    for f in files.iter() {
+        let tests: BTreeMap<String, StateTest> =
+            match serde_json::from_slice(&fs::read(f).unwrap()) {
+                Ok(t) => t,
+                Err(e) => {
+                    failures.push(format!("{}: invalid fixture: {}", f.display(), e));
+                    continue;
+                }
+            };
+        for (name, test) in tests.iter() {
+            for (fork_name, fork) in FORKS.iter() {
+                for (i, post) in
+                    test.post.get(*fork_name).into_iter().flatten().enumerate()
+                {
+                    cnt += 1;
+                    if let Err(e) = run(test, *fork, post) {
+                        failures.push(format!("[{}] {}#{}: {}", fork_name, name, i, e));
+                    }
+                }
+            }
+        }
+    }
+
+    assert!(0 < cnt, "no cases found in {}", dir.display());
+    println!("{} cases, {} failures", cnt, failures.len());
+    failures.iter().for_each(|f| println!("{}", f));
+    assert!(
+        failures.is_empty(),
+        "{} of {} cases failed",
+        failures.len(),
+        cnt
+    );
+}
+
+fn collect_json(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_json(&path, files);
+        } else if path.extension().map(|e| e == "json").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+}
+
+fn run(test: &StateTest, fork: EvmFork, post: &PostState) -> Result<(), String> {
+    let state = State::default();
+    state.branch_create(BRANCH).unwrap();
+    state.branch_set_default(BRANCH).unwrap();
+    state.version_create(VersionName(b"pre")).unwrap();
+
+    // the pre-state is written through the backend,
+    // so the code is stored the same way as a deployed one
+    let mut storage_keys = BTreeSet::new();
+    let pre = test
+        .pre
+        .iter()
+        .map(|(addr, acc)| {
+            let address = h160(addr);
+            let storage = acc
+                .storage
+                .iter()
+                .map(|(k, v)| (h256(k), h256(v)))
+                .collect::<Vec<_>>();
+            storage.iter().for_each(|(k, _)| {
+                storage_keys.insert((address, *k));
+            });
+            Apply::Modify {
+                address,
+                basic: Basic {
+                    balance: u256(&acc.balance),
+                    nonce: u256(&acc.nonce),
+                },
+                code: Some(bytes(&acc.code)),
+                storage,
+                reset_storage: false,
+            }
+        })
+        .collect::<Vec<_>>();
+    state.get_backend_hdr(BRANCH).apply(pre, vec![], false);
+
+    let mut addresses = test.pre.keys().map(|a| h160(a)).collect::<BTreeSet<_>>();
+
+    let env = &test.env;
+    let tx = &test.transaction;
+    let base_fee = env
+        .current_base_fee
+        .as_deref()
+        .map(u256)
+        .unwrap_or_default();
+    let gas_price = if let Some(max_fee) = tx.max_fee_per_gas.as_deref().map(u256) {
+        let priority = tx
+            .max_priority_fee_per_gas
+            .as_deref()
+            .map(u256)
+            .unwrap_or_default();
+        max_fee.min(base_fee.saturating_add(priority))
+    } else {
+        tx.gas_price.as_deref().map(u256).unwrap_or_default()
+    };
+
+    let vicinity = OvrVicinity {
+        gas_price,
+        origin: sender(tx),
+        chain_id: U256::one(),
+        block_number: u256(&env.current_number),
+        block_coinbase: h160(&env.current_coinbase),
+        block_timestamp: u256(&env.current_timestamp),
+        block_difficulty: u256(&env.current_difficulty),
+        block_gas_limit: u256(&env.current_gas_limit),
+        block_base_fee_per_gas: base_fee,
+    };
+    let caller = vicinity.origin;
+    let coinbase = vicinity.block_coinbase;
+    let mut state = state;
+    state.vicinity = vicinity;
+
+    let data = bytes(&tx.data[post.indexes.data]);
+    let gas_limit = u256(&tx.gas_limit[post.indexes.gas]);
+    let value = u256(&tx.value[post.indexes.value]);
+    let access_list = tx
+        .access_lists
+        .get(post.indexes.data)
+        .and_then(|a| a.as_ref())
+        .into_iter()
+        .flatten()
+        .map(|i| {
+            (
+                h160(&i.address),
+                i.storage_keys.iter().map(|k| h256(k)).collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let cfg = fork.evm_cfg();
+    let mut backend = state.get_backend_hdr(BRANCH);
+
+    let pre_check = validate(
+        &backend,
+        fork,
+        tx,
+        caller,
+        &data,
+        gas_limit,
+        value,
+        &access_list,
+    );
+    if let Some(e) = post.expect_exception.as_ref() {
+        if pre_check.is_ok() {
+            return Err(format!("expected exception '{}' is not raised", e));
+        }
+    } else if let Err(e) = pre_check {
+        return Err(e);
+    } else {
+        let gas_limit = gas_limit.as_u64();
+        let metadata = StackSubstateMetadata::new(gas_limit, &cfg);
+        let stack_state = OvrStackState::new(metadata, &backend);
+        let precompiles =
+            OvrPrecompileSet::new(&standard_precompiles(), None, None, None);
+        let mut executor =
+            StackExecutor::new_with_precompiles(stack_state, &cfg, &precompiles);
+
+        let total_fee = gas_price * U256::from(gas_limit);
+        executor.state_mut().withdraw(caller, total_fee).unwrap();
+
+        let to = tx.to.trim_start_matches("0x");
+        if to.is_empty() {
+            executor.transact_create(caller, value, data, gas_limit, access_list);
+        } else {
+            executor.transact_call(
+                caller,
+                h160(to),
+                value,
+                data,
+                gas_limit,
+                access_list,
+            );
+        }
+
+        let used_gas = U256::from(executor.used_gas());
+        let actual_fee = used_gas * gas_price;
+        let miner_fee = if EvmFork::London <= fork {
+            used_gas * (gas_price - base_fee)
+        } else {
+            actual_fee
+        };
+        executor.state_mut().deposit(coinbase, miner_fee);
+        executor.state_mut().deposit(caller, total_fee - actual_fee);
+
+        let (values, logs) = executor.into_state().deconstruct();
+        let logs = logs.into_iter().collect::<Vec<_>>();
+
+        for v in values.iter() {
+            match v {
+                Apply::Modify {
+                    address, storage, ..
+                } => {
+                    addresses.insert(*address);
+                    storage.keys().for_each(|k| {
+                        storage_keys.insert((*address, *k));
+                    });
+                }
+                Apply::Delete { address } => {
+                    addresses.insert(*address);
+                }
+            }
+        }
+
+        let logs_hash = {
+            let logs = logs
+                .iter()
+                .map(|l| ethereum::Log {
+                    address: l.address,
+                    topics: l.topics.clone(),
+                    data: l.data.clone(),
+                })
+                .collect::<Vec<_>>();
+            H256::from_slice(&Keccak256::digest(&rlp::encode_list::<ethereum::Log, _>(
+                &logs,
+            )))
+        };
+        if logs_hash != post.logs {
+            return Err(format!(
+                "logs hash mismatch, expected {:?}, got {:?}",
+                post.logs, logs_hash
+            ));
+        }
+
+        // EIP-161 is active in all the supported forks
+        backend.apply(values, logs, true);
+    }
+
+    let root = state_root(&backend, &addresses, &storage_keys);
+    if root != post.hash {
+        return Err(format!(
+            "state root mismatch, expected {:?}, got {:?}",
+            post.hash, root
+        ));
+    }
+
+    Ok(())
+}
+
+// Checks of the transaction before the execution,
+// failures here are the `expectException` of the fixtures.
+#[allow(clippy::too_many_arguments)]
+fn validate<B: Backend>(
+    backend: &B,
+    fork: EvmFork,
+    tx: &Transaction,
+    caller: H160,
+    data: &[u8],
+    gas_limit: U256,
+    value: U256,
+    access_list: &[(H160, Vec<H256>)],
+) -> Result<(), String> {
+    let account = backend.basic(caller);
+    if account.nonce != u256(&tx.nonce) {
+        return Err("invalid nonce".to_owned());
+    }
+    if gas_limit > backend.block_gas_limit() {
+        return Err("gas limit exceeds the block gas limit".to_owned());
+    }
+
+    let base_fee = backend.block_base_fee_per_gas();
+    let max_price = if let Some(max_fee) = tx.max_fee_per_gas.as_deref().map(u256) {
+        if EvmFork::London > fork {
+            return Err("eip-1559 transaction before london".to_owned());
+        }
+        let priority = tx
+            .max_priority_fee_per_gas
+            .as_deref()
+            .map(u256)
+            .unwrap_or_default();
+        if priority > max_fee {
+            return Err("priority fee is greater than max fee".to_owned());
+        }
+        max_fee
+    } else {
+        tx.gas_price.as_deref().map(u256).unwrap_or_default()
+    };
+    if EvmFork::London <= fork && max_price < base_fee {
+        return Err("max fee is less than the base fee".to_owned());
+    }
+    if !access_list.is_empty() && EvmFork::Berlin > fork {
+        return Err("access list before berlin".to_owned());
+    }
+
+    let max_cost = gas_limit
+        .checked_mul(max_price)
+        .and_then(|c| c.checked_add(value))
+        .ok_or("cost overflow")?;
+    if account.balance < max_cost {
+        return Err("insufficient balance".to_owned());
+    }
+
+    let is_create = tx.to.trim_start_matches("0x").is_empty();
+    let intrinsic = intrinsic_gas(is_create, data, access_list);
+    if gas_limit < U256::from(intrinsic) {
+        return Err("intrinsic gas too low".to_owned());
+    }
+
+    Ok(())
+}
+
+fn intrinsic_gas(
+    is_create: bool,
+    data: &[u8],
+    access_list: &[(H160, Vec<H256>)],
+) -> u64 {
+    let zeros = data.iter().filter(|b| 0 == **b).count() as u64;
+    let non_zeros = data.len() as u64 - zeros;
+    let keys = access_list.iter().map(|(_, k)| k.len() as u64).sum::<u64>();
+
+    alt!(is_create, 53000, 21000)
+        + zeros * 4
+        + non_zeros * 16
+        + access_list.len() as u64 * 2400
+        + keys * 1900
+}
+
+// The standard precompiles of ethereum.
+fn standard_precompiles() -> PrecompileCfg {
+    [
+        ECRECOVER,
+        SHA256,
+        RIPEMD160,
+        IDENTITY,
+        MODEXP,
+        BN128_ADD,
+        BN128_MUL,
+        BN128_PAIRING,
+        BLAKE2F,
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, name)| (H160::from_low_u64_be(1 + i as u64), name.to_string()))
+    .collect()
+}
+
+fn state_root<B: Backend>(
+    backend: &B,
+    addresses: &BTreeSet<H160>,
+    storage_keys: &BTreeSet<(H160, H256)>,
+) -> H256 {
+    let accounts = addresses
+        .iter()
+        .filter(|addr| backend.exists(**addr))
+        .map(|addr| {
+            let storage = storage_keys
+                .iter()
+                .filter(|(a, _)| a == addr)
+                .map(|(_, k)| (*k, backend.storage(*addr, *k)))
+                .filter(|(_, v)| *v != H256::zero())
+                .map(|(k, v)| (k, rlp::encode(&U256::from_big_endian(v.as_bytes()))))
+                .collect::<Vec<_>>();
+            let basic = backend.basic(*addr);
+            let account = ethereum::Account {
+                nonce: basic.nonce,
+                balance: basic.balance,
+                storage_root: ethereum::util::sec_trie_root(storage),
+                code_hash: H256::from_slice(&Keccak256::digest(&backend.code(*addr))),
+            };
+            (*addr, rlp::encode(&account))
+        })
+        .collect::<Vec<_>>();
+
+    ethereum::util::sec_trie_root(accounts)
+}
+
+fn sender(tx: &Transaction) -> H160 {
+    if let Some(s) = tx.sender.as_deref() {
+        return h160(s);
+    }
+    let mut secret = [0u8; 32];
+    secret.copy_from_slice(&bytes(&tx.secret_key));
+    let sk = libsecp256k1::SecretKey::parse(&secret).unwrap();
+    let pk = libsecp256k1::PublicKey::from_secret_key(&sk).serialize();
+    H160::from_slice(&Keccak256::digest(&pk[1..])[12..])
+}
+
+fn bytes(s: &str) -> Vec<u8> {
+    let s = s.trim_start_matches("0x");
+    hex::decode(s).unwrap_or_else(|e| panic!("invalid hex '{}': {}", s, e))
+}
+
+fn u256(s: &str) -> U256 {
+    let s = s.trim_start_matches("0x");
+    alt!(
+        s.is_empty(),
+        U256::zero(),
+        U256::from_str_radix(s, 16).unwrap()
+    )
+}
+
+fn h256(s: &str) -> H256 {
+    let mut buf = [0u8; 32];
+    u256(s).to_big_endian(&mut buf);
+    H256(buf)
+}
+
+fn h160(s: &str) -> H160 {
+    let mut buf = [0u8; 20];
+    let b = bytes(s);
+    buf[20 - b.len()..].copy_from_slice(&b);
+    H160(buf)
+}